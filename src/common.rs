@@ -2,8 +2,14 @@ pub mod cache;
 pub mod client;
 pub mod config;
 pub mod constant;
+pub mod dpop;
 pub mod errors;
 pub mod jwt;
+pub mod middleware;
 pub mod nacos;
+pub mod proxy;
 pub mod srp;
+pub mod tls;
+pub mod totp;
 pub mod utils;
+pub mod webauthn;
@@ -52,6 +52,11 @@ pub async fn get_client_config(client_id: &str) -> Result<Option<ClientConfig>>
     })
 }
 
+// secret 轮换后立即失效，避免继续使用缓存中的旧 secret
+pub async fn invalidate_client_config(client_id: &str) {
+    CLIENT_CONFIG_CACHE.invalidate(client_id).await;
+}
+
 pub async fn get_idp_config(client_id: &str) -> Result<Option<ClientIdpConfigs>> {
     Ok(match IDP_CONFIG_CACHE.get(client_id).await {
         Some(client) => Some(client),
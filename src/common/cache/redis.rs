@@ -5,6 +5,7 @@ use redis::{aio::Connection, Client};
 use crate::common::{
     config::env_var,
     errors::{ConfigError, Result},
+    utils::gen_id,
 };
 
 lazy_static::lazy_static! {
@@ -74,3 +75,156 @@ where
         .context(format!("redis SETEX failed {}", key))?;
     Ok(())
 }
+
+pub async fn redis_del(key: &str) -> Result<()> {
+    let mut conn = borrow().await?;
+    redis::cmd("DEL")
+        .arg(key)
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis DEL failed {}", key))?;
+    Ok(())
+}
+
+pub async fn redis_incr(key: &str, expires_in: Duration) -> Result<i64> {
+    let mut conn = borrow().await?;
+    let (count,): (i64,) = redis::pipe()
+        .atomic()
+        .cmd("INCR")
+        .arg(key)
+        .cmd("EXPIRE")
+        .arg(key)
+        .arg(expires_in.num_seconds())
+        .arg("NX")
+        .ignore()
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis INCR failed {}", key))?;
+    Ok(count)
+}
+
+pub async fn redis_getdel<T>(key: &str) -> Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut conn = borrow().await?;
+    let value: Option<String> = redis::cmd("GETDEL")
+        .arg(key)
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis execute GETDEL failed: {}", key))?;
+    match value {
+        Some(v) => Ok(Some(
+            serde_json::from_str::<T>(&v)
+                .context(format!("redis GETDEL deserialize failed: {}", key))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// SET NX EX，键已存在时返回 false
+pub async fn redis_setnx<T>(key: &str, value: T, expires_in: Duration) -> Result<bool>
+where
+    T: serde::Serialize,
+{
+    let mut conn = borrow().await?;
+    let result: Option<String> = redis::cmd("SET")
+        .arg(key)
+        .arg(
+            serde_json::to_string(&value)
+                .context(format!("redis SET NX serialize failed {}", key))?,
+        )
+        .arg("NX")
+        .arg("EX")
+        .arg(expires_in.num_seconds())
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis SET NX failed {}", key))?;
+    Ok(result.is_some())
+}
+
+// 滑动窗口计数：清理窗口外的记录后写入本次请求，返回窗口内的次数和最早一次的时间戳（毫秒）
+pub async fn redis_slide(key: &str, now: i64, window: Duration) -> Result<(i64, i64)> {
+    let mut conn = borrow().await?;
+    let (count, oldest): (i64, Vec<(String, i64)>) = redis::pipe()
+        .atomic()
+        .cmd("ZREMRANGEBYSCORE")
+        .arg(key)
+        .arg("-inf")
+        .arg(now - window.num_milliseconds())
+        .ignore()
+        .cmd("ZADD")
+        .arg(key)
+        .arg(now)
+        .arg(format!("{now}:{}", gen_id(8)))
+        .ignore()
+        .cmd("ZCARD")
+        .arg(key)
+        .cmd("ZRANGE")
+        .arg(key)
+        .arg(0)
+        .arg(0)
+        .arg("WITHSCORES")
+        .cmd("PEXPIRE")
+        .arg(key)
+        .arg(window.num_milliseconds())
+        .ignore()
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis sliding window failed {}", key))?;
+    Ok((
+        count,
+        oldest.first().map(|(_, score)| *score).unwrap_or(now),
+    ))
+}
+
+// 剩余过期秒数，键不存在时返回 None
+pub async fn redis_ttl(key: &str) -> Result<Option<i64>> {
+    let mut conn = borrow().await?;
+    let ttl: i64 = redis::cmd("TTL")
+        .arg(key)
+        .query_async(&mut conn)
+        .await
+        .context(format!("redis TTL failed {}", key))?;
+    Ok((ttl >= 0).then_some(ttl))
+}
+
+lazy_static::lazy_static! {
+  // 令牌桶：按流逝时间补充令牌后尝试取出一个，返回是否放行与剩余令牌数（千分之一个）
+  static ref TOKEN_BUCKET_SCRIPT: redis::Script = redis::Script::new(r"
+    local capacity = tonumber(ARGV[1])
+    local rate = tonumber(ARGV[2])
+    local now = tonumber(ARGV[3])
+    local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+    local tokens = tonumber(bucket[1]) or capacity
+    local updated_at = tonumber(bucket[2]) or now
+    tokens = math.min(capacity, tokens + math.max(0, now - updated_at) * rate)
+    local allowed = 0
+    if tokens >= 1 then
+      tokens = tokens - 1
+      allowed = 1
+    end
+    redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', now)
+    redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate))
+    return {allowed, math.floor(tokens * 1000)}
+  ");
+}
+
+// rate 为每毫秒补充的令牌数，now 为毫秒时间戳
+pub async fn redis_token_bucket(
+    key: &str,
+    capacity: u32,
+    rate: f64,
+    now: i64,
+) -> Result<(bool, f64)> {
+    let mut conn = borrow().await?;
+    let (allowed, millitokens): (i64, i64) = TOKEN_BUCKET_SCRIPT
+        .key(key)
+        .arg(capacity)
+        .arg(rate)
+        .arg(now)
+        .invoke_async(&mut conn)
+        .await
+        .context(format!("redis token bucket failed {}", key))?;
+    Ok((allowed == 1, millitokens as f64 / 1000.0))
+}
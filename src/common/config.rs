@@ -25,3 +25,11 @@ where
         }
     }
 }
+
+// 对外暴露的授权服务地址，用于断言 aud 与 DPoP htu
+pub fn auth_server_endpoint() -> String {
+    env_var_default::<String>(
+        "AUTH_SERVER_ENDPOINT",
+        "https://auth.heliannuuthus.com".to_string(),
+    )
+}
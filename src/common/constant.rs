@@ -3,10 +3,29 @@ pub const FORUM_SERVER: &str = "forum-server";
 pub const FORUM_SERVER_CLUSTER: &str = "default";
 pub const OPENID_SCOPE: &str = "openid";
 pub const OFFLINE_ACCESS_SCOPE: &str = "offline_access";
+// https://datatracker.ietf.org/doc/html/rfc8693#section-3
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+pub const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+pub const DPOP_HEADER: &str = "DPoP";
+pub const DPOP_NONCE_HEADER: &str = "DPoP-Nonce";
 pub const TOKEN_ISSUER: &str = "https://auth.heliannuuthus.com/issuer/{}";
-pub const CONFLICT_RESPONSE_TYPE: &[&ResponseType] = &[&ResponseType::IdToken, &ResponseType::Code];
+pub const EMAIL_CODE_TEMPLATE: &str = "email_code";
+pub const EMAIL_LINK_TEMPLATE: &str = "email_link";
+pub const RECOVERY_USED_TEMPLATE: &str = "recovery_used";
+pub const RECOVERY_REGENERATED_TEMPLATE: &str = "recovery_regenerated";
+pub const PASSWORD_RESET_TEMPLATE: &str = "password_reset";
+pub const PASSWORD_CHANGED_TEMPLATE: &str = "password_changed";
+pub const MFA_REQUIRED_ROLES: &[&str] = &["admin", "moderator"];
+// https://datatracker.ietf.org/doc/html/rfc8176#section-2
+pub const AMR_PWD: &str = "pwd";
+pub const AMR_OTP: &str = "otp";
+pub const AMR_EMAIL: &str = "email";
+pub const AMR_HWK: &str = "hwk";
+pub const AMR_SWK: &str = "swk";
+pub const AMR_MFA: &str = "mfa";
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum ChallengeType {
     Link,
@@ -21,6 +40,45 @@ pub enum ResponseType {
     IdToken,
 }
 
+// https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#ResponseModes
+// https://openid.net/specs/oauth-v2-jarm.html#name-response-mode-jwt
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    Query,
+    Fragment,
+    FormPost,
+    Jwt,
+    #[serde(rename = "query.jwt")]
+    QueryJwt,
+    #[serde(rename = "fragment.jwt")]
+    FragmentJwt,
+    #[serde(rename = "form_post.jwt")]
+    FormPostJwt,
+}
+
+impl ResponseMode {
+    pub fn jarm(&self) -> bool {
+        matches!(
+            self,
+            ResponseMode::Jwt
+                | ResponseMode::QueryJwt
+                | ResponseMode::FragmentJwt
+                | ResponseMode::FormPostJwt
+        )
+    }
+
+    // JARM 最终经由哪种方式写回，jwt 需要先按 response_type 确定
+    pub fn base(&self) -> ResponseMode {
+        match self {
+            ResponseMode::QueryJwt => ResponseMode::Query,
+            ResponseMode::FragmentJwt | ResponseMode::Jwt => ResponseMode::Fragment,
+            ResponseMode::FormPostJwt => ResponseMode::FormPost,
+            mode => *mode,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum PromptType {
@@ -54,4 +112,6 @@ pub enum Gander {
 pub enum TokenType {
     Bearer,
     Basic,
+    // https://datatracker.ietf.org/doc/html/rfc9449#section-5
+    DPoP,
 }
@@ -0,0 +1,133 @@
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, Jwk},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{
+    errors::Result,
+    utils::{decode64url, encode64url, sha256},
+};
+use crate::dto::auth::{AuthError, AuthorizationError};
+
+pub const DPOP_JWT_TYPE: &str = "dpop+jwt";
+
+// https://datatracker.ietf.org/doc/html/rfc9449#section-4.2
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DpopClaims {
+    pub jti: String,
+    pub htm: String,
+    pub htu: String,
+    pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+// 校验通过的 proof，jkt 为证明密钥的 JWK thumbprint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpopProof {
+    pub jkt: String,
+    pub jti: String,
+    pub iat: i64,
+    pub nonce: Option<String>,
+}
+
+fn invalid_proof(description: impl Into<String>) -> AuthorizationError {
+    AuthorizationError::local(AuthError::InvalidDpopProof, description)
+}
+
+// 只保留 RFC 7638 要求的成员，按字典序序列化后取 SHA-256
+// https://datatracker.ietf.org/doc/html/rfc7638#section-3.2
+pub fn jwk_thumbprint(jwk: &Jwk) -> Result<String> {
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+    let canonical = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+            serde_json::to_string(&params.curve).map_err(anyhow::Error::from)?,
+            quote(&params.x),
+            quote(&params.y)
+        ),
+        AlgorithmParameters::RSA(params) => format!(
+            r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+            quote(&params.e),
+            quote(&params.n)
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":{}}}"#,
+            serde_json::to_string(&params.curve).map_err(anyhow::Error::from)?,
+            quote(&params.x)
+        ),
+        AlgorithmParameters::OctetKey(_) => {
+            return Err(invalid_proof("symmetric jwk is not allowed").into())
+        }
+    };
+    Ok(encode64url(&sha256(canonical.as_bytes())))
+}
+
+// htu 比较时忽略 query 与 fragment
+fn target_uri(uri: &str) -> Option<String> {
+    let mut uri = Url::parse(uri).ok()?;
+    uri.set_query(None);
+    uri.set_fragment(None);
+    Some(uri.to_string())
+}
+
+// 校验签名、typ、htm、htu 与 iat，jti 与 nonce 由调用方结合 Redis 校验
+// https://datatracker.ietf.org/doc/html/rfc9449#section-4.3
+pub fn verify_proof(proof: &str, htm: &str, htu: &str, now: i64, window: i64) -> Result<DpopProof> {
+    let header =
+        jsonwebtoken::decode_header(proof).map_err(|_| invalid_proof("malformed DPoP proof"))?;
+    if header.typ.as_deref() != Some(DPOP_JWT_TYPE) {
+        return Err(invalid_proof("DPoP proof typ must be dpop+jwt").into());
+    }
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(invalid_proof("DPoP proof must use an asymmetric algorithm").into());
+    }
+    let jwk = header
+        .jwk
+        .as_ref()
+        .ok_or_else(|| invalid_proof("DPoP proof jwk is lacked"))?;
+    // 反序列化 Jwk 会丢掉私钥成员，需要在原始 header 中检查
+    let raw = proof
+        .split('.')
+        .next()
+        .and_then(|header| decode64url(header).ok())
+        .and_then(|header| serde_json::from_slice::<serde_json::Value>(&header).ok())
+        .ok_or_else(|| invalid_proof("malformed DPoP proof"))?;
+    if ["d", "p", "q", "dp", "dq", "qi", "k"]
+        .iter()
+        .any(|member| raw["jwk"].get(member).is_some())
+    {
+        return Err(invalid_proof("DPoP proof jwk must not contain private key").into());
+    }
+    let jkt = jwk_thumbprint(jwk)?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|_| invalid_proof("DPoP proof jwk is invalid"))?;
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = false;
+    validation.set_required_spec_claims::<&str>(&[]);
+    let claims = jsonwebtoken::decode::<DpopClaims>(proof, &key, &validation)
+        .map_err(|e| invalid_proof(format!("invalid DPoP proof: {e}")))?
+        .claims;
+    if claims.htm != htm {
+        return Err(invalid_proof("DPoP proof htm mismatched").into());
+    }
+    if target_uri(&claims.htu).is_none() || target_uri(&claims.htu) != target_uri(htu) {
+        return Err(invalid_proof("DPoP proof htu mismatched").into());
+    }
+    if (claims.iat - now).abs() > window {
+        return Err(invalid_proof("DPoP proof is expired or issued in the future").into());
+    }
+    if claims.jti.is_empty() {
+        return Err(invalid_proof("DPoP proof jti is lacked").into());
+    }
+    Ok(DpopProof {
+        jkt,
+        jti: claims.jti,
+        iat: claims.iat,
+        nonce: claims.nonce,
+    })
+}
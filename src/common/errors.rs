@@ -1,6 +1,9 @@
 use actix_web::{
     error::ErrorBadRequest,
-    http::{header::ContentType, StatusCode},
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
     ResponseError,
 };
 use chrono::Local;
@@ -8,7 +11,8 @@ use redis::RedisError;
 use thiserror::Error;
 use validator::ValidationErrors;
 
-use super::srp::errors::SrpError;
+use super::{srp::errors::SrpError, webauthn::WebauthnError};
+use crate::dto::auth::AuthorizationError;
 
 pub type Result<T> = std::result::Result<T, ApiError>;
 
@@ -34,6 +38,18 @@ pub enum ApiError {
     Http(#[from] http::Error),
     #[error("srp error {0}")]
     SrpAuth(#[from] SrpError),
+    #[error("webauthn error {0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("too many requests, retry after {0}s")]
+    Throttled(u64),
+    #[error("{0}")]
+    Authorization(Box<AuthorizationError>),
+}
+
+impl From<AuthorizationError> for ApiError {
+    fn from(value: AuthorizationError) -> Self {
+        ApiError::Authorization(Box::new(value))
+    }
 }
 
 impl From<ValidationErrors> for ApiError {
@@ -49,6 +65,7 @@ impl From<ValidationErrors> for ApiError {
 impl ResponseError for ApiError {
     fn status_code(&self) -> http::StatusCode {
         match self {
+            ApiError::Response(e) => e.as_response_error().status_code(),
             ApiError::InternalConfig(e) => match e {
                 ConfigError::Reqwest(e) => e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
                 ConfigError::Redis(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -57,22 +74,35 @@ impl ResponseError for ApiError {
                 SrpError::ProgressError(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
                 _ => StatusCode::BAD_REQUEST,
             },
+            ApiError::Webauthn(e) => match e {
+                WebauthnError::Malformed(_) | WebauthnError::UnSupported(_) => {
+                    StatusCode::BAD_REQUEST
+                }
+                _ => StatusCode::UNAUTHORIZED,
+            },
+            ApiError::Throttled(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Authorization(e) => e.status_code(),
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse {
-        actix_web::HttpResponse::build(self.status_code())
-            .insert_header(ContentType::json())
-            .body(format!(
-                r#"{{
+        if let ApiError::Authorization(e) = self {
+            return e.error_response();
+        }
+        let mut resp = actix_web::HttpResponse::build(self.status_code());
+        if let ApiError::Throttled(retry_after) = self {
+            resp.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        resp.insert_header(ContentType::json()).body(format!(
+            r#"{{
                 "code": {},
                 "msg": "{}",
                 "timestamp": "{}"
             }}"#,
-                self.status_code().as_str(),
-                self,
-                Local::now().naive_utc()
-            ))
+            self.status_code().as_str(),
+            self,
+            Local::now().naive_utc()
+        ))
     }
 }
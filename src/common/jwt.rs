@@ -1,11 +1,15 @@
-use std::{convert::AsRef, fmt::Debug, str::FromStr, time::Duration};
+use std::{convert::AsRef, fmt::Debug, io, str::FromStr, sync::OnceLock, time::Duration};
 
-use chrono::{
-    serde::ts_microseconds::{deserialize as deserialize_to_ts, serialize as serialize_to_ts},
-    DateTime, Utc,
-};
+use chrono::{DateTime, TimeZone, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey};
-use openssl::error::ErrorStack;
+use openssl::{
+    encrypt::Encrypter,
+    error::ErrorStack,
+    hash::MessageDigest,
+    pkey::PKey,
+    rsa::Padding,
+    symm::{encrypt_aead, Cipher},
+};
 use ring::{
     error::KeyRejected,
     rand::{SecureRandom, SystemRandom},
@@ -15,10 +19,22 @@ use ring::{
     },
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use strum::{AsRefStr, EnumIter};
+use strum::{AsRefStr, EnumIter, IntoEnumIterator};
+
+use super::{
+    config::env_var_default,
+    utils::{decode64, encode64url, gen_id},
+};
+use crate::{
+    common::constant::{Gander, TOKEN_ISSUER},
+    dto::user::UserProfile,
+};
 
-use super::utils::gen_id;
-use crate::{common::constant::Gander, dto::user::UserProfile};
+static ACTIVE_KEY: OnceLock<JwKPair> = OnceLock::new();
+
+pub fn issuer(client_id: &str) -> String {
+    TOKEN_ISSUER.replace("{}", client_id)
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IdToken {
@@ -28,13 +44,22 @@ pub struct IdToken {
     pub picture: String,
     pub name: String,
     pub gander: Gander,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    // https://openid.net/specs/openid-connect-core-1_0.html#HybridIDToken
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_hash: Option<String>,
 }
 
 impl IdToken {
     pub fn new(client_id: &str, user: &UserProfile, expires_in: Duration) -> Self {
         Self {
             token: TokenCalims::new(
-                format!("https://auth.heliannuuthus.com/issuer/{}", client_id).as_str(),
+                issuer(client_id).as_str(),
                 &user.openid,
                 client_id,
                 expires_in,
@@ -43,35 +68,97 @@ impl IdToken {
             picture: user.avatar.clone(),
             name: user.nickname.clone(),
             gander: user.gander.clone(),
+            amr: Vec::new(),
+            nonce: None,
+            c_hash: None,
+            at_hash: None,
         }
     }
 }
 
+// https://datatracker.ietf.org/doc/html/rfc9068#section-2.1
+pub const ACCESS_TOKEN_JWT_TYPE: &str = "at+jwt";
+
+// https://datatracker.ietf.org/doc/html/rfc9068#section-2.2
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccessToken {
     #[serde(flatten)]
     pub token: TokenCalims,
-    pub azp: String,
+    pub client_id: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_scope",
+        deserialize_with = "deserialize_scope"
+    )]
     pub scope: Vec<String>,
+    // https://datatracker.ietf.org/doc/html/rfc9068#section-2.2.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+// 委托链，最外层为当前调用方，嵌套的 act 为此前的调用方
+// https://datatracker.ietf.org/doc/html/rfc8693#section-4.1
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Actor {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Box<Actor>>,
+}
+
+// 持有者约束，resource server 校验调用方与 token 绑定的证书
+// https://datatracker.ietf.org/doc/html/rfc7800#section-3.1
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Confirmation {
+    // https://datatracker.ietf.org/doc/html/rfc8705#section-3.1
+    #[serde(rename = "x5t#S256", default, skip_serializing_if = "Option::is_none")]
+    pub x5t_s256: Option<String>,
+    // https://datatracker.ietf.org/doc/html/rfc9449#section-6.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jkt: Option<String>,
+}
+
+// scope 为空格分隔的字符串
+// https://datatracker.ietf.org/doc/html/rfc9068#section-2.2.3
+fn serialize_scope<S: serde::Serializer>(
+    scope: &[String],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&scope.join(" "))
+}
+
+fn deserialize_scope<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    Ok(String::deserialize(deserializer)?
+        .split(' ')
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect())
 }
 
 impl AccessToken {
     pub fn new(
         subject: &str,
         audience: &str,
-        azp: &str,
+        client_id: &str,
         expires_in: Duration,
         scope: Vec<String>,
     ) -> Self {
         Self {
-            token: TokenCalims::new(
-                format!("https://auth.heliannuuthus.com/issuer/{}", azp).as_str(),
-                subject,
-                audience,
-                expires_in,
-            ),
-            azp: azp.to_string(),
+            token: TokenCalims::new(issuer(client_id).as_str(), subject, audience, expires_in),
+            client_id: client_id.to_string(),
             scope,
+            auth_time: None,
+            acr: None,
+            cnf: None,
+            act: None,
         }
     }
 }
@@ -83,38 +170,57 @@ pub struct TokenCalims {
     // audience using single resource server
     // https://datatracker.ietf.org/doc/html/draft-ietf-oauth-security-topics#name-access-token-privilege-rest
     pub aud: String,
-    #[serde(
-        serialize_with = "serialize_to_ts",
-        deserialize_with = "deserialize_to_ts"
-    )]
+    #[serde(with = "numeric_date")]
     pub exp: DateTime<Utc>,
-    #[serde(
-        serialize_with = "serialize_to_ts",
-        deserialize_with = "deserialize_to_ts"
-    )]
+    #[serde(with = "numeric_date")]
     pub nbf: DateTime<Utc>,
-    #[serde(
-        serialize_with = "serialize_to_ts",
-        deserialize_with = "deserialize_to_ts"
-    )]
+    #[serde(with = "numeric_date")]
     pub iat: DateTime<Utc>,
     pub jti: String,
 }
 
 impl TokenCalims {
     pub fn new(iss: &str, sub: &str, aud: &str, expires_in: std::time::Duration) -> Self {
+        // 时钟偏差交给校验方的 leeway 处理，nbf 与 iat 一致
+        let now = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
         Self {
             iss: iss.to_string(),
             sub: sub.to_string(),
             aud: aud.to_string(),
-            exp: Utc::now() + chrono::Duration::from_std(expires_in).unwrap(),
-            nbf: Utc::now() - chrono::Duration::minutes(5),
-            iat: Utc::now(),
+            exp: now + chrono::Duration::from_std(expires_in).unwrap(),
+            nbf: now,
+            iat: now,
             jti: gen_id(24),
         }
     }
 }
 
+// NumericDate 为自 epoch 起的秒数，接收方需兼容小数
+// https://datatracker.ietf.org/doc/html/rfc7519#section-2
+pub mod numeric_date {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        date: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(date.timestamp())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        if !seconds.is_finite() {
+            return Err(D::Error::custom("NumericDate must be finite"));
+        }
+        Utc.timestamp_opt(seconds.floor() as i64, 0)
+            .single()
+            .ok_or_else(|| D::Error::custom("NumericDate is out of range"))
+    }
+}
+
 pub struct JwKPair {
     alg: JwtAlgorithm,
     inner: Vec<u8>,
@@ -124,6 +230,10 @@ impl JwKPair {
     pub fn new(alg: JwtAlgorithm, inner: Vec<u8>) -> Self {
         JwKPair { alg, inner }
     }
+    pub fn alg(&self) -> &JwtAlgorithm {
+        &self.alg
+    }
+
     pub fn export_alg(&self) -> Result<jsonwebtoken::Algorithm> {
         jsonwebtoken::Algorithm::from_str(self.alg.as_ref()).map_err(|e| {
             JwtErorr::UnSupported(format!(
@@ -188,6 +298,16 @@ pub enum JwtAlgorithm {
     EdDSA,
 }
 
+impl FromStr for JwtAlgorithm {
+    type Err = JwtErorr;
+
+    fn from_str(s: &str) -> Result<Self> {
+        JwtAlgorithm::iter()
+            .find(|alg| alg.as_ref() == s)
+            .ok_or_else(|| JwtErorr::UnSupported(format!("unknown alg {}", s)))
+    }
+}
+
 impl JwtAlgorithm {
     pub fn variant(&self) -> JwtAlgorithmVariant {
         match self {
@@ -216,6 +336,24 @@ impl JwtAlgorithm {
         }
     }
 
+    // 签名算法对应的摘要算法，EdDSA 按 Ed25519 使用 SHA-512
+    pub fn digest(&self) -> &'static ring::digest::Algorithm {
+        match self {
+            JwtAlgorithm::HS256
+            | JwtAlgorithm::ES256
+            | JwtAlgorithm::RS256
+            | JwtAlgorithm::PS256 => &ring::digest::SHA256,
+            JwtAlgorithm::HS384
+            | JwtAlgorithm::ES384
+            | JwtAlgorithm::RS384
+            | JwtAlgorithm::PS384 => &ring::digest::SHA384,
+            JwtAlgorithm::HS512
+            | JwtAlgorithm::RS512
+            | JwtAlgorithm::PS512
+            | JwtAlgorithm::EdDSA => &ring::digest::SHA512,
+        }
+    }
+
     pub fn compute_ecdsa_algorithm(alg: &JwtAlgorithm) -> Result<&'static EcdsaSigningAlgorithm> {
         match alg {
             JwtAlgorithm::ES256 => Ok(&ECDSA_P256_SHA256_ASN1_SIGNING),
@@ -244,6 +382,8 @@ pub enum JwtErorr {
     VerifyError(String),
     #[error("sign token erorr {0}")]
     SignError(String),
+    #[error("invalid signing key: {0}")]
+    InvalidKey(String),
 }
 
 // 根据指定的算法生成一个 Key, ed 相关没有
@@ -291,7 +431,13 @@ pub fn genrate_key(alg: JwtAlgorithm) -> Result<JwKPair> {
 }
 
 pub fn generate_jws<T: Serialize>(claims: &T, secret: &JwKPair) -> Result<String> {
+    generate_typed_jws(claims, secret, "JWT")
+}
+
+// access token 使用 at+jwt，避免与 ID token 混用
+pub fn generate_typed_jws<T: Serialize>(claims: &T, secret: &JwKPair, typ: &str) -> Result<String> {
     let mut headers = jsonwebtoken::Header::new(secret.export_alg()?);
+    headers.typ = Some(typ.to_string());
     let encoding_key = match secret.alg.variant() {
         JwtAlgorithmVariant::Hmac => EncodingKey::from_secret(&secret.export_prikey()),
         JwtAlgorithmVariant::Rsa => EncodingKey::from_rsa_der(&secret.export_prikey()),
@@ -322,8 +468,145 @@ pub fn verify_jws<T: DeserializeOwned>(
     Ok(claims)
 }
 
+fn access_token_typed(token: &str) -> Result<bool> {
+    let typ = jsonwebtoken::decode_header(token)
+        .map_err(|e| JwtErorr::VerifyError(format!("{}", e)))?
+        .typ
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    Ok(typ == ACCESS_TOKEN_JWT_TYPE || typ == format!("application/{ACCESS_TOKEN_JWT_TYPE}"))
+}
+
+// 只接受 typ 为 at+jwt 的 access token
+// https://datatracker.ietf.org/doc/html/rfc9068#section-4
+pub fn verify_access_token(
+    token: &str,
+    secret: &JwKPair,
+    validation: jsonwebtoken::Validation,
+) -> Result<AccessToken> {
+    if !access_token_typed(token)? {
+        return Err(JwtErorr::VerifyError(
+            "token is not an access token".to_string(),
+        ));
+    }
+    verify_jws::<AccessToken>(token, secret, validation)
+}
+
+// 拒绝把 access token 当作 ID token 使用
+pub fn verify_id_token(
+    token: &str,
+    secret: &JwKPair,
+    validation: jsonwebtoken::Validation,
+) -> Result<IdToken> {
+    if access_token_typed(token)? {
+        return Err(JwtErorr::VerifyError(
+            "token is not an id token".to_string(),
+        ));
+    }
+    verify_jws::<IdToken>(token, secret, validation)
+}
+
+// 校验 exp 与 nbf 时允许的时钟偏差（秒）
+pub fn leeway() -> u64 {
+    env_var_default::<u64>("JWT_LEEWAY", 60)
+}
+
 pub fn validation(key: &JwKPair, audience: Vec<String>) -> Result<jsonwebtoken::Validation> {
     let mut validation = jsonwebtoken::Validation::new(key.export_alg()?);
     validation.set_audience(&audience);
+    validation.leeway = leeway();
+    validation.validate_nbf = true;
     Ok(validation)
 }
+
+// 签发使用的活动密钥，JWT_SIGNING_KEY 为 base64 编码的私钥（RSA 为 DER，其余为 PKCS#8）
+// 临时密钥在重启后失效且各实例互不相同，只有显式设置 JWT_EPHEMERAL_KEY 才允许使用
+pub fn load_active_key() -> Result<JwKPair> {
+    let alg = env_var_default::<String>("JWT_SIGNING_ALG", "RS256".to_string())
+        .parse::<JwtAlgorithm>()?;
+    let key = match env_var_default::<String>("JWT_SIGNING_KEY", String::new()) {
+        key if !key.is_empty() => JwKPair::new(
+            alg,
+            decode64(key.trim_end_matches('='))
+                .map_err(|_| JwtErorr::InvalidKey("JWT_SIGNING_KEY must be base64".to_string()))?,
+        ),
+        _ if env_var_default::<bool>("JWT_EPHEMERAL_KEY", false) => {
+            tracing::warn!("JWT_SIGNING_KEY is absent, signing with an ephemeral key");
+            genrate_key(alg)?
+        }
+        _ => {
+            return Err(JwtErorr::InvalidKey(
+                "JWT_SIGNING_KEY is absent".to_string(),
+            ))
+        }
+    };
+    // 私钥与算法不匹配时无法导出公钥
+    key.export_alg()?;
+    if key.export_pubkey()?.is_empty() {
+        return Err(JwtErorr::InvalidKey("JWT_SIGNING_KEY is empty".to_string()));
+    }
+    Ok(key)
+}
+
+// 启动时加载，密钥缺失或无效时终止启动
+pub fn init_active_key() -> io::Result<()> {
+    let key = load_active_key().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let _ = ACTIVE_KEY.set(key);
+    Ok(())
+}
+
+// c_hash / at_hash：摘要左半部分的 base64url
+// https://openid.net/specs/openid-connect-core-1_0.html#CodeIDToken
+pub fn half_hash(alg: &JwtAlgorithm, value: &str) -> String {
+    let digest = ring::digest::digest(alg.digest(), value.as_bytes());
+    let digest = digest.as_ref();
+    encode64url(&digest[..digest.len() / 2])
+}
+
+// main 启动时已通过 init_active_key 加载
+pub fn active_key() -> &'static JwKPair {
+    ACTIVE_KEY.get_or_init(|| load_active_key().expect("JWT signing key is invalid"))
+}
+
+// RSA-OAEP-256 + A256GCM 的 JWE 紧凑序列化，public_key 为 PEM 格式的 RSA 公钥
+// https://datatracker.ietf.org/doc/html/rfc7516#section-3.1
+pub fn generate_jwe(payload: &[u8], public_key: &[u8], cty: &str) -> Result<String> {
+    let key = PKey::public_key_from_pem(public_key)?;
+    let header = encode64url(
+        serde_json::json!({ "alg": "RSA-OAEP-256", "enc": "A256GCM", "cty": cty })
+            .to_string()
+            .as_bytes(),
+    );
+    let rng = SystemRandom::new();
+    let mut cek = [0u8; 32];
+    let mut iv = [0u8; 12];
+    rng.fill(&mut cek)
+        .and_then(|_| rng.fill(&mut iv))
+        .map_err(|_| JwtErorr::SignError("generate content encryption key failed".to_string()))?;
+
+    let mut encrypter = Encrypter::new(&key)?;
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    encrypter.set_rsa_mgf1_md(MessageDigest::sha256())?;
+    let mut encrypted_key = vec![0u8; encrypter.encrypt_len(&cek)?];
+    let len = encrypter.encrypt(&cek, &mut encrypted_key)?;
+    encrypted_key.truncate(len);
+
+    let mut tag = [0u8; 16];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &cek,
+        Some(&iv),
+        header.as_bytes(),
+        payload,
+        &mut tag,
+    )?;
+    Ok([
+        header,
+        encode64url(&encrypted_key),
+        encode64url(&iv),
+        encode64url(&ciphertext),
+        encode64url(&tag),
+    ]
+    .join("."))
+}
@@ -1 +1,374 @@
-use actix_web::error::ResponseError;
+use std::{
+    collections::HashMap,
+    io,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::ResponseError,
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    mime,
+    web::{Bytes, Query},
+    HttpMessage,
+};
+use chrono::{Duration, Utc};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::Deserialize;
+
+use super::{
+    cache::redis::redis_token_bucket,
+    config::env_var_default,
+    errors::ApiError,
+    proxy::client_ip,
+    utils::{decode64, decode64url},
+};
+
+const DEFAULT_RULES: &str =
+    r#"[{"route": "/", "by": "remote_addr", "capacity": 120, "refill_rate": 2}]"#;
+// Redis 不可用时改用本地令牌桶，并在这段时间后再重试 Redis
+const REDIS_RETRY_INTERVAL: i64 = 5000;
+
+lazy_static::lazy_static! {
+    static ref LOCAL_BUCKETS: moka::future::Cache<String, Arc<Mutex<TokenBucket>>> =
+        moka::future::Cache::builder()
+            .name("rate_limit_bucket_cache")
+            .time_to_idle(Duration::minutes(10).to_std().unwrap())
+            .build();
+}
+
+static REDIS_RETRY_AT: AtomicI64 = AtomicI64::new(0);
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    Route,
+    ClientId,
+    RemoteAddr,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimitRule {
+    // 路径前缀，"/" 匹配全部
+    pub route: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    pub by: RateLimitKey,
+    pub capacity: u32,
+    // 每秒补充的令牌数
+    pub refill_rate: f64,
+}
+
+impl RateLimitRule {
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let route = self.route.trim_end_matches('/');
+        let prefixed = path == route
+            || route.is_empty()
+            || path
+                .strip_prefix(route)
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or_default();
+        prefixed
+            && self
+                .method
+                .as_ref()
+                .map(|m| m.eq_ignore_ascii_case(method))
+                .unwrap_or(true)
+    }
+
+    // 令牌从空到满所需的秒数
+    pub fn window(&self) -> u64 {
+        (self.capacity as f64 / self.refill_rate).ceil() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated_at: i64,
+}
+
+impl TokenBucket {
+    pub fn new(rule: &RateLimitRule, now: i64) -> Self {
+        Self {
+            tokens: rule.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    // now 为毫秒时间戳，与 Redis 脚本的计算保持一致
+    pub fn take(&mut self, rule: &RateLimitRule, now: i64) -> Decision {
+        let elapsed = (now - self.updated_at).max(0) as f64;
+        self.tokens = (self.tokens + elapsed * rule.refill_rate / 1000.0).min(rule.capacity as f64);
+        self.updated_at = now;
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        Decision::new(rule, allowed, self.tokens)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // 令牌补满所需秒数
+    pub reset: u64,
+    pub retry_after: u64,
+    pub window: u64,
+}
+
+impl Decision {
+    pub fn new(rule: &RateLimitRule, allowed: bool, tokens: f64) -> Self {
+        Self {
+            allowed,
+            limit: rule.capacity,
+            remaining: tokens.floor().max(0.0) as u32,
+            reset: ((rule.capacity as f64 - tokens).max(0.0) / rule.refill_rate).ceil() as u64,
+            retry_after: if allowed {
+                0
+            } else {
+                ((1.0 - tokens) / rule.refill_rate).ceil().max(1.0) as u64
+            },
+            window: rule.window(),
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            ("ratelimit-limit", self.limit.to_string()),
+            ("ratelimit-remaining", self.remaining.to_string()),
+            ("ratelimit-reset", self.reset.to_string()),
+            (
+                "ratelimit-policy",
+                format!("{};w={}", self.limit, self.window),
+            ),
+        ] {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(&value).unwrap(),
+            );
+        }
+    }
+
+    // 多条规则同时命中时，拒绝优先，其次取剩余最少的
+    pub fn strictest(self, other: Decision) -> Decision {
+        match (self.allowed, other.allowed) {
+            (true, false) => other,
+            (false, true) => self,
+            (false, false) if other.retry_after > self.retry_after => other,
+            (true, true) if other.remaining < self.remaining => other,
+            _ => self,
+        }
+    }
+}
+
+// 限流规则从 RATE_LIMIT_RULES 读取（JSON 数组），修改后重启即可生效
+pub fn load_rules() -> io::Result<Vec<RateLimitRule>> {
+    parse_rules(&env_var_default::<String>(
+        "RATE_LIMIT_RULES",
+        DEFAULT_RULES.to_string(),
+    ))
+}
+
+pub fn parse_rules(rules: &str) -> io::Result<Vec<RateLimitRule>> {
+    let rules = serde_json::from_str::<Vec<RateLimitRule>>(rules).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("RATE_LIMIT_RULES must be a json array of rate limit rules: {e}"),
+        )
+    })?;
+    // 容量或补充速率无效的规则会让限流失效，直接拒绝启动
+    if let Some(rule) = rules.iter().find(|rule| {
+        rule.capacity == 0 || !(rule.refill_rate.is_finite() && rule.refill_rate > 0.0)
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "RATE_LIMIT_RULES contains invalid rule for {} (capacity {}, refill_rate {})",
+                rule.route, rule.capacity, rule.refill_rate
+            ),
+        ));
+    }
+    Ok(rules)
+}
+
+// 表单中的 client_id，或 client_assertion 的 sub / iss，只用于限流分组，不做校验
+// https://datatracker.ietf.org/doc/html/rfc7523#section-3
+pub fn form_client_id(body: &[u8]) -> Option<String> {
+    let params = url::form_urlencoded::parse(body).collect::<HashMap<_, _>>();
+    if let Some(client_id) = params.get("client_id").filter(|id| !id.is_empty()) {
+        return Some(client_id.to_string());
+    }
+    let claims = params
+        .get("client_assertion")?
+        .split('.')
+        .nth(1)
+        .and_then(|claims| decode64url(claims).ok())
+        .and_then(|claims| serde_json::from_slice::<serde_json::Value>(&claims).ok())?;
+    ["sub", "iss"]
+        .iter()
+        .find_map(|claim| claims[claim].as_str().map(str::to_string))
+}
+
+fn basic_client_id(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| decode64(credentials.trim_end_matches('=')).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| credentials.split_once(':').map(|(id, _)| id.to_string()))
+}
+
+// client_id 依次取自查询参数、client_secret_basic 的用户名与表单，
+// 读取表单后把请求体放回，后续的处理器仍能读取
+async fn client_id(req: &mut ServiceRequest) -> String {
+    if let Some(client_id) = Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("client_id").cloned())
+    {
+        return client_id;
+    }
+    if let Some(client_id) = basic_client_id(req) {
+        return client_id;
+    }
+    let form = matches!(
+        req.mime_type(),
+        Ok(Some(mime)) if mime.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str()
+    );
+    if form {
+        if let Ok(body) = req.extract::<Bytes>().await {
+            let client_id = form_client_id(&body);
+            req.set_payload(Payload::from(body));
+            if let Some(client_id) = client_id {
+                return client_id;
+            }
+        }
+    }
+    "anonymous".to_string()
+}
+
+async fn take(key: String, rule: &RateLimitRule, now: i64) -> Decision {
+    if now >= REDIS_RETRY_AT.load(Ordering::Relaxed) {
+        match redis_token_bucket(&key, rule.capacity, rule.refill_rate / 1000.0, now).await {
+            Ok((allowed, tokens)) => return Decision::new(rule, allowed, tokens),
+            Err(e) => {
+                tracing::warn!("rate limit falls back to local buckets, {e}");
+                REDIS_RETRY_AT.store(now + REDIS_RETRY_INTERVAL, Ordering::Relaxed);
+            }
+        }
+    }
+    let bucket = LOCAL_BUCKETS
+        .get_with(key, async {
+            Arc::new(Mutex::new(TokenBucket::new(rule, now)))
+        })
+        .await;
+    let decision = bucket.lock().unwrap().take(rule, now);
+    decision
+}
+
+async fn evaluate(rules: &[RateLimitRule], req: &mut ServiceRequest) -> Option<Decision> {
+    let now = Utc::now().timestamp_millis();
+    let mut decision: Option<Decision> = None;
+    let mut client: Option<String> = None;
+    for (index, rule) in rules.iter().enumerate() {
+        if !rule.matches(req.method().as_str(), req.path()) {
+            continue;
+        }
+        let subject = match rule.by {
+            RateLimitKey::Route => rule.route.clone(),
+            RateLimitKey::ClientId => match &client {
+                Some(client) => client.clone(),
+                None => client.insert(client_id(req).await).clone(),
+            },
+            RateLimitKey::RemoteAddr => client_ip(req.peer_addr(), req.headers()),
+        };
+        let current = take(
+            format!("forum:auth:ratelimit:{index}:{}", subject),
+            rule,
+            now,
+        )
+        .await;
+        decision = Some(match decision {
+            Some(previous) => previous.strictest(current),
+            None => current,
+        });
+    }
+    decision
+}
+
+pub struct RateLimiter {
+    rules: Rc<Vec<RateLimitRule>>,
+}
+
+impl RateLimiter {
+    pub fn new(rules: Vec<RateLimitRule>) -> Self {
+        Self {
+            rules: Rc::new(rules),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            rules: self.rules.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    rules: Rc<Vec<RateLimitRule>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let rules = self.rules.clone();
+        Box::pin(async move {
+            let mut req = req;
+            let decision = evaluate(&rules, &mut req).await;
+            if let Some(decision) = decision.as_ref().filter(|decision| !decision.allowed) {
+                tracing::warn!("rate limited: {} {}", req.method(), req.path());
+                let mut resp = ApiError::Throttled(decision.retry_after).error_response();
+                decision.apply(resp.headers_mut());
+                return Ok(req.into_response(resp).map_into_right_body());
+            }
+            let mut resp = service.call(req).await?;
+            if let Some(decision) = decision {
+                decision.apply(resp.headers_mut());
+            }
+            Ok(resp.map_into_left_body())
+        })
+    }
+}
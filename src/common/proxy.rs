@@ -0,0 +1,81 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+use actix_web::http::header::HeaderMap;
+use ipnet::IpNet;
+
+use super::config::env_var_default;
+
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+static TRUSTED_PROXIES: OnceLock<Vec<IpNet>> = OnceLock::new();
+
+// TRUSTED_PROXIES 为逗号分隔的 IP 或 CIDR，只有这些代理转发的 X-Forwarded-For 可信
+pub fn parse_trusted_proxies(proxies: &str) -> io::Result<Vec<IpNet>> {
+    proxies
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .map(|proxy| {
+            proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("TRUSTED_PROXIES contains malformed address: {proxy}"),
+                    )
+                })
+        })
+        .collect()
+}
+
+// 启动时加载，配置错误直接终止启动
+pub fn init_trusted_proxies() -> io::Result<()> {
+    let proxies =
+        parse_trusted_proxies(&env_var_default::<String>("TRUSTED_PROXIES", String::new()))?;
+    let _ = TRUSTED_PROXIES.set(proxies);
+    Ok(())
+}
+
+// 从右往左跳过可信代理，第一个不可信的地址即客户端地址
+pub fn resolve_client_ip(peer: IpAddr, forwarded_for: &str, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    let mut client = peer;
+    if !is_trusted(&client) {
+        return client;
+    }
+    for hop in forwarded_for.rsplit(',').map(str::trim) {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !is_trusted(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+// 直连地址不在可信代理列表中时忽略客户端可伪造的转发头
+pub fn client_ip(peer: Option<SocketAddr>, headers: &HeaderMap) -> String {
+    let Some(peer) = peer else {
+        return "unknown".to_string();
+    };
+    let forwarded_for = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    resolve_client_ip(
+        peer.ip(),
+        &forwarded_for,
+        TRUSTED_PROXIES.get().map(Vec::as_slice).unwrap_or_default(),
+    )
+    .to_string()
+}
@@ -1,15 +1,17 @@
 use num_bigint::BigUint;
-use ring::digest::Context;
+use ring::{constant_time::verify_slices_are_equal, digest::Context};
 
 use crate::common::srp::{
     errors::SrpError,
     groups::SrpGroup,
-    utils::{compute_k, compute_m1, compute_m2, compute_u},
+    types::SrpProofMode,
+    utils::{compute_k, compute_key, compute_m1, compute_m2, compute_u},
 };
 
 /// SRP client state before handshake with the server.
 pub struct SrpClient<'a> {
     params: &'a SrpGroup,
+    mode: SrpProofMode,
 }
 
 /// SRP client state after handshake with the server.
@@ -22,7 +24,12 @@ pub struct SrpClientVerifier {
 impl<'a> SrpClient<'a> {
     /// Create new SRP client instance.
     pub fn new(params: &'a SrpGroup) -> Self {
-        Self { params }
+        Self::with_mode(params, SrpProofMode::default())
+    }
+
+    /// Create new SRP client instance with the given proof mode.
+    pub fn with_mode(params: &'a SrpGroup, mode: SrpProofMode) -> Self {
+        Self { params, mode }
     }
 
     pub fn compute_a_pub(&self, a: &BigUint) -> BigUint {
@@ -106,27 +113,35 @@ impl<'a> SrpClient<'a> {
             return Err(SrpError::IllegalParameter("b_pub".to_owned()));
         }
 
-        let u = compute_u(self.params, &a_pub.to_bytes_be(), &b_pub.to_bytes_be());
+        let u = compute_u(
+            self.params,
+            self.mode,
+            &a_pub.to_bytes_be(),
+            &b_pub.to_bytes_be(),
+        );
+        if u == BigUint::default() {
+            return Err(SrpError::IllegalParameter("u".to_owned()));
+        }
         let k = compute_k(self.params);
         let identity_hash = Self::compute_identity_hash(self.params, username, password);
         let x = Self::compute_x(self.params, identity_hash.as_slice(), salt);
 
-        let key = self.compute_pre_master_secret(&b_pub, &k, &x, &a, &u);
+        let premaster_secret = self.compute_pre_master_secret(&b_pub, &k, &x, &a, &u);
+        let key = compute_key(self.params, self.mode, &premaster_secret.to_bytes_be());
 
         let m1 = compute_m1(
             self.params,
+            self.mode,
+            username,
+            salt,
             &a_pub.to_bytes_be(),
             &b_pub.to_bytes_be(),
-            &key.to_bytes_be(),
+            &key,
         );
 
-        let m2 = compute_m2(self.params, &a_pub.to_bytes_be(), &m1, &key.to_bytes_be());
+        let m2 = compute_m2(self.params, &a_pub.to_bytes_be(), &m1, &key);
 
-        Ok(SrpClientVerifier {
-            m1,
-            m2,
-            key: key.to_bytes_be(),
-        })
+        Ok(SrpClientVerifier { m1, m2, key })
     }
 }
 
@@ -145,11 +160,7 @@ impl SrpClientVerifier {
 
     /// Verify server reply to verification data.
     pub fn verify_server(&self, reply: &[u8]) -> Result<(), SrpError> {
-        if self.m2 == reply {
-            // aka == 0
-            Ok(())
-        } else {
-            Err(SrpError::BadRecordMac("server".to_owned()))
-        }
+        verify_slices_are_equal(&self.m2, reply)
+            .map_err(|_| SrpError::BadRecordMac("server".to_owned()))
     }
 }
@@ -5,7 +5,9 @@
 //! they are provided only for compatibility with the legacy software.
 use lazy_static::lazy_static;
 use num_bigint::BigUint;
-use ring::digest::{Algorithm, SHA256};
+use ring::digest::{Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+
+use crate::common::srp::types::{SrpGroupId, SrpHash};
 
 /// Group used for SRP computations
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -17,6 +19,23 @@ pub struct SrpGroup {
     pub alg: &'static Algorithm,
 }
 
+lazy_static! {
+    /// RFC 5054 appendix A 1024-bit group, hashed with SHA-1 as in appendix B
+    pub static ref G_1024: SrpGroup = SrpGroup {
+        n: BigUint::from_bytes_be(include_bytes!("groups/1024.bin")),
+        g: BigUint::from_bytes_be(&[2]),
+        alg: &SHA1_FOR_LEGACY_USE_ONLY
+    };
+}
+
+lazy_static! {
+    pub static ref G_1536: SrpGroup = SrpGroup {
+        n: BigUint::from_bytes_be(include_bytes!("groups/1536.bin")),
+        g: BigUint::from_bytes_be(&[2]),
+        alg: &SHA256
+    };
+}
+
 lazy_static! {
     pub static ref G_2048: SrpGroup = SrpGroup {
         n: BigUint::from_bytes_be(include_bytes!("groups/2048.bin")),
@@ -56,3 +75,20 @@ lazy_static! {
         alg: &SHA256
     };
 }
+
+/// Group parameters of the user record, hashed with the chosen algorithm.
+pub fn select_group(group: SrpGroupId, hash: SrpHash) -> SrpGroup {
+    let params: &SrpGroup = match group {
+        SrpGroupId::G1024 => &G_1024,
+        SrpGroupId::G1536 => &G_1536,
+        SrpGroupId::G2048 => &G_2048,
+        SrpGroupId::G3072 => &G_3072,
+        SrpGroupId::G4096 => &G_4096,
+        SrpGroupId::G6144 => &G_6144,
+        SrpGroupId::G8192 => &G_8192,
+    };
+    SrpGroup {
+        alg: hash.algorithm(),
+        ..params.clone()
+    }
+}
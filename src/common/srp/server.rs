@@ -1,14 +1,17 @@
 use num_bigint::BigUint;
+use ring::constant_time::verify_slices_are_equal;
 
 use crate::common::srp::{
     errors::SrpError,
     groups::SrpGroup,
-    utils::{compute_k, compute_m1, compute_m2, compute_u},
+    types::SrpProofMode,
+    utils::{compute_k, compute_key, compute_m1, compute_m2, compute_u},
 };
 
 /// SRP server state
 pub struct SrpServer<'a> {
     params: &'a SrpGroup,
+    mode: SrpProofMode,
 }
 
 /// SRP server state after handshake with the client.
@@ -20,9 +23,9 @@ pub struct SrpServerVerifier {
 }
 
 impl<'a> SrpServer<'a> {
-    /// Create new server state.
-    pub fn new(params: &'a SrpGroup) -> Self {
-        Self { params }
+    /// Create new server state with the proof mode of the user record.
+    pub fn with_mode(params: &'a SrpGroup, mode: SrpProofMode) -> Self {
+        Self { params, mode }
     }
 
     //  k*v + g^b % N
@@ -55,10 +58,13 @@ impl<'a> SrpServer<'a> {
     }
 
     /// Process client reply to the handshake.
+    /// username and salt are the user record, only used by the rfc5054 proof,
     /// b is a random value,
     /// v is the provided during initial user registration
     pub fn process_reply(
         &self,
+        username: &[u8],
+        salt: &[u8],
         b: &[u8],
         v: &[u8],
         a_pub: &[u8],
@@ -75,24 +81,32 @@ impl<'a> SrpServer<'a> {
             return Err(SrpError::IllegalParameter("a_pub".to_owned()));
         }
 
-        let u = compute_u(self.params, &a_pub.to_bytes_be(), &b_pub.to_bytes_be());
+        let u = compute_u(
+            self.params,
+            self.mode,
+            &a_pub.to_bytes_be(),
+            &b_pub.to_bytes_be(),
+        );
+        if u == BigUint::default() {
+            return Err(SrpError::IllegalParameter("u".to_owned()));
+        }
 
-        let key = self.compute_pre_master_secret(&a_pub, &v, &u, &b);
+        let premaster_secret = self.compute_pre_master_secret(&a_pub, &v, &u, &b);
+        let key = compute_key(self.params, self.mode, &premaster_secret.to_bytes_be());
 
         let m1 = compute_m1(
             self.params,
+            self.mode,
+            username,
+            salt,
             &a_pub.to_bytes_be(),
             &b_pub.to_bytes_be(),
-            &key.to_bytes_be(),
+            &key,
         );
 
-        let m2 = compute_m2(self.params, &a_pub.to_bytes_be(), &m1, &key.to_bytes_be());
+        let m2 = compute_m2(self.params, &a_pub.to_bytes_be(), &m1, &key);
 
-        Ok(SrpServerVerifier {
-            m1,
-            m2,
-            key: key.to_bytes_be(),
-        })
+        Ok(SrpServerVerifier { m1, m2, key })
     }
 }
 
@@ -111,11 +125,7 @@ impl SrpServerVerifier {
 
     /// Process user proof of having the same shared secret.
     pub fn verify_client(&self, reply: &[u8]) -> Result<(), SrpError> {
-        if self.m1 == reply {
-            // aka == 0
-            Ok(())
-        } else {
-            Err(SrpError::BadRecordMac("client".to_owned()))
-        }
+        verify_slices_are_equal(&self.m1, reply)
+            .map_err(|_| SrpError::BadRecordMac("client".to_owned()))
     }
 }
@@ -1 +1,77 @@
+use ring::digest::{Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256, SHA384, SHA512};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+/// How u, the session key K and the M1/M2 proofs are derived.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SrpProofMode {
+    /// u = H(A | B), K = S, M1 = H(A | B | K)
+    ///
+    /// Kept for verifiers registered before the spec-compliant mode existed.
+    #[default]
+    Legacy,
+    /// [RFC 5054](https://tools.ietf.org/html/rfc5054) / RFC 2945 compatible:
+    /// u = H(PAD(A) | PAD(B)), K = H(S), M1 = H(H(N) xor H(g) | H(I) | s | A | B | K)
+    Rfc5054,
+}
+
+/// Groups from [RFC 5054](https://tools.ietf.org/html/rfc5054) appendix A
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum SrpGroupId {
+    #[serde(rename = "1024")]
+    G1024,
+    #[serde(rename = "1536")]
+    G1536,
+    #[default]
+    #[serde(rename = "2048")]
+    G2048,
+    #[serde(rename = "3072")]
+    G3072,
+    #[serde(rename = "4096")]
+    G4096,
+    #[serde(rename = "6144")]
+    G6144,
+    #[serde(rename = "8192")]
+    G8192,
+}
+
+impl SrpGroupId {
+    pub fn bits(&self) -> usize {
+        match self {
+            SrpGroupId::G1024 => 1024,
+            SrpGroupId::G1536 => 1536,
+            SrpGroupId::G2048 => 2048,
+            SrpGroupId::G3072 => 3072,
+            SrpGroupId::G4096 => 4096,
+            SrpGroupId::G6144 => 6144,
+            SrpGroupId::G8192 => 8192,
+        }
+    }
+}
+
+/// Hash function H used for k, u, x, K and the proofs.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum SrpHash {
+    /// Only for RFC 5054 test vectors and legacy clients.
+    #[serde(rename = "SHA-1")]
+    Sha1,
+    #[default]
+    #[serde(rename = "SHA-256")]
+    Sha256,
+    #[serde(rename = "SHA-384")]
+    Sha384,
+    #[serde(rename = "SHA-512")]
+    Sha512,
+}
+
+impl SrpHash {
+    pub fn algorithm(&self) -> &'static Algorithm {
+        match self {
+            SrpHash::Sha1 => &SHA1_FOR_LEGACY_USE_ONLY,
+            SrpHash::Sha256 => &SHA256,
+            SrpHash::Sha384 => &SHA384,
+            SrpHash::Sha512 => &SHA512,
+        }
+    }
+}
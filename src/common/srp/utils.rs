@@ -1,41 +1,94 @@
 use num_bigint::BigUint;
-use ring::digest::Context;
+use ring::{digest::Context, hmac};
 
-use crate::common::srp::groups::SrpGroup;
+use crate::common::srp::{groups::SrpGroup, types::SrpProofMode};
 
-// u = H(PAD(A) | PAD(B))
-pub fn compute_u(params: &SrpGroup, a_pub: &[u8], b_pub: &[u8]) -> BigUint {
+// PAD(x): left pad with zeros to the byte length of N
+pub fn pad(params: &SrpGroup, bytes: &[u8]) -> Vec<u8> {
+    let n_len = (params.n.bits() as usize).div_ceil(8);
+    if bytes.len() >= n_len {
+        return bytes.to_vec();
+    }
+    let mut buf = vec![0u8; n_len];
+    buf[n_len - bytes.len()..].copy_from_slice(bytes);
+    buf
+}
+
+// legacy:  u = H(A | B)
+// rfc5054: u = H(PAD(A) | PAD(B))
+pub fn compute_u(params: &SrpGroup, mode: SrpProofMode, a_pub: &[u8], b_pub: &[u8]) -> BigUint {
     let mut u = Context::new(params.alg);
-    u.update(a_pub);
-    u.update(b_pub);
+    match mode {
+        SrpProofMode::Legacy => {
+            u.update(a_pub);
+            u.update(b_pub);
+        }
+        SrpProofMode::Rfc5054 => {
+            u.update(&pad(params, a_pub));
+            u.update(&pad(params, b_pub));
+        }
+    }
     BigUint::from_bytes_be(u.finish().as_ref())
 }
 
 // k = H(N | PAD(g))
 pub fn compute_k(params: &SrpGroup) -> BigUint {
-    let n = params.n.to_bytes_be();
-    let g_bytes = params.g.to_bytes_be();
-    let mut buf = vec![0u8; n.len()];
-    let l = n.len() - g_bytes.len();
-    buf[l..].copy_from_slice(&g_bytes);
-
     let mut d = Context::new(params.alg);
-    d.update(&n);
-    d.update(&buf);
+    d.update(&params.n.to_bytes_be());
+    d.update(&pad(params, &params.g.to_bytes_be()));
     BigUint::from_bytes_be(d.finish().as_ref())
 }
 
-// M1 = H(A, B, K) this doesn't follow the spec but apparently no one does for M1
-// M1 should equal =  H(H(N) XOR H(g) | H(U) | s | A | B | K) according to the spec
-pub fn compute_m1(params: &SrpGroup, a_pub: &[u8], b_pub: &[u8], key: &[u8]) -> Vec<u8> {
+// legacy:  K = S
+// rfc5054: K = H(S)
+pub fn compute_key(params: &SrpGroup, mode: SrpProofMode, premaster_secret: &[u8]) -> Vec<u8> {
+    match mode {
+        SrpProofMode::Legacy => premaster_secret.to_vec(),
+        SrpProofMode::Rfc5054 => {
+            let mut d = Context::new(params.alg);
+            d.update(premaster_secret);
+            d.finish().as_ref().to_vec()
+        }
+    }
+}
+
+// legacy:  M1 = H(A | B | K), doesn't follow the spec, kept for existing clients
+// rfc5054: M1 = H(H(N) XOR H(g) | H(I) | s | A | B | K)
+pub fn compute_m1(
+    params: &SrpGroup,
+    mode: SrpProofMode,
+    username: &[u8],
+    salt: &[u8],
+    a_pub: &[u8],
+    b_pub: &[u8],
+    key: &[u8],
+) -> Vec<u8> {
     let mut d = Context::new(params.alg);
+    if mode == SrpProofMode::Rfc5054 {
+        let digest = |bytes: &[u8]| {
+            let mut d = Context::new(params.alg);
+            d.update(bytes);
+            d.finish()
+        };
+        let h_n = digest(&params.n.to_bytes_be());
+        let h_g = digest(&params.g.to_bytes_be());
+        let h_xor = h_n
+            .as_ref()
+            .iter()
+            .zip(h_g.as_ref())
+            .map(|(n, g)| n ^ g)
+            .collect::<Vec<u8>>();
+        d.update(&h_xor);
+        d.update(digest(username).as_ref());
+        d.update(salt);
+    }
     d.update(a_pub);
     d.update(b_pub);
     d.update(key);
     d.finish().as_ref().to_vec()
 }
 
-// M2 = H(A, M1, K)
+// M2 = H(A | M1 | K)
 pub fn compute_m2(params: &SrpGroup, a_pub: &[u8], m1: &[u8], key: &[u8]) -> Vec<u8> {
     let mut d = Context::new(params.alg);
     d.update(a_pub);
@@ -43,3 +96,11 @@ pub fn compute_m2(params: &SrpGroup, a_pub: &[u8], m1: &[u8], key: &[u8]) -> Vec
     d.update(key);
     d.finish().as_ref().to_vec()
 }
+
+// HMAC-SHA256(K, "srp session confirmation" | session), proves both sides hold K for this session
+pub fn session_confirmation(key: &[u8], session: &str) -> Vec<u8> {
+    let mut context = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA256, key));
+    context.update(b"srp session confirmation");
+    context.update(session.as_bytes());
+    context.sign().as_ref().to_vec()
+}
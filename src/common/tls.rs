@@ -0,0 +1,164 @@
+use std::{any::Any, fs, io};
+
+use actix_tls::accept::openssl::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
+use openssl::{
+    error::ErrorStack,
+    pkey::PKey,
+    ssl::{SslAcceptor, SslAcceptorBuilder, SslMethod, SslRef, SslVerifyMode},
+    x509::{store::X509StoreBuilder, X509NameRef, X509Ref, X509VerifyResult, X509},
+};
+
+use super::{
+    config::env_var_default,
+    utils::{encode64url, sha256},
+};
+
+// 客户端在 TLS 握手中出示的证书，连接建立时写入 conn_data
+// https://datatracker.ietf.org/doc/html/rfc8705#section-2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate {
+    pub subject_dn: String,
+    // x5t#S256
+    pub thumbprint: String,
+    // 证书链能否由 TLS_CLIENT_CA_FILE 中的 CA 校验通过
+    pub trusted: bool,
+}
+
+impl PeerCertificate {
+    pub fn from_x509(certificate: &X509Ref, trusted: bool) -> Option<Self> {
+        Some(Self {
+            subject_dn: distinguished_name(certificate.subject_name()),
+            thumbprint: thumbprint(certificate)?,
+            trusted,
+        })
+    }
+
+    pub fn from_ssl(ssl: &SslRef) -> Option<Self> {
+        let certificate = ssl.peer_certificate()?;
+        Self::from_x509(&certificate, ssl.verify_result() == X509VerifyResult::OK)
+    }
+}
+
+// 证书 DER 编码的 SHA-256 摘要
+// https://datatracker.ietf.org/doc/html/rfc8705#section-3.1
+pub fn thumbprint(certificate: &X509Ref) -> Option<String> {
+    Some(encode64url(&sha256(&certificate.to_der().ok()?)))
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        if matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';')
+            || (i == 0 && matches!(c, '#' | ' '))
+            || (i == last && c == ' ')
+        {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// RFC 4514 字符串表示，RDN 顺序与证书中相反
+pub fn distinguished_name(name: &X509NameRef) -> String {
+    let mut rdns = name
+        .entries()
+        .filter_map(|entry| {
+            let key = entry.object().nid().short_name().ok()?;
+            let value = String::from_utf8_lossy(entry.data().as_slice());
+            Some(format!("{key}={}", escape_dn_value(&value)))
+        })
+        .collect::<Vec<_>>();
+    rdns.reverse();
+    rdns.join(",")
+}
+
+fn split_dn(dn: &str) -> Vec<(String, String)> {
+    let mut rdns = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in dn.chars().chain(std::iter::once(',')) {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' => {
+                current.push(c);
+                escaped = true;
+            }
+            ',' => {
+                let rdn = std::mem::take(&mut current);
+                if let Some((key, value)) = rdn.split_once('=') {
+                    rdns.push((key.trim().to_uppercase(), value.trim().to_string()));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    rdns
+}
+
+// 属性名大小写不敏感，逗号两侧的空白忽略
+pub fn same_dn(left: &str, right: &str) -> bool {
+    let left = split_dn(left);
+    !left.is_empty() && left == split_dn(right)
+}
+
+// 客户端证书是可选的，自签名证书同样放行，是否可信留给客户端认证方式判断
+pub fn acceptor(
+    certificate: &[u8],
+    private_key: &[u8],
+    client_ca: Option<&[u8]>,
+) -> Result<SslAcceptorBuilder, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    let mut chain = X509::stack_from_pem(certificate)?.into_iter();
+    if let Some(leaf) = chain.next() {
+        builder.set_certificate(&leaf)?;
+    }
+    for intermediate in chain {
+        builder.add_extra_chain_cert(intermediate)?;
+    }
+    let private_key = PKey::private_key_from_pem(private_key)?;
+    builder.set_private_key(&private_key)?;
+    builder.check_private_key()?;
+    let mut store = X509StoreBuilder::new()?;
+    if let Some(client_ca) = client_ca {
+        for ca in X509::stack_from_pem(client_ca)? {
+            store.add_cert(ca)?;
+        }
+    }
+    builder.set_verify_cert_store(store.build())?;
+    builder.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+    Ok(builder)
+}
+
+// 配置了 TLS_CERT_FILE 与 TLS_KEY_FILE 时启用 TLS
+pub fn acceptor_from_env() -> io::Result<Option<SslAcceptorBuilder>> {
+    let certificate = env_var_default::<String>("TLS_CERT_FILE", String::new());
+    let private_key = env_var_default::<String>("TLS_KEY_FILE", String::new());
+    if certificate.is_empty() || private_key.is_empty() {
+        return Ok(None);
+    }
+    let client_ca = match env_var_default::<String>("TLS_CLIENT_CA_FILE", String::new()) {
+        path if path.is_empty() => None,
+        path => Some(fs::read(path)?),
+    };
+    acceptor(
+        &fs::read(certificate)?,
+        &fs::read(private_key)?,
+        client_ca.as_deref(),
+    )
+    .map(Some)
+    .map_err(io::Error::other)
+}
+
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        if let Some(certificate) = PeerCertificate::from_ssl(stream.ssl()) {
+            data.insert(certificate);
+        }
+    }
+}
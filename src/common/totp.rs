@@ -0,0 +1,71 @@
+//! Time-based one-time passwords from [RFC 6238](https://tools.ietf.org/html/rfc6238)
+//!
+//! Only HMAC-SHA1 is implemented, it is the only algorithm mainstream
+//! authenticator apps honour from the `otpauth://` URI.
+use ring::{constant_time::verify_slices_are_equal, hmac};
+use url::Url;
+
+use super::utils::encode32;
+
+#[derive(Debug, Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    pub digits: u32,
+    pub period: u64,
+}
+
+impl Totp {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self {
+            secret,
+            digits: 6,
+            period: 30,
+        }
+    }
+
+    pub fn counter(&self, timestamp: u64) -> u64 {
+        timestamp / self.period
+    }
+
+    // HOTP(K, C) = Truncate(HMAC-SHA1(K, C)) mod 10^digits
+    pub fn generate(&self, counter: u64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.secret);
+        let tag = hmac::sign(&key, &counter.to_be_bytes());
+        let hash = tag.as_ref();
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Verify the code within `skew` periods around `timestamp`, returns the
+    /// matched counter so that the caller can reject replays.
+    pub fn verify(&self, code: &str, timestamp: u64, skew: u64) -> Option<u64> {
+        let current = self.counter(timestamp);
+        (current.saturating_sub(skew)..=current + skew).find(|&counter| {
+            verify_slices_are_equal(self.generate(counter).as_bytes(), code.as_bytes()).is_ok()
+        })
+    }
+
+    /// Key URI understood by authenticator apps
+    /// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+    pub fn otpauth_uri(&self, issuer: &str, account: &str) -> String {
+        let mut uri = Url::parse("otpauth://totp/").unwrap();
+        uri.set_path(format!("{issuer}:{account}").as_str());
+        uri.query_pairs_mut()
+            .append_pair("secret", &encode32(&self.secret))
+            .append_pair("issuer", issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &self.digits.to_string())
+            .append_pair("period", &self.period.to_string());
+        uri.to_string()
+    }
+}
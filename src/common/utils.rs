@@ -11,9 +11,16 @@ use base64::{
     engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
     Engine,
 };
-use ring::rand::{SecureRandom, SystemRandom};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest::{digest, SHA256},
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use super::errors::{ApiError, Result};
 
-use super::errors::Result;
+const BASE32_CHARSETS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
 
 pub fn gen_id(size: usize) -> String {
     let rng = SystemRandom::new();
@@ -29,6 +36,31 @@ pub fn gen_random(size: usize) -> String {
     encode64(&dest)
 }
 
+// 生成指定位数的数字验证码
+pub fn gen_digits(size: usize) -> String {
+    let rng = SystemRandom::new();
+    let mut code = String::with_capacity(size);
+    let mut dest = [0u8; 1];
+    while code.len() < size {
+        rng.fill(&mut dest).unwrap();
+        // reject the tail of the byte range to keep the digits uniform
+        if dest[0] < 250 {
+            code.push(char::from(b'0' + dest[0] % 10));
+        }
+    }
+    code
+}
+
+pub fn sha256(source: &[u8]) -> Vec<u8> {
+    digest(&SHA256, source).as_ref().to_vec()
+}
+
+pub fn hmac_sha256(key: &[u8], source: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), source)
+        .as_ref()
+        .to_vec()
+}
+
 pub fn encode62(source: &[u8]) -> String {
     let base: usize = BASE62_CHARSETS.len();
     let mut result = String::new();
@@ -57,3 +89,56 @@ pub fn decode64url(source: &str) -> Result<Vec<u8>> {
         .decode(source)
         .context("base64url decode failed".to_string())?)
 }
+
+// RFC 4648 base32 without padding, used by authenticator apps
+pub fn encode32(source: &[u8]) -> String {
+    let mut result = String::with_capacity((source.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in source {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_CHARSETS[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_CHARSETS[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+// AES-256-GCM 加密，输出 nonce | ciphertext | tag 的 base64url
+pub fn seal(key: &[u8], plaintext: &[u8]) -> Result<String> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+        .map(LessSafeKey::new)
+        .map_err(|_| ApiError::Internal(anyhow::anyhow!("illegal seal key")))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).unwrap();
+    let mut in_out = plaintext.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut in_out,
+    )
+    .map_err(|_| ApiError::Internal(anyhow::anyhow!("seal failed")))?;
+    Ok(encode64url(&[nonce.as_slice(), &in_out].concat()))
+}
+
+pub fn unseal(key: &[u8], sealed: &str) -> Result<Vec<u8>> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+        .map(LessSafeKey::new)
+        .map_err(|_| ApiError::Internal(anyhow::anyhow!("illegal seal key")))?;
+    let mut sealed = decode64url(sealed)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(ApiError::Internal(anyhow::anyhow!("sealed data truncated")));
+    }
+    let mut in_out = sealed.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&sealed)
+        .map_err(|_| ApiError::Internal(anyhow::anyhow!("illegal seal nonce")))?;
+    let plaintext = key
+        .open_in_place(nonce, Aad::empty(), &mut in_out)
+        .map_err(|_| ApiError::Internal(anyhow::anyhow!("unseal failed")))?;
+    Ok(plaintext.to_vec())
+}
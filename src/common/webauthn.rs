@@ -0,0 +1,368 @@
+//! Relying party checks from [Web Authentication Level 2](https://www.w3.org/TR/webauthn-2/)
+//!
+//! Only the `none` and `packed` attestation statement formats are accepted,
+//! credential keys may be ES256, EdDSA or RS256.
+use std::io::Cursor;
+
+use ciborium::value::Value;
+use openssl::{hash::MessageDigest, sign::Verifier, x509::X509};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    signature::{self, RsaPublicKeyComponents, UnparsedPublicKey},
+};
+use serde::Deserialize;
+
+use super::utils::sha256;
+
+pub const FLAG_UP: u8 = 0x01;
+pub const FLAG_UV: u8 = 0x04;
+pub const FLAG_BE: u8 = 0x08;
+pub const FLAG_AT: u8 = 0x40;
+
+// https://www.iana.org/assignments/cose/cose.xhtml#algorithms
+pub const COSE_ES256: i64 = -7;
+pub const COSE_EDDSA: i64 = -8;
+pub const COSE_RS256: i64 = -257;
+
+type Result<T> = std::result::Result<T, WebauthnError>;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum WebauthnError {
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+    #[error("{0} mismatched")]
+    Mismatched(&'static str),
+    #[error("unsupported {0}")]
+    UnSupported(String),
+    #[error("bad signature")]
+    BadSignature,
+    #[error("sign count regressed, the authenticator may be cloned")]
+    CounterRegressed,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectedClientData {
+    #[serde(rename = "type")]
+    pub ceremony: String,
+    pub challenge: String,
+    pub origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    pub cross_origin: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttestedCredential {
+    pub aaguid: Vec<u8>,
+    pub credential_id: Vec<u8>,
+    /// COSE_Key encoded credential public key
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    // rpIdHash(32) | flags(1) | signCount(4) | attestedCredentialData | extensions
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 37 {
+            return Err(WebauthnError::Malformed("authenticator data"));
+        }
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+        let attested_credential = if flags & FLAG_AT != 0 {
+            // aaguid(16) | credentialIdLength(2) | credentialId | credentialPublicKey
+            let rest = &data[37..];
+            if rest.len() < 18 {
+                return Err(WebauthnError::Malformed("attested credential data"));
+            }
+            let length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            let rest_key = &rest[18..];
+            if rest_key.len() < length {
+                return Err(WebauthnError::Malformed("credential id"));
+            }
+            // COSE_Key 之后可能还有扩展数据，只截取一个 CBOR 对象的长度
+            let mut cursor = Cursor::new(&rest_key[length..]);
+            ciborium::de::from_reader::<Value, _>(&mut cursor)
+                .map_err(|_| WebauthnError::Malformed("credential public key"))?;
+            Some(AttestedCredential {
+                aaguid: rest[..16].to_vec(),
+                credential_id: rest_key[..length].to_vec(),
+                public_key: rest_key[length..length + cursor.position() as usize].to_vec(),
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            rp_id_hash: data[..32].to_vec(),
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_UP != 0
+    }
+
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_UV != 0
+    }
+
+    pub fn backup_eligible(&self) -> bool {
+        self.flags & FLAG_BE != 0
+    }
+}
+
+fn map_get<'a>(entries: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    entries
+        .iter()
+        .find(|(k, _)| matches!(k, Value::Text(text) if text == key))
+        .map(|(_, v)| v)
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => i64::try_from(i128::from(*i)).ok(),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoseKey {
+    pub alg: i64,
+    params: Vec<(Value, Value)>,
+}
+
+impl CoseKey {
+    pub fn parse(source: &[u8]) -> Result<Self> {
+        let params = match ciborium::de::from_reader::<Value, _>(source) {
+            Ok(Value::Map(params)) => params,
+            _ => return Err(WebauthnError::Malformed("cose key")),
+        };
+        let alg = params
+            .iter()
+            .find(|(k, _)| as_i64(k) == Some(3))
+            .and_then(|(_, v)| as_i64(v))
+            .ok_or(WebauthnError::Malformed("cose key alg"))?;
+        Ok(Self { alg, params })
+    }
+
+    fn bytes(&self, label: i64) -> Result<&[u8]> {
+        self.params
+            .iter()
+            .find(|(k, _)| as_i64(k) == Some(label))
+            .and_then(|(_, v)| match v {
+                Value::Bytes(bytes) => Some(bytes.as_slice()),
+                _ => None,
+            })
+            .ok_or(WebauthnError::Malformed("cose key parameter"))
+    }
+
+    pub fn verify(&self, message: &[u8], sig: &[u8]) -> Result<()> {
+        match self.alg {
+            COSE_ES256 => {
+                let point = [&[0x04u8][..], self.bytes(-2)?, self.bytes(-3)?].concat();
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                    .verify(message, sig)
+            }
+            COSE_EDDSA => {
+                UnparsedPublicKey::new(&signature::ED25519, self.bytes(-2)?).verify(message, sig)
+            }
+            COSE_RS256 => RsaPublicKeyComponents {
+                n: self.bytes(-1)?,
+                e: self.bytes(-2)?,
+            }
+            .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig),
+            alg => return Err(WebauthnError::UnSupported(format!("cose alg({alg})"))),
+        }
+        .map_err(|_| WebauthnError::BadSignature)
+    }
+}
+
+// 计数器均为 0 表示认证器不支持计数，否则必须严格递增
+pub fn verify_sign_count(stored: u32, received: u32) -> Result<()> {
+    if (stored != 0 || received != 0) && received <= stored {
+        Err(WebauthnError::CounterRegressed)
+    } else {
+        Ok(())
+    }
+}
+
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn new(id: &str, origin: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            origin: origin.to_string(),
+        }
+    }
+
+    fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: &str,
+        challenge: &str,
+    ) -> Result<Vec<u8>> {
+        let client_data = serde_json::from_slice::<CollectedClientData>(client_data_json)
+            .map_err(|_| WebauthnError::Malformed("client data"))?;
+        if client_data.ceremony != ceremony {
+            return Err(WebauthnError::Mismatched("ceremony type"));
+        }
+        verify_slices_are_equal(client_data.challenge.as_bytes(), challenge.as_bytes())
+            .map_err(|_| WebauthnError::Mismatched("challenge"))?;
+        if client_data.origin != self.origin || client_data.cross_origin {
+            return Err(WebauthnError::Mismatched("origin"));
+        }
+        Ok(sha256(client_data_json))
+    }
+
+    fn verify_authenticator_data(
+        &self,
+        authenticator_data: &AuthenticatorData,
+        user_verification: bool,
+    ) -> Result<()> {
+        if authenticator_data.rp_id_hash != sha256(self.id.as_bytes()) {
+            return Err(WebauthnError::Mismatched("rp id"));
+        }
+        if !authenticator_data.user_present() {
+            return Err(WebauthnError::Mismatched("user presence"));
+        }
+        if user_verification && !authenticator_data.user_verified() {
+            return Err(WebauthnError::Mismatched("user verification"));
+        }
+        Ok(())
+    }
+
+    /// Registration ceremony, returns the authenticator data carrying the new
+    /// credential.
+    /// https://www.w3.org/TR/webauthn-2/#sctn-registering-a-new-credential
+    pub fn verify_registration(
+        &self,
+        challenge: &str,
+        client_data_json: &[u8],
+        attestation_object: &[u8],
+        user_verification: bool,
+    ) -> Result<AuthenticatorData> {
+        let client_data_hash =
+            self.verify_client_data(client_data_json, "webauthn.create", challenge)?;
+        let attestation = match ciborium::de::from_reader::<Value, _>(attestation_object) {
+            Ok(Value::Map(entries)) => entries,
+            _ => return Err(WebauthnError::Malformed("attestation object")),
+        };
+        let raw_authenticator_data = match map_get(&attestation, "authData") {
+            Some(Value::Bytes(bytes)) => bytes,
+            _ => return Err(WebauthnError::Malformed("attestation authData")),
+        };
+        let authenticator_data = AuthenticatorData::parse(raw_authenticator_data)?;
+        self.verify_authenticator_data(&authenticator_data, user_verification)?;
+        let credential = authenticator_data
+            .attested_credential
+            .as_ref()
+            .ok_or(WebauthnError::Malformed("attested credential data"))?;
+        let credential_key = CoseKey::parse(&credential.public_key)?;
+
+        match map_get(&attestation, "fmt") {
+            Some(Value::Text(fmt)) if fmt == "none" => {}
+            Some(Value::Text(fmt)) if fmt == "packed" => {
+                let statement = match map_get(&attestation, "attStmt") {
+                    Some(Value::Map(statement)) => statement,
+                    _ => return Err(WebauthnError::Malformed("attestation statement")),
+                };
+                verify_packed(
+                    statement,
+                    &[raw_authenticator_data.as_slice(), &client_data_hash].concat(),
+                    &credential_key,
+                )?;
+            }
+            Some(Value::Text(fmt)) => {
+                return Err(WebauthnError::UnSupported(format!(
+                    "attestation format({fmt})"
+                )))
+            }
+            _ => return Err(WebauthnError::Malformed("attestation fmt")),
+        }
+        Ok(authenticator_data)
+    }
+
+    /// Authentication ceremony against a stored credential public key.
+    /// https://www.w3.org/TR/webauthn-2/#sctn-verifying-assertion
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_assertion(
+        &self,
+        challenge: &str,
+        public_key: &[u8],
+        stored_sign_count: u32,
+        client_data_json: &[u8],
+        raw_authenticator_data: &[u8],
+        sig: &[u8],
+        user_verification: bool,
+    ) -> Result<AuthenticatorData> {
+        let client_data_hash =
+            self.verify_client_data(client_data_json, "webauthn.get", challenge)?;
+        let authenticator_data = AuthenticatorData::parse(raw_authenticator_data)?;
+        self.verify_authenticator_data(&authenticator_data, user_verification)?;
+        CoseKey::parse(public_key)?
+            .verify(&[raw_authenticator_data, &client_data_hash].concat(), sig)?;
+        verify_sign_count(stored_sign_count, authenticator_data.sign_count)?;
+        Ok(authenticator_data)
+    }
+}
+
+// https://www.w3.org/TR/webauthn-2/#sctn-packed-attestation
+fn verify_packed(
+    statement: &[(Value, Value)],
+    signed: &[u8],
+    credential_key: &CoseKey,
+) -> Result<()> {
+    let alg = map_get(statement, "alg")
+        .and_then(as_i64)
+        .ok_or(WebauthnError::Malformed("packed alg"))?;
+    let sig = match map_get(statement, "sig") {
+        Some(Value::Bytes(sig)) => sig,
+        _ => return Err(WebauthnError::Malformed("packed sig")),
+    };
+    match map_get(statement, "x5c") {
+        Some(Value::Array(certificates)) => {
+            let leaf = match certificates.first() {
+                Some(Value::Bytes(der)) => {
+                    X509::from_der(der).map_err(|_| WebauthnError::Malformed("x5c"))?
+                }
+                _ => return Err(WebauthnError::Malformed("x5c")),
+            };
+            let digest = match alg {
+                COSE_ES256 | COSE_RS256 => MessageDigest::sha256(),
+                alg => return Err(WebauthnError::UnSupported(format!("packed alg({alg})"))),
+            };
+            let key = leaf
+                .public_key()
+                .map_err(|_| WebauthnError::Malformed("x5c public key"))?;
+            let verified = Verifier::new(digest, &key)
+                .and_then(|mut verifier| {
+                    verifier.update(signed)?;
+                    verifier.verify(sig)
+                })
+                .unwrap_or_default();
+            if !verified {
+                return Err(WebauthnError::BadSignature);
+            }
+            Ok(())
+        }
+        // self attestation 使用凭证私钥签名
+        None => {
+            if alg != credential_key.alg {
+                return Err(WebauthnError::Mismatched("packed alg"));
+            }
+            credential_key.verify(signed, sig)
+        }
+        _ => Err(WebauthnError::Malformed("x5c")),
+    }
+}
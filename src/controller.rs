@@ -1,4 +1,8 @@
+pub mod admin_controller;
 pub mod authenticate_controller;
 pub mod authorize_controller;
 pub mod challenge_controller;
 pub mod idp_controller;
+pub mod mfa_controller;
+pub mod token_controller;
+pub mod webauthn_controller;
@@ -0,0 +1,50 @@
+use actix_web::{
+    delete, error::ErrorUnauthorized, http::header, post, web::Path, HttpRequest, HttpResponse,
+    Responder,
+};
+use ring::constant_time::verify_slices_are_equal;
+
+use crate::{
+    common::{
+        config::env_var_default,
+        errors::{ApiError, Result},
+    },
+    service::{client_service, throttle_service},
+};
+
+// 管理接口使用 ADMIN_API_TOKEN 作为 bearer token，未配置时全部拒绝
+fn authorize_admin(req: &HttpRequest) -> Result<()> {
+    let expected = env_var_default::<String>("ADMIN_API_TOKEN", String::new());
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if expected.is_empty()
+        || verify_slices_are_equal(expected.as_bytes(), token.as_bytes()).is_err()
+    {
+        return Err(ApiError::Response(ErrorUnauthorized("unauthorized")));
+    }
+    Ok(())
+}
+
+#[delete("/admin/lockout/{identifier}")]
+pub async fn unlock(req: HttpRequest, identifier: Path<String>) -> Result<impl Responder> {
+    authorize_admin(&req)?;
+    throttle_service::unlock(&identifier).await?;
+    tracing::info!("account unlocked by admin: {}", identifier);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// 签发新的 client secret，旧 secret 在宽限期内仍然有效
+#[post("/admin/clients/{client_id}/secrets")]
+pub async fn provision_secret(req: HttpRequest, client_id: Path<String>) -> Result<impl Responder> {
+    authorize_admin(&req)?;
+    let client_secret =
+        client_service::rotate_secret(&client_service::RemoteSecretStore, &client_id).await?;
+    tracing::info!("client secret rotated by admin: {}", client_id);
+    Ok(HttpResponse::Created()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(serde_json::json!({ "client_secret": client_secret })))
+}
@@ -1,33 +1,78 @@
 use actix_web::{
     get, post,
     web::{Form, Json, Query},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
-use http::{header, StatusCode};
 
 use crate::{
     common::errors::Result,
-    dto::password::{PreSrpRequest, PreSrpRespose, SrpPassword, SrpRequest},
-    service::user_service,
+    dto::{
+        auth::{self, persist_flow},
+        password::{
+            PasswordResetConfirm, PasswordResetRequest, PreSrpRequest, SrpChangeRequest,
+            SrpPassword, SrpRequest,
+        },
+        throttle::{Attempt, SRP_SCOPE},
+    },
+    service::{throttle_service, user_service},
 };
 
 #[post("/registry")]
-pub async fn registry(Json(form): Json<SrpPassword>) -> Result<impl Responder> {
-    user_service::create_srp(&form).await?;
+pub async fn registry(req: HttpRequest, Json(form): Json<SrpPassword>) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    user_service::create_srp(&flow, &form).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+// 先以旧口令调用 GET /login 取得握手，再提交 M1 和新的 verifier
+#[post("/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    Json(form): Json<SrpChangeRequest>,
+) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    let attempt = Attempt::new(&req, &flow, SRP_SCOPE, &form.password.identifier);
+    throttle_service::guard(&attempt, user_service::change_srp(&flow, &form)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
 // commit identifier and A
 #[get("/login")]
-pub async fn pre_login(Query(query): Query<PreSrpRequest>) -> Result<impl Responder> {
-    let (salt, b_pub) = user_service::pre_srp_login(&query.identifier, &query.a_pub).await?;
-    Ok(Json(PreSrpRespose { salt, b_pub }))
+pub async fn pre_login(
+    req: HttpRequest,
+    Query(query): Query<PreSrpRequest>,
+) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    throttle_service::check(&Attempt::new(&req, &flow, SRP_SCOPE, &query.identifier)).await?;
+    user_service::pre_srp_login(&flow, &query.identifier, &query.a_pub)
+        .await
+        .map(Json)
 }
 
 #[post("/login")]
-pub async fn form_login(Form(form): Form<SrpRequest>) -> Result<impl Responder> {
-    user_service::srp_login(&form.identity, &form.proof).await?;
-    Ok(HttpResponse::build(StatusCode::MOVED_PERMANENTLY)
-        .append_header((header::LOCATION, "http://forum.heliannuuthus.com"))
-        .finish())
+pub async fn form_login(req: HttpRequest, Form(form): Form<SrpRequest>) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    let attempt = Attempt::new(&req, &flow, SRP_SCOPE, &form.identity);
+    let resp = throttle_service::guard(
+        &attempt,
+        user_service::srp_login(&mut flow, &form.handshake, &form.identity, &form.proof),
+    )
+    .await?;
+    persist_flow(&flow).await?;
+    Ok(Json(resp))
+}
+
+// 统一返回 204，不暴露用户是否存在
+#[post("/password/reset")]
+pub async fn password_reset(Json(form): Json<PasswordResetRequest>) -> Result<impl Responder> {
+    user_service::request_reset(&form.identifier);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/password/reset/confirm")]
+pub async fn password_reset_confirm(
+    Json(form): Json<PasswordResetConfirm>,
+) -> Result<impl Responder> {
+    user_service::confirm_reset(&form).await?;
+    Ok(HttpResponse::NoContent().finish())
 }
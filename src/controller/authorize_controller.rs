@@ -1,60 +1,89 @@
 use actix_web::{
-    cookie::CookieBuilder,
-    error::ErrorUnauthorized,
-    get,
-    http::header,
-    post,
-    web::{self, Form, Query},
-    HttpResponse, Responder,
+    get, post,
+    web::{self, Json},
+    HttpRequest, Responder,
 };
-use chrono::Duration;
-use http::StatusCode;
-use validator::Validate;
+use tracing::error;
 
 use crate::{
-    common::{
-        cache::{moka, redis::redis_setex},
-        errors::{ApiError, Result},
-        utils::gen_id,
+    common::{cache::moka, errors::Result},
+    dto::auth::{
+        self, form_params, persist_flow, require_form, AuthError, AuthRequest, AuthorizationError,
+        ConsentRequest, Flow,
     },
-    dto::auth::{AuthRequest, AuthorizationCode, Flow},
+    service::{auth_service, token_service},
 };
+
 #[get("/authorize")]
-pub async fn query_authorize(Query(params): web::Query<AuthRequest>) -> Result<impl Responder> {
-    authorize(&params).await
+pub async fn query_authorize(req: HttpRequest) -> Result<impl Responder> {
+    authorize(&resolve(req.query_string().as_bytes()).await?).await
 }
 
 #[post("/authorize")]
-pub async fn form_authorize(Form(form): web::Form<AuthRequest>) -> Result<impl Responder> {
-    authorize(&form).await
+pub async fn form_authorize(req: HttpRequest, body: web::Bytes) -> Result<impl Responder> {
+    require_form(&req)?;
+    authorize(&resolve(&body).await?).await
+}
+
+// 携带 request_uri 时使用事先推送的授权请求
+// https://datatracker.ietf.org/doc/html/rfc9126#section-4
+async fn resolve(source: &[u8]) -> Result<AuthRequest> {
+    let params = form_params(source)?;
+    match params.get("request_uri") {
+        Some(request_uri) => {
+            let client_id = params.get("client_id").ok_or_else(|| {
+                AuthorizationError::local(AuthError::InvalidRequest, "client_id is lacked")
+            })?;
+            auth_service::pushed_request(client_id, request_uri).await
+        }
+        None => AuthRequest::from_params(params),
+    }
 }
 
 async fn authorize(params: &AuthRequest) -> Result<impl Responder> {
-    params.validate()?;
     let mut flow = Flow::new(params.clone());
 
-    match moka::get_idp_config(&flow.request.client_id).await? {
+    match moka::get_client_config(&flow.request.client_id).await? {
         Some(client) => {
+            flow.with_client(client);
+        }
+        None => {
+            return Err(
+                AuthorizationError::local(AuthError::InvalidClient, "unknown client").into(),
+            )
+        }
+    };
+    flow.verify_redirect_uri()?;
+    // redirect_uri 已确认，之后的错误回跳到客户端
+    match moka::get_idp_config(&flow.request.client_id).await {
+        Ok(Some(client)) => {
             flow.client_idp_configs = Some(client);
         }
-        None => return Err(ApiError::Response(ErrorUnauthorized("invalid_client"))),
+        Ok(None) => {
+            return Err(flow.fail(
+                AuthError::UnauthorizedClient,
+                "no identity provider configured",
+            ))
+        }
+        Err(e) => {
+            error!("load idp config failed: {e}");
+            return Err(flow.fail(AuthError::ServerError, "load client configuration failed"));
+        }
     };
     // flow 校验
     flow.validate()?;
-
-    Ok(HttpResponse::build(StatusCode::MOVED_PERMANENTLY)
-        .append_header((header::LOCATION, flow.request.redirect_uri))
-        .cookie(CookieBuilder::new("heliannuuthus", flow.id).finish())
-        .finish())
+    persist_flow(&flow).await?;
+    flow.dispatch()
 }
 
-async fn authorization_code(flow: &Flow) -> Result<AuthorizationCode> {
-    let authorization_code = AuthorizationCode::new(gen_id(16), flow.request.state.clone());
-    redis_setex(
-        format!("forum:auth:code:{}", &flow.id).as_str(),
-        authorization_code.code.to_string(),
-        Duration::minutes(10),
-    )
-    .await?;
-    Ok(authorization_code)
+// 用户确认授权后回跳客户端，拒绝时以 access_denied 结束流程
+#[post("/confirm")]
+pub async fn consent(req: HttpRequest, Json(form): Json<ConsentRequest>) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    if !form.approved {
+        return Err(flow.fail(AuthError::AccessDenied, "user denied the request"));
+    }
+    token_service::grant(&mut flow).await?;
+    persist_flow(&flow).await?;
+    flow.dispatch()
 }
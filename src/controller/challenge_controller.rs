@@ -1,16 +1,71 @@
-use actix_web::{error::ErrorBadRequest, get, post, web::Json, Responder};
+use std::time::Duration;
+
+use actix_web::{
+    error::ErrorBadRequest,
+    get,
+    http::header::{self, ContentType},
+    post,
+    web::{Bytes, Form, Json, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::Context;
+use futures_util::stream;
 
 use crate::{
     common::{
         cache::moka,
+        constant::ChallengeType as ChallengeConfigType,
         errors::{ApiError, Result},
+        proxy::client_ip,
     },
-    dto::auth::ChallengeRequest,
+    dto::{
+        auth::{self, load_flow, persist_flow, ChallengeRequest, ChallengeType},
+        challenge::LinkRequest,
+        throttle::{Attempt, CHALLENGE_SCOPE},
+    },
+    service::{challenge_service, throttle_service},
 };
 
+const LINK_CONFIRM_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head><meta charset="utf-8"><meta name="referrer" content="no-referrer"><title>Sign in</title></head>
+  <body>
+    <form method="post" action="">
+      <p>Continue signing in as {{ identifier }}?</p>
+      <p>This sign-in was requested by {{ client_name }} from {{ ip }} at {{ requested_at }}.</p>
+      <p>If you did not start it on one of your devices, close this page.</p>
+      <input type="hidden" name="token" value="{{ token }}">
+      <button type="submit">Sign in</button>
+    </form>
+  </body>
+</html>"#;
+
+const LINK_DONE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head><meta charset="utf-8"><title>Signed in</title></head>
+  <body><p>You are signed in. Return to the window where you started to continue.</p></body>
+</html>"#;
+
+fn render_page(template: &str, context: &tera::Context) -> Result<HttpResponse> {
+    let page = tera::Tera::one_off(template, context, true).context("render page failed")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .body(page))
+}
+
 #[get("/challenge")]
-pub async fn code_challenge(Json(cq): Json<ChallengeRequest>) -> Result<impl Responder> {
-    let _config = match moka::get_challenge_config(&cq.client_id).await? {
+pub async fn code_challenge(
+    req: HttpRequest,
+    Query(cq): Query<ChallengeRequest>,
+) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    if flow.request.client_id != cq.client_id {
+        return Err(ApiError::Response(ErrorBadRequest("invalid_client")));
+    }
+    throttle_service::check(&Attempt::new(&req, &flow, CHALLENGE_SCOPE, &cq.identifier)).await?;
+    let config = match moka::get_challenge_config(&cq.client_id).await? {
         Some(config) => config,
         None => {
             return Err(ApiError::Response(ErrorBadRequest(
@@ -18,10 +73,115 @@ pub async fn code_challenge(Json(cq): Json<ChallengeRequest>) -> Result<impl Res
             )))
         }
     };
-    Ok("".to_string())
+    match cq.challenge_type {
+        ChallengeType::EmailCode if config.challenge_type == ChallengeConfigType::Code => {
+            challenge_service::code_challenge(&flow, &cq.identifier).await?
+        }
+        ChallengeType::EmailLink if config.challenge_type == ChallengeConfigType::Link => {
+            let ip = client_ip(req.peer_addr(), req.headers());
+            challenge_service::link_challenge(&flow, &cq.identifier, &ip).await?
+        }
+        _ => {
+            return Err(ApiError::Response(ErrorBadRequest(
+                "unsupported challenge type",
+            )))
+        }
+    };
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[post("/challenge")]
-pub async fn challenge_continous() -> Result<impl Responder> {
-    Ok("".to_string())
+pub async fn challenge_continous(
+    req: HttpRequest,
+    Json(cq): Json<ChallengeRequest>,
+) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    if flow.request.client_id != cq.client_id {
+        return Err(ApiError::Response(ErrorBadRequest("invalid_client")));
+    }
+    let proof = cq
+        .proof
+        .as_ref()
+        .ok_or(ApiError::Response(ErrorBadRequest("proof is lacked")))?;
+    match cq.challenge_type {
+        ChallengeType::EmailCode => {
+            let attempt = Attempt::new(&req, &flow, CHALLENGE_SCOPE, &cq.identifier);
+            throttle_service::guard(
+                &attempt,
+                challenge_service::verify_code(&mut flow, &cq.identifier, proof),
+            )
+            .await?
+        }
+        ChallengeType::EmailLink => {
+            return Err(ApiError::Response(ErrorBadRequest(
+                "unsupported challenge type",
+            )))
+        }
+    };
+    persist_flow(&flow).await?;
+    flow.dispatch()
+}
+
+// 打开链接只展示确认页面，需要用户提交后才会消费链接
+#[get("/challenge/link")]
+pub async fn link_confirm(Query(link): Query<LinkRequest>) -> Result<impl Responder> {
+    let challenge = challenge_service::inspect_link(&link.token).await?;
+    let mut context = tera::Context::new();
+    context.insert("identifier", &challenge.identifier);
+    context.insert("client_name", &challenge.client_name);
+    context.insert("ip", &challenge.ip);
+    context.insert(
+        "requested_at",
+        &challenge
+            .requested_at
+            .format("%Y-%m-%d %H:%M:%S UTC")
+            .to_string(),
+    );
+    context.insert("token", &link.token);
+    render_page(LINK_CONFIRM_PAGE, &context)
+}
+
+#[post("/challenge/link")]
+pub async fn link_continous(Form(link): Form<LinkRequest>) -> Result<impl Responder> {
+    challenge_service::consume_link(&link.token).await?;
+    render_page(LINK_DONE_PAGE, &tera::Context::new())
+}
+
+// 发起设备轮询链接是否已在其他设备上确认
+#[get("/challenge/link/status")]
+pub async fn link_status(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    Ok(Json(challenge_service::link_status(&flow)))
+}
+
+// 发起设备通过 server-sent events 等待链接确认
+#[get("/challenge/link/events")]
+pub async fn link_events(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    let events = stream::unfold(Some(flow.id), |flow_id| async move {
+        let flow_id = flow_id?;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let (event, next) = match load_flow(&flow_id).await {
+            Ok(flow) => {
+                let status = challenge_service::link_status(&flow);
+                if status.authenticated {
+                    (
+                        format!(
+                            "event: authenticated\ndata: {}\n\n",
+                            serde_json::to_string(&status).unwrap_or_default()
+                        ),
+                        None,
+                    )
+                } else {
+                    (": pending\n\n".to_string(), Some(flow_id))
+                }
+            }
+            Err(_) => ("event: expired\ndata: {}\n\n".to_string(), None),
+        };
+        Some((Ok::<_, actix_web::Error>(Bytes::from(event)), next))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
 }
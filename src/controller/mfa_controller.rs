@@ -0,0 +1,76 @@
+use actix_web::{get, post, web::Json, HttpRequest, Responder};
+
+use crate::{
+    common::errors::Result,
+    dto::{
+        auth::{self, persist_flow, Flow},
+        mfa::{RecoveryCodeRequest, TotpRequest},
+        throttle::{Attempt, MFA_SCOPE},
+    },
+    service::{mfa_service, throttle_service},
+};
+
+// 第二因子按用户 openid 计算失败次数
+fn attempt(req: &HttpRequest, flow: &Flow) -> Result<Attempt> {
+    let openid = &mfa_service::subject(flow)?.openid;
+    Ok(Attempt::new(req, flow, MFA_SCOPE, openid))
+}
+
+#[post("/mfa/totp/enrollment")]
+pub async fn totp_enrollment(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    mfa_service::enroll_totp(&flow).await.map(Json)
+}
+
+#[post("/mfa/totp/enrollment/confirm")]
+pub async fn totp_confirm(
+    req: HttpRequest,
+    Json(form): Json<TotpRequest>,
+) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    let attempt = attempt(&req, &flow)?;
+    throttle_service::guard(&attempt, mfa_service::confirm_totp(&flow, &form.code)).await?;
+    flow.dispatch()
+}
+
+#[post("/mfa/totp")]
+pub async fn totp_verify(
+    req: HttpRequest,
+    Json(form): Json<TotpRequest>,
+) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    let attempt = attempt(&req, &flow)?;
+    throttle_service::guard(&attempt, mfa_service::verify_totp(&mut flow, &form.code)).await?;
+    persist_flow(&flow).await?;
+    flow.dispatch()
+}
+
+#[get("/mfa/recovery")]
+pub async fn recovery_codes(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    mfa_service::issued_recovery_codes(&flow).await.map(Json)
+}
+
+#[post("/mfa/recovery/regeneration")]
+pub async fn recovery_regenerate(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    mfa_service::regenerate_recovery_codes(&flow)
+        .await
+        .map(Json)
+}
+
+#[post("/mfa/recovery")]
+pub async fn recovery_verify(
+    req: HttpRequest,
+    Json(form): Json<RecoveryCodeRequest>,
+) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    let attempt = attempt(&req, &flow)?;
+    throttle_service::guard(
+        &attempt,
+        mfa_service::verify_recovery_code(&mut flow, &form.code),
+    )
+    .await?;
+    persist_flow(&flow).await?;
+    flow.dispatch()
+}
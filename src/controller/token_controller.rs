@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    common::{
+        constant::{DPOP_NONCE_HEADER, TOKEN_EXCHANGE_GRANT_TYPE},
+        errors::Result,
+        tls::PeerCertificate,
+    },
+    dto::auth::{form_params, require_form, AuthError, AuthorizationError},
+    service::{auth_service, client_service, dpop_service, token_service},
+};
+
+fn form(req: &HttpRequest, body: &[u8]) -> Result<HashMap<String, String>> {
+    require_form(req)?;
+    form_params(body)
+}
+
+fn required<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a String> {
+    params.get(name).ok_or_else(|| {
+        AuthorizationError::local(AuthError::InvalidRequest, format!("{name} is lacked")).into()
+    })
+}
+
+#[post("/token")]
+pub async fn token(req: HttpRequest, body: web::Bytes) -> Result<impl Responder> {
+    let params = form(&req, &body)?;
+    let client = client_service::authenticate(&req, &params).await?;
+    let dpop = dpop_service::verify(&req, &client).await?;
+    let cnf =
+        token_service::confirmation(&client, req.conn_data::<PeerCertificate>(), dpop.as_ref())?;
+    let tokens = match required(&params, "grant_type")?.as_str() {
+        "authorization_code" => token_service::exchange_code(&client, &params, cnf).await?,
+        "refresh_token" => token_service::refresh(&client, &params, cnf).await?,
+        TOKEN_EXCHANGE_GRANT_TYPE => token_service::exchange_token(&client, &params, cnf).await?,
+        grant_type => {
+            return Err(AuthorizationError::local(
+                AuthError::UnsupportedGrantType,
+                format!("unsupported grant_type {grant_type}"),
+            )
+            .into())
+        }
+    };
+    let mut resp = HttpResponse::Ok();
+    if let Some(nonce) = dpop_service::issue_nonce().await? {
+        resp.insert_header((DPOP_NONCE_HEADER, nonce));
+    }
+    Ok(resp
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(tokens))
+}
+
+#[post("/introspect")]
+pub async fn introspect(req: HttpRequest, body: web::Bytes) -> Result<impl Responder> {
+    let params = form(&req, &body)?;
+    let client = client_service::authenticate(&req, &params).await?;
+    let introspection = token_service::introspect(&client, required(&params, "token")?).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(introspection))
+}
+
+#[post("/revoke")]
+pub async fn revoke(req: HttpRequest, body: web::Bytes) -> Result<impl Responder> {
+    let params = form(&req, &body)?;
+    let client = client_service::authenticate(&req, &params).await?;
+    token_service::revoke(&client, required(&params, "token")?).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// https://datatracker.ietf.org/doc/html/rfc9126#section-2.2
+#[post("/par")]
+pub async fn pushed_authorize(req: HttpRequest, body: web::Bytes) -> Result<impl Responder> {
+    let mut params = form(&req, &body)?;
+    let client = client_service::authenticate(&req, &params).await?;
+    for name in ["client_secret", "client_assertion", "client_assertion_type"] {
+        params.remove(name);
+    }
+    let (request_uri, expires_in) = auth_service::push_request(&client, params).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(serde_json::json!({ "request_uri": request_uri, "expires_in": expires_in })))
+}
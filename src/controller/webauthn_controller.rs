@@ -0,0 +1,45 @@
+use actix_web::{post, web::Json, HttpRequest, Responder};
+
+use crate::{
+    common::errors::Result,
+    dto::{
+        auth::{self, persist_flow},
+        webauthn::{AssertionRequest, RegistrationRequest},
+    },
+    service::webauthn_service,
+};
+
+#[post("/webauthn/registration/options")]
+pub async fn registration_options(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    webauthn_service::registration_options(&flow)
+        .await
+        .map(Json)
+}
+
+#[post("/webauthn/registration")]
+pub async fn registration(
+    req: HttpRequest,
+    Json(form): Json<RegistrationRequest>,
+) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    webauthn_service::register(&flow, &form).await?;
+    flow.dispatch()
+}
+
+#[post("/webauthn/assertion/options")]
+pub async fn assertion_options(req: HttpRequest) -> Result<impl Responder> {
+    let flow = auth::validate_flow(&req).await?;
+    webauthn_service::assertion_options(&flow).await.map(Json)
+}
+
+#[post("/webauthn/assertion")]
+pub async fn assertion(
+    req: HttpRequest,
+    Json(form): Json<AssertionRequest>,
+) -> Result<impl Responder> {
+    let mut flow = auth::validate_flow(&req).await?;
+    webauthn_service::authenticate(&mut flow, &form).await?;
+    persist_flow(&flow).await?;
+    flow.dispatch()
+}
@@ -1,6 +1,9 @@
 pub mod auth;
 pub mod challenge;
 pub mod client;
+pub mod mfa;
 pub mod password;
 pub mod sms;
+pub mod throttle;
 pub mod user;
+pub mod webauthn;
@@ -1,59 +1,449 @@
-use std::time::Duration;
+use std::{collections::HashMap, marker::PhantomData, time::Duration};
 
 use actix_web::{
     cookie::Cookie,
-    error::{ErrorPreconditionFailed, ErrorUnauthorized},
-    HttpResponse,
+    error::ErrorPreconditionFailed,
+    http::{header, StatusCode},
+    mime, HttpMessage, HttpResponse, ResponseError,
 };
 use chrono::{DateTime, Utc};
 use http::Uri;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use tracing::error;
+use url::Url;
 use validator::Validate;
 
 use super::client::{ClientConfig, ClientIdpConfigs};
 use crate::{
     common::{
         cache::redis::{redis_get, redis_setex},
+        config::env_var_default,
         constant::{
-            AuthRequestType, PromptType, ResponseType, TokenType, CONFLICT_RESPONSE_TYPE,
+            AuthRequestType, PromptType, ResponseMode, ResponseType, TokenType, DPOP_NONCE_HEADER,
             OPENID_SCOPE,
         },
         errors::{ApiError, Result},
-        jwt::{AccessToken, IdToken},
+        jwt::{
+            active_key, generate_jwe, generate_jws, generate_typed_jws, half_hash, issuer,
+            validation, verify_id_token, AccessToken, Confirmation, IdToken, ACCESS_TOKEN_JWT_TYPE,
+        },
         utils::gen_id,
     },
     dto::user::{UserAssociation, UserProfile},
 };
 
-#[derive(Debug, Clone, thiserror::Error, serde::Deserialize, serde::Serialize)]
+// https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.2.1
+// https://openid.net/specs/openid-connect-core-1_0.html#AuthError
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum AuthError {
+    #[error("invalid_request")]
+    InvalidRequest,
     #[error("invalid_client")]
     InvalidClient,
+    #[error("invalid_grant")]
+    InvalidGrant,
+    #[error("unauthorized_client")]
+    UnauthorizedClient,
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+    #[error("access_denied")]
+    AccessDenied,
+    #[error("unsupported_response_type")]
+    UnsupportedResponseType,
+    #[error("invalid_scope")]
+    InvalidScope,
+    #[error("server_error")]
+    ServerError,
+    #[error("temporarily_unavailable")]
+    TemporarilyUnavailable,
+    #[error("interaction_required")]
+    InteractionRequired,
     #[error("login_required")]
     LoginRequired,
     #[error("account_selection_required")]
     AccountSelectionRequired,
     #[error("consent_required")]
     ConsentRequired,
+    #[error("invalid_request_uri")]
+    InvalidRequestUri,
+    #[error("invalid_request_object")]
+    InvalidRequestObject,
+    #[error("request_not_supported")]
+    RequestNotSupported,
+    #[error("request_uri_not_supported")]
+    RequestUriNotSupported,
+    #[error("registration_not_supported")]
+    RegistrationNotSupported,
+    // https://datatracker.ietf.org/doc/html/rfc9449#section-5
+    #[error("invalid_dpop_proof")]
+    InvalidDpopProof,
+    // https://datatracker.ietf.org/doc/html/rfc9449#section-8
+    #[error("use_dpop_nonce")]
+    UseDpopNonce,
+    // https://datatracker.ietf.org/doc/html/rfc8693#section-2.2.2
+    #[error("invalid_target")]
+    InvalidTarget,
+}
+
+impl AuthError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidClient => StatusCode::UNAUTHORIZED,
+            AuthError::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::TemporarilyUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+// 授权端点的结果（授权码或错误）统一按 response_mode 写回客户端
+// https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#ResponseModes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationResponse {
+    pub redirect_uri: String,
+    pub response_mode: ResponseMode,
+    pub params: Vec<(String, String)>,
+    pub state: Option<String>,
+    pub iss: String,
+    pub aud: String,
+    pub encryption_key: Option<String>,
+}
+
+impl AuthorizationResponse {
+    pub fn new(request: &AuthRequest) -> Self {
+        Self {
+            redirect_uri: request.redirect_uri.clone(),
+            response_mode: request.response_mode(),
+            params: Vec::new(),
+            state: request.state.clone(),
+            iss: issuer(&request.client_id),
+            aud: request.client_id.clone(),
+            encryption_key: None,
+        }
+    }
+
+    pub fn encrypt_with(mut self, encryption_key: Option<String>) -> Self {
+        self.encryption_key = encryption_key;
+        self
+    }
+
+    pub fn param(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.params.push((name.to_string(), value.into()));
+        self
+    }
+
+    fn pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = self.params.clone();
+        if let Some(state) = &self.state {
+            pairs.push(("state".to_string(), state.clone()));
+        }
+        // https://datatracker.ietf.org/doc/html/rfc9207
+        pairs.push(("iss".to_string(), self.iss.clone()));
+        pairs
+    }
+
+    // https://openid.net/specs/oauth-v2-jarm.html#name-the-jwt-response-document
+    fn jwt(&self) -> Result<String> {
+        let mut claims = serde_json::Map::new();
+        for (name, value) in self.pairs() {
+            claims.insert(name, serde_json::Value::String(value));
+        }
+        claims.insert("aud".to_string(), self.aud.clone().into());
+        claims.insert(
+            "exp".to_string(),
+            (Utc::now() + chrono::Duration::minutes(10))
+                .timestamp()
+                .into(),
+        );
+        let jws = generate_jws(&claims, active_key())
+            .map_err(|e| ApiError::Internal(anyhow::anyhow!("sign authorization response: {e}")))?;
+        match &self.encryption_key {
+            Some(key) => generate_jwe(jws.as_bytes(), key.as_bytes(), "JWT").map_err(|e| {
+                ApiError::Internal(anyhow::anyhow!("encrypt authorization response: {e}"))
+            }),
+            None => Ok(jws),
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self.response_mode.base() {
+            ResponseMode::FormPost => StatusCode::OK,
+            _ => StatusCode::FOUND,
+        }
+    }
+
+    pub fn write(&self) -> Result<HttpResponse> {
+        let mut uri = Url::parse(&self.redirect_uri).map_err(|e| {
+            AuthorizationError::local(
+                AuthError::InvalidRequest,
+                format!("malformed redirect_uri: {e}"),
+            )
+        })?;
+        let pairs = if self.response_mode.jarm() {
+            vec![("response".to_string(), self.jwt()?)]
+        } else {
+            self.pairs()
+        };
+        match self.response_mode.base() {
+            ResponseMode::Query => {
+                uri.query_pairs_mut().extend_pairs(pairs);
+            }
+            ResponseMode::Fragment => {
+                let fragment = url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(pairs)
+                    .finish();
+                uri.set_fragment(Some(&fragment));
+            }
+            // https://openid.net/specs/oauth-v2-form-post-response-mode-1_0.html
+            _ => {
+                let inputs = pairs
+                    .iter()
+                    .map(|(name, value)| {
+                        format!(
+                            r#"<input type="hidden" name="{}" value="{}"/>"#,
+                            escape_html(name),
+                            escape_html(value)
+                        )
+                    })
+                    .collect::<String>();
+                return Ok(HttpResponse::Ok()
+                    .insert_header((header::CACHE_CONTROL, "no-store"))
+                    .insert_header(header::ContentType::html())
+                    .body(format!(
+                        r#"<!DOCTYPE html><html><head><title>Submit This Form</title></head><body onload="javascript:document.forms[0].submit()"><form method="post" action="{}">{}<noscript><button type="submit">Continue</button></noscript></form></body></html>"#,
+                        escape_html(uri.as_str()),
+                        inputs
+                    )));
+            }
+        }
+        Ok(HttpResponse::Found()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .insert_header((header::LOCATION, uri.to_string()))
+            .finish())
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+// client 或 redirect_uri 未经确认时只能在本地展示错误，否则按 response_mode 回跳到客户端
+// https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.2.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationError {
+    pub error: AuthError,
+    pub description: Option<String>,
+    pub redirect: Option<AuthorizationResponse>,
+    // use_dpop_nonce 时通过 DPoP-Nonce 下发新的 nonce
+    pub dpop_nonce: Option<String>,
+}
+
+impl AuthorizationError {
+    pub fn local(error: AuthError, description: impl Into<String>) -> Self {
+        Self {
+            error,
+            description: Some(description.into()),
+            redirect: None,
+            dpop_nonce: None,
+        }
+    }
+
+    pub fn with_dpop_nonce(mut self, nonce: String) -> Self {
+        self.dpop_nonce = Some(nonce);
+        self
+    }
+
+    pub fn redirect(
+        response: AuthorizationResponse,
+        error: AuthError,
+        description: Option<String>,
+    ) -> Self {
+        Self {
+            error,
+            description,
+            redirect: Some(response),
+            dpop_nonce: None,
+        }
+    }
+
+    fn render(&self) -> HttpResponse {
+        let mut resp = HttpResponse::build(self.error.status_code());
+        // https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
+        if self.error == AuthError::InvalidClient {
+            resp.insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="forum""#));
+        }
+        if let Some(nonce) = &self.dpop_nonce {
+            resp.insert_header((DPOP_NONCE_HEADER, nonce.as_str()));
+        }
+        resp.insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(serde_json::json!({
+                "error": self.error,
+                "error_description": self.description,
+            }))
+    }
+}
+
+impl std::fmt::Display for AuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{}: {}", self.error, description),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for AuthorizationError {}
+
+impl ResponseError for AuthorizationError {
+    fn status_code(&self) -> StatusCode {
+        match &self.redirect {
+            Some(redirect) => redirect.status_code(),
+            None => self.error.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let Some(redirect) = &self.redirect else {
+            return self.render();
+        };
+        let mut redirect = redirect.clone().param("error", self.error.to_string());
+        if let Some(description) = &self.description {
+            redirect = redirect.param("error_description", description.clone());
+        }
+        redirect.write().unwrap_or_else(|_| self.render())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Validate, Default)]
 pub struct AuthRequest {
     pub client_id: String,
     pub audience: Option<String>,
-    #[validate(length(min = 1, max = 2))]
+    #[validate(length(min = 1, max = 3))]
+    #[serde(deserialize_with = "space_delimited")]
     pub response_type: Vec<ResponseType>,
+    #[serde(default, deserialize_with = "space_delimited")]
     pub scope: Vec<String>,
     pub state: Option<String>,
     #[validate(url)]
     pub redirect_uri: String,
+    #[serde(default)]
+    pub response_mode: Option<ResponseMode>,
     pub nonce: Option<String>,
     // https://openid.net/specs/openid-connect-core-1_0.html#CodeFlowSteps
-    pub prompt: PromptType,
+    #[serde(default)]
+    pub prompt: Option<PromptType>,
     // https://datatracker.ietf.org/doc/html/rfc7636
     pub code_challenge_method: Option<String>,
     pub code_challenge: Option<String>,
+    // https://openid.net/specs/openid-connect-core-1_0.html#AuthRequest
+    #[serde(default)]
+    pub id_token_hint: Option<String>,
+}
+
+// 按 MIME essence 比较，允许携带 charset 等参数
+// https://datatracker.ietf.org/doc/html/rfc6749#appendix-B
+pub fn require_form(req: &impl HttpMessage) -> Result<()> {
+    match req.mime_type() {
+        Ok(Some(mime))
+            if mime.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str() =>
+        {
+            Ok(())
+        }
+        _ => Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            "content type must be application/x-www-form-urlencoded",
+        )
+        .into()),
+    }
+}
+
+// 参数不能重复，没有值的参数视为未携带，无法识别的参数由调用方忽略
+// 此时 client 与 redirect_uri 都未确认，错误只能在本地展示
+// https://datatracker.ietf.org/doc/html/rfc6749#section-3.1
+pub fn form_params(source: &[u8]) -> Result<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (name, value) in url::form_urlencoded::parse(source) {
+        if value.is_empty() {
+            continue;
+        }
+        if params
+            .insert(name.to_string(), value.into_owned())
+            .is_some()
+        {
+            return Err(AuthorizationError::local(
+                AuthError::InvalidRequest,
+                format!("parameter {name} is duplicated"),
+            )
+            .into());
+        }
+    }
+    Ok(params)
+}
+
+// 请求参数中以空格分隔，持久化后的 flow 中则是数组
+// https://datatracker.ietf.org/doc/html/rfc6749#section-3.3
+fn space_delimited<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct SpaceDelimited<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> de::Visitor<'de> for SpaceDelimited<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a space-delimited string or a sequence")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+            v.split(' ')
+                .filter(|item| !item.is_empty())
+                .map(|item| T::deserialize(de::IntoDeserializer::into_deserializer(item)))
+                .collect()
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            let mut items = Vec::new();
+            while let Some(item) = seq.next_element()? {
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_any(SpaceDelimited(PhantomData))
+}
+
+impl AuthRequest {
+    pub fn from_params(params: HashMap<String, String>) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::Object(
+            params.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        ))
+        .map_err(|e| AuthorizationError::local(AuthError::InvalidRequest, e.to_string()).into())
+    }
+
+    // 未指定 response_mode 时按 response_type 取默认值
+    // https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#ResponseModes
+    pub fn response_mode(&self) -> ResponseMode {
+        let code_only = self.response_type.iter().all(|r| r == &ResponseType::Code);
+        match self.response_mode {
+            Some(ResponseMode::Jwt) if code_only => ResponseMode::QueryJwt,
+            Some(ResponseMode::Jwt) => ResponseMode::FragmentJwt,
+            Some(response_mode) => response_mode,
+            None if code_only => ResponseMode::Query,
+            None => ResponseMode::Fragment,
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AuthorizationCode {
@@ -67,12 +457,41 @@ impl AuthorizationCode {
     }
 }
 
+// https://datatracker.ietf.org/doc/html/rfc6749#section-5.1
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Tokens {
-    token_type: TokenType,
-    id_token: IdToken,
-    access_token: AccessToken,
-    expires_in: Duration,
+    pub token_type: TokenType,
+    pub access_token: String,
+    pub expires_in: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    // https://datatracker.ietf.org/doc/html/rfc8693#section-2.2.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+}
+
+// 不透明的 refresh token，公开客户端使用 DPoP 时绑定到 jkt
+// https://datatracker.ietf.org/doc/html/rfc9449#section-5
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefreshToken {
+    pub client_id: String,
+    pub subject: String,
+    pub audience: String,
+    pub scope: Vec<String>,
+    // 轮换时沿用首次签发时间，revoke_all 之后全部失效
+    pub issued_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jkt: Option<String>,
+}
+
+pub fn token_expires_in() -> Duration {
+    Duration::from_secs(env_var_default::<u64>("ACCESS_TOKEN_EXPIRES", 3600))
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
@@ -80,9 +499,10 @@ pub enum FlowStage {
     #[default]
     Initialized = 1,
     Authenticating = 2,
-    Authenticated = 3,
-    Authorized = 4,
-    Completed = 5,
+    MultiFactor = 3,
+    Authenticated = 4,
+    Authorized = 5,
+    Completed = 6,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
@@ -90,16 +510,29 @@ pub struct Flow {
     pub id: String,
     pub request: AuthRequest,
     pub flow_type: Vec<AuthRequestType>,
+    // 客户端配置含有 secret 摘要，只在内存中使用，不随 flow 写入 Redis
+    #[serde(skip)]
     pub client_config: Option<ClientConfig>,
+    // JARM 加密使用的客户端公钥
+    #[serde(default)]
+    pub encryption_key: Option<String>,
     pub client_idp_configs: Option<ClientIdpConfigs>,
     pub authorization_code: Option<AuthorizationCode>,
     pub tokens: Option<Tokens>,
     pub subject: Option<UserProfile>,
     pub associations: Vec<UserAssociation>,
+    // https://datatracker.ietf.org/doc/html/rfc8176
+    pub amr: Vec<String>,
     pub stage: FlowStage,
     pub error: Option<AuthError>,
     message: Option<String>,
     pub expires_at: DateTime<Utc>,
+    // 完成认证的时间，写入 token 的 auth_time
+    #[serde(default)]
+    pub auth_time: Option<DateTime<Utc>>,
+    // id_token_hint 指明的用户，授权时必须与认证结果一致
+    #[serde(default)]
+    pub hinted_subject: Option<String>,
 }
 
 impl Flow {
@@ -111,63 +544,250 @@ impl Flow {
         Flow {
             id: Self::gen_id(),
             request: params,
+            expires_at: Utc::now() + chrono::Duration::minutes(10),
             ..Default::default()
         }
     }
 
-    pub fn validate(&mut self) -> Result<()> {
-        let redirect_url = &self.client_config.as_ref().unwrap().redirect_url;
+    pub fn with_client(&mut self, client: ClientConfig) {
+        self.encryption_key = client.authorization_encryption_key.clone();
+        self.client_config = Some(client);
+    }
+
+    // 在 redirect_uri 确认之前出现的错误都不能回跳
+    pub fn verify_redirect_uri(&self) -> Result<()> {
+        let registered = self
+            .client_config
+            .as_ref()
+            .map(|c| c.redirect_url.contains(&self.request.redirect_uri))
+            .unwrap_or_default();
         // https://datatracker.ietf.org/doc/html/draft-ietf-oauth-security-topics#name-insufficient-redirect-uri-v
-        if !redirect_url.contains(&self.request.redirect_uri) {
-            return Err(ApiError::Response(ErrorUnauthorized(
-                "invalid_redirect_url",
-            )));
-        };
+        if !registered {
+            return Err(AuthorizationError::local(
+                AuthError::InvalidRequest,
+                "redirect_uri is not registered for the client",
+            )
+            .into());
+        }
+        Ok(())
+    }
 
-        if self
-            .request
-            .response_type
+    pub fn validate(&mut self) -> Result<()> {
+        self.request
+            .validate()
+            .map_err(|e| self.fail(AuthError::InvalidRequest, e.to_string()))?;
+        // code、token、id_token 的任意组合都是合法的，但不能重复
+        // https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#Combinations
+        let response_type = &self.request.response_type;
+        if response_type
             .iter()
-            .filter(|&r| CONFLICT_RESPONSE_TYPE.contains(&r))
-            .count()
-            == CONFLICT_RESPONSE_TYPE.len()
+            .enumerate()
+            .any(|(i, r)| response_type[..i].contains(r))
         {
-            return Err(ApiError::Response(ErrorUnauthorized(
-                "conflict_response_type",
-            )));
+            return Err(self.fail(
+                AuthError::UnsupportedResponseType,
+                "duplicated response type",
+            ));
+        }
+        // 含有 token 的响应不能经由 query 返回（加密的 JARM 除外），错误按 fragment 写回
+        // https://openid.net/specs/oauth-v2-jarm.html#name-response-mode-queryjwt
+        let response_mode = self.request.response_mode();
+        let encrypted = self.encryption_key.is_some();
+        if response_mode.base() == ResponseMode::Query
+            && !(response_mode.jarm() && encrypted)
+            && self
+                .request
+                .response_type
+                .iter()
+                .any(|r| r != &ResponseType::Code)
+        {
+            self.request.response_mode = response_mode.jarm().then_some(ResponseMode::FragmentJwt);
+            return Err(self.fail(
+                AuthError::InvalidRequest,
+                "response_mode query is not allowed for this response_type",
+            ));
+        }
+        // https://datatracker.ietf.org/doc/html/rfc6749#section-3.3
+        if self.request.scope.iter().any(|scope| {
+            scope.is_empty()
+                || !scope
+                    .bytes()
+                    .all(|b| b == 0x21 || (0x23..=0x5b).contains(&b) || (0x5d..=0x7e).contains(&b))
+        }) {
+            return Err(self.fail(AuthError::InvalidScope, "malformed scope"));
+        }
+        let oidc = self.request.scope.contains(&OPENID_SCOPE.to_string());
+        if !oidc && self.request.response_type.contains(&ResponseType::IdToken) {
+            return Err(self.fail(
+                AuthError::InvalidScope,
+                "id_token requires the openid scope",
+            ));
+        }
+        // implicit 与 hybrid 流程必须携带 nonce
+        // https://openid.net/specs/openid-connect-core-1_0.html#ImplicitAuthRequest
+        if oidc
+            && self
+                .request
+                .response_type
+                .iter()
+                .any(|r| r != &ResponseType::Code)
+            && self.request.nonce.as_deref().unwrap_or_default().is_empty()
+        {
+            return Err(self.fail(
+                AuthError::InvalidRequest,
+                "nonce is required for implicit and hybrid flows",
+            ));
+        }
+        // 每次授权都会新建流程，不存在可以静默复用的登录态
+        // https://openid.net/specs/openid-connect-core-1_0.html#AuthRequest
+        if matches!(self.request.prompt, Some(PromptType::None)) {
+            return Err(self.fail(AuthError::LoginRequired, "no active session"));
+        }
+        // id_token_hint 必须是签发给该客户端的 ID token，已过期的同样可以作为提示
+        if let Some(hint) = &self.request.id_token_hint {
+            let key = active_key();
+            let id_token = validation(key, vec![self.request.client_id.clone()])
+                .and_then(|mut validation| {
+                    validation.validate_exp = false;
+                    verify_id_token(hint, key, validation)
+                })
+                .map_err(|_| self.fail(AuthError::InvalidRequest, "id_token_hint is invalid"))?;
+            self.hinted_subject = Some(id_token.token.sub);
         }
         let flow_types = &mut self.flow_type;
         // https://openid.net/specs/openid-connect-core-1_0.html#AuthRequestValidation
-        if self.request.scope.contains(&OPENID_SCOPE.to_string()) {
+        if oidc {
             flow_types.push(AuthRequestType::Oidc);
         }
         flow_types.push(AuthRequestType::Oauth);
         Ok(())
     }
 
+    pub fn response(&self) -> AuthorizationResponse {
+        AuthorizationResponse::new(&self.request).encrypt_with(self.encryption_key.clone())
+    }
+
+    // 以 OAuth 错误结束流程并回跳到客户端
+    pub fn fail(&self, error: AuthError, description: impl Into<String>) -> ApiError {
+        AuthorizationError::redirect(self.response(), error, Some(description.into())).into()
+    }
+
+    // 记录已完成的认证因子，需要多因子认证时停留在 MultiFactor 阶段
+    pub fn authenticated(&mut self, method: &str, mfa_required: bool) {
+        if !self.amr.iter().any(|m| m == method) {
+            self.amr.push(method.to_string());
+        }
+        self.stage = if mfa_required && self.amr.len() < 2 {
+            FlowStage::MultiFactor
+        } else {
+            self.auth_time.get_or_insert_with(Utc::now);
+            FlowStage::Authenticated
+        };
+    }
+
+    fn subject(&self) -> Result<&UserProfile> {
+        self.subject
+            .as_ref()
+            .ok_or_else(|| ApiError::Internal(anyhow::anyhow!("flow subject is lacked")))
+    }
+
+    // cnf 为 token 绑定的持有者凭证，授权端点直接签发的 token 不绑定
+    pub fn access_token(&self, cnf: Option<Confirmation>) -> Result<String> {
+        let mut access_token = AccessToken::new(
+            &self.subject()?.openid,
+            self.request
+                .audience
+                .as_deref()
+                .unwrap_or(&self.request.client_id),
+            &self.request.client_id,
+            token_expires_in(),
+            self.request.scope.clone(),
+        );
+        access_token.auth_time = self.auth_time.map(|t| t.timestamp());
+        access_token.cnf = cnf;
+        generate_typed_jws(&access_token, active_key(), ACCESS_TOKEN_JWT_TYPE)
+            .map_err(|e| ApiError::Internal(anyhow::anyhow!("sign access token failed: {e}")))
+    }
+
+    // 与 code 或 access_token 一同签发时携带 c_hash / at_hash
+    pub fn id_token(&self, code: Option<&str>, access_token: Option<&str>) -> Result<String> {
+        let key = active_key();
+        let mut id_token =
+            IdToken::new(&self.request.client_id, self.subject()?, token_expires_in());
+        id_token.amr = self.amr.clone();
+        id_token.nonce = self.request.nonce.clone();
+        id_token.c_hash = code.map(|c| half_hash(key.alg(), c));
+        id_token.at_hash = access_token.map(|t| half_hash(key.alg(), t));
+        generate_jws(&id_token, key)
+            .map_err(|e| ApiError::Internal(anyhow::anyhow!("sign id token failed: {e}")))
+    }
+
+    // 按 response_type 组装授权端点返回的 code、access_token 与 id_token
+    // https://openid.net/specs/openid-connect-core-1_0.html#HybridAuthResponse
+    pub fn authorization_response(&self) -> Result<AuthorizationResponse> {
+        let response_type = &self.request.response_type;
+        let server_error = |e: ApiError| self.fail(AuthError::ServerError, e.to_string());
+        let mut response = self.response();
+        let code = match response_type.contains(&ResponseType::Code) {
+            true => Some(
+                self.authorization_code
+                    .as_ref()
+                    .ok_or_else(|| {
+                        self.fail(AuthError::ServerError, "authorization code is lacked")
+                    })?
+                    .code
+                    .clone(),
+            ),
+            false => None,
+        };
+        if let Some(code) = &code {
+            response = response.param("code", code.clone());
+        }
+        let access_token = match response_type.contains(&ResponseType::Token) {
+            true => Some(self.access_token(None).map_err(server_error)?),
+            false => None,
+        };
+        if let Some(access_token) = &access_token {
+            response = response
+                .param("access_token", access_token.clone())
+                .param("token_type", "Bearer")
+                .param("expires_in", token_expires_in().as_secs().to_string());
+        }
+        if response_type.contains(&ResponseType::IdToken) {
+            response = response.param(
+                "id_token",
+                self.id_token(code.as_deref(), access_token.as_deref())
+                    .map_err(server_error)?,
+            );
+        }
+        Ok(response)
+    }
+
     pub fn next_uri(&self) -> String {
         let mut builder = Uri::builder().scheme("http");
         let next_uri = match self.stage {
             FlowStage::Initialized => "/login",
             FlowStage::Authenticating => "/login",
+            FlowStage::MultiFactor => "/mfa",
             FlowStage::Authenticated => "/confirm",
             FlowStage::Authorized => self.request.redirect_uri.as_str(),
             FlowStage::Completed => "/done",
         };
         builder = builder.path_and_query(next_uri);
-        if let Some(auth_error) = &self.error {
-            builder.path_and_query(format!("error={auth_error}"))
-        } else {
-            builder
-        }
-        .build()
-        .unwrap_or_default()
-        .to_string()
+        builder.build().unwrap_or_default().to_string()
     }
 
     pub fn dispatch(&self) -> Result<HttpResponse> {
+        if let Some(error) = &self.error {
+            return Err(AuthorizationError::redirect(
+                self.response(),
+                error.clone(),
+                self.message.clone(),
+            )
+            .into());
+        }
         let mut resp = match self.stage {
-            FlowStage::Initialized | FlowStage::Authenticating => {
+            FlowStage::Initialized | FlowStage::Authenticating | FlowStage::MultiFactor => {
                 // 展示认证和登录页面，让用户继续流程（可能输入用户名和密码也可能输入验证码等）
                 HttpResponse::MultipleChoices()
                     .append_header((http::header::LOCATION, self.next_uri()))
@@ -175,15 +795,13 @@ impl Flow {
             }
             FlowStage::Authenticated => {
                 // 已认证，需要授权
-                HttpResponse::MovedPermanently()
+                HttpResponse::Found()
                     .append_header((http::header::LOCATION, self.next_uri()))
                     .finish()
             }
             FlowStage::Authorized => {
-                // 已授权，去拿 token
-                HttpResponse::MovedPermanently()
-                    .append_header((http::header::LOCATION, self.next_uri()))
-                    .finish()
+                // 已授权，按 response_mode 把授权结果交给客户端
+                self.authorization_response()?.write()?
             }
             FlowStage::Completed => {
                 // 已授权，去拿 token
@@ -209,7 +827,11 @@ pub async fn validate_flow(req: &actix_web::HttpRequest) -> Result<Flow> {
         )))
         .map(|c| c.value().to_owned())?;
 
-    redis_get::<Flow>(format!("forum:auth:flow:{}", session).as_str())
+    load_flow(&session).await
+}
+
+pub async fn load_flow(id: &str) -> Result<Flow> {
+    redis_get::<Flow>(format!("forum:auth:flow:{}", id).as_str())
         .await
         .map_err(|_| ApiError::Response(ErrorPreconditionFailed("session is nonexsistent")))?
         .ok_or(ApiError::Response(ErrorPreconditionFailed(
@@ -225,7 +847,7 @@ pub async fn validate_flow(req: &actix_web::HttpRequest) -> Result<Flow> {
         })
 }
 
-async fn persist_flow(flow: &'_ Flow) -> Result<&'_ Flow> {
+pub async fn persist_flow(flow: &'_ Flow) -> Result<&'_ Flow> {
     let now = Utc::now();
     if flow.expires_at < now {
         Err(ApiError::Response(ErrorPreconditionFailed(
@@ -235,7 +857,7 @@ async fn persist_flow(flow: &'_ Flow) -> Result<&'_ Flow> {
         redis_setex(
             format!("forum:auth:flow:{}", flow.id).as_str(),
             flow,
-            now - flow.expires_at,
+            flow.expires_at - now,
         )
         .await?;
         Ok(flow)
@@ -258,6 +880,12 @@ pub struct ChallengeRequest {
     pub challenge_type: ChallengeType,
     #[serde(rename = "identifier")]
     pub identifier: String,
-    #[serde(rename = "proof")]
-    pub proof: String,
+    #[serde(rename = "proof", default)]
+    pub proof: Option<String>,
+}
+
+// 用户在 /confirm 页面同意或拒绝授权
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ConsentRequest {
+    pub approved: bool,
 }
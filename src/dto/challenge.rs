@@ -1,7 +1,9 @@
+use chrono::{DateTime, Utc};
+use ring::constant_time::verify_slices_are_equal;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::common::constant::ChallengeType;
+use crate::common::{constant::ChallengeType, utils::sha256};
 
 #[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 pub struct ChallengeCofig {
@@ -10,3 +12,55 @@ pub struct ChallengeCofig {
     #[serde(rename = "type")]
     pub challenge_type: ChallengeType,
 }
+
+// 验证码只保存摘要，并与接收方绑定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CodeChallenge {
+    pub identifier: String,
+    pub code_hash: String,
+}
+
+impl CodeChallenge {
+    pub fn new(identifier: &str, code: &str) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            code_hash: Self::digest(identifier, code),
+        }
+    }
+
+    pub fn verify(&self, identifier: &str, proof: &str) -> bool {
+        verify_slices_are_equal(
+            self.code_hash.as_bytes(),
+            Self::digest(identifier, proof).as_bytes(),
+        )
+        .is_ok()
+    }
+
+    fn digest(identifier: &str, code: &str) -> String {
+        hex::encode(sha256(format!("{identifier}:{code}").as_bytes()))
+    }
+}
+
+// 登录链接与发起登录的 flow 绑定，可在其他设备上打开
+// 确认页面展示发起方的 client、IP 与时间，便于用户识别被钓鱼转发的链接
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkChallenge {
+    pub flow_id: String,
+    pub identifier: String,
+    pub client_name: String,
+    pub ip: String,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LinkRequest {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LinkStatus {
+    pub authenticated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_uri: Option<String>,
+}
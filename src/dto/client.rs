@@ -1,8 +1,48 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, num::NonZeroU32};
 
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use openssl::x509::X509;
+use ring::{
+    constant_time::verify_slices_are_equal,
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
 use serde::{Deserialize, Serialize};
 
-use crate::service::connection::IdpType;
+use super::auth::{AuthError, AuthorizationError};
+use crate::{
+    common::{
+        config::env_var_default,
+        errors::{ApiError, Result},
+        jwt,
+        tls::{self, PeerCertificate},
+        utils::{decode64, decode64url, encode64, seal, unseal},
+    },
+    service::connection::IdpType,
+};
+
+// 轮换期间同一个客户端最多同时存在两个有效的 secret
+pub const MAX_ACTIVE_SECRETS: usize = 2;
+const PBKDF2_ITERATIONS: u32 = 310_000;
+
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+// https://www.rfc-editor.org/rfc/rfc7591#section-2
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenEndpointAuthMethod {
+    #[default]
+    ClientSecretBasic,
+    ClientSecretPost,
+    ClientSecretJwt,
+    PrivateKeyJwt,
+    // https://datatracker.ietf.org/doc/html/rfc8705#section-2.1
+    TlsClientAuth,
+    // https://datatracker.ietf.org/doc/html/rfc8705#section-2.2
+    SelfSignedTlsClientAuth,
+    None,
+}
 
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct ClientConfig {
@@ -10,8 +50,380 @@ pub struct ClientConfig {
     pub name: String,
     pub logo: String,
     pub description: String,
-    pub secret: String,
+    #[serde(default)]
+    pub secrets: Vec<ClientSecret>,
     pub redirect_url: Vec<String>,
+    #[serde(default)]
+    pub mfa_required: bool,
+    // PEM 格式的 RSA 公钥，注册后 JARM 响应会再加密一层
+    #[serde(default)]
+    pub authorization_encryption_key: Option<String>,
+    #[serde(default)]
+    pub token_endpoint_auth_method: TokenEndpointAuthMethod,
+    // private_key_jwt 校验断言使用的 PEM 公钥
+    #[serde(default)]
+    pub public_key: Option<String>,
+    // tls_client_auth 要求证书由受信 CA 签发且 subject DN 一致
+    #[serde(default)]
+    pub tls_client_auth_subject_dn: Option<String>,
+    // self_signed_tls_client_auth 注册的 PEM 证书
+    #[serde(default)]
+    pub tls_client_certificate: Option<String>,
+    // https://datatracker.ietf.org/doc/html/rfc8705#section-3.4
+    #[serde(default)]
+    pub tls_client_certificate_bound_access_tokens: bool,
+    // 要求 token 端点的每次请求都携带 DPoP proof
+    // https://datatracker.ietf.org/doc/html/rfc9449#section-5.2
+    #[serde(default)]
+    pub dpop_bound_access_tokens: bool,
+    // 允许通过 token exchange 换取的 audience
+    // https://datatracker.ietf.org/doc/html/rfc8693#section-2.1
+    #[serde(default)]
+    pub token_exchange_audiences: Vec<String>,
+}
+
+// 只保存 secret 的 PBKDF2 摘要：pbkdf2-sha256$<iterations>$<salt>$<digest>
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ClientSecret {
+    pub digest: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // client_secret_jwt 需要原始 secret 作为 HMAC 密钥，只能以 CLIENT_SECRET_KEY 加密保存
+    #[serde(default)]
+    pub sealed: Option<String>,
+}
+
+impl ClientSecret {
+    pub fn hash(secret: &str, expires_at: Option<DateTime<Utc>>) -> Self {
+        Self::hash_with(secret, PBKDF2_ITERATIONS, expires_at)
+    }
+
+    pub fn hash_with(secret: &str, iterations: u32, expires_at: Option<DateTime<Utc>>) -> Self {
+        let mut salt = [0u8; 16];
+        SystemRandom::new().fill(&mut salt).unwrap();
+        let mut digest = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN),
+            &salt,
+            secret.as_bytes(),
+            &mut digest,
+        );
+        Self {
+            digest: format!(
+                "pbkdf2-sha256${iterations}${}${}",
+                encode64(&salt),
+                encode64(&digest)
+            ),
+            expires_at,
+            sealed: None,
+        }
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map(|at| at > now).unwrap_or(true)
+    }
+
+    pub fn verify(&self, secret: &str) -> bool {
+        let mut parts = self.digest.split('$');
+        let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(digest), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return false;
+        };
+        let (Some(iterations), Ok(salt), Ok(digest)) = (
+            iterations.parse::<u32>().ok().and_then(NonZeroU32::new),
+            decode64(salt),
+            decode64(digest),
+        ) else {
+            return false;
+        };
+        pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            secret.as_bytes(),
+            &digest,
+        )
+        .is_ok()
+    }
+
+    // client_secret_jwt 的 secret 额外以 CLIENT_SECRET_KEY 加密保存原文
+    pub fn seal(mut self, secret: &str) -> Result<Self> {
+        let key = seal_key().ok_or_else(|| {
+            ApiError::Internal(anyhow::anyhow!("CLIENT_SECRET_KEY is not configured"))
+        })?;
+        self.sealed = Some(seal(&key, secret.as_bytes())?);
+        Ok(self)
+    }
+
+    pub fn unseal(&self) -> Option<Vec<u8>> {
+        unseal(&seal_key()?, self.sealed.as_deref()?).ok()
+    }
+}
+
+fn seal_key() -> Option<Vec<u8>> {
+    hex::decode(env_var_default::<String>(
+        "CLIENT_SECRET_KEY",
+        String::new(),
+    ))
+    .ok()
+    .filter(|key| key.len() == 32)
+}
+
+fn invalid_client(description: impl Into<String>) -> AuthorizationError {
+    AuthorizationError::local(AuthError::InvalidClient, description)
+}
+
+// 客户端在 token、introspection、revocation 与 PAR 端点出示的凭证
+// https://datatracker.ietf.org/doc/html/rfc6749#section-2.3
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientCredentials {
+    Basic {
+        client_id: String,
+        secret: String,
+    },
+    Post {
+        client_id: String,
+        secret: String,
+    },
+    Assertion {
+        client_id: String,
+        assertion: String,
+    },
+    None {
+        client_id: String,
+    },
+}
+
+impl ClientCredentials {
+    // 同时使用多种认证方式时拒绝
+    pub fn extract(authorization: Option<&str>, params: &HashMap<String, String>) -> Result<Self> {
+        let basic = authorization.and_then(|value| value.strip_prefix("Basic "));
+        let secret = params.get("client_secret");
+        let assertion = params.get("client_assertion");
+        if [basic.is_some(), secret.is_some(), assertion.is_some()]
+            .iter()
+            .filter(|&&presented| presented)
+            .count()
+            > 1
+        {
+            return Err(AuthorizationError::local(
+                AuthError::InvalidRequest,
+                "multiple client authentication methods",
+            )
+            .into());
+        }
+        let client_id = params.get("client_id").cloned();
+        let credentials = if let Some(basic) = basic {
+            // client_id 与 secret 在 base64 之前经过 form 编码
+            let decoded = decode64(basic.trim_end_matches('='))
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or_else(|| invalid_client("malformed basic credentials"))?;
+            let (id, secret) = decoded
+                .split_once(':')
+                .ok_or_else(|| invalid_client("malformed basic credentials"))?;
+            let decode = |value: &str| -> String {
+                url::form_urlencoded::parse(format!("v={value}").as_bytes())
+                    .map(|(_, v)| v.into_owned())
+                    .next()
+                    .unwrap_or_default()
+            };
+            ClientCredentials::Basic {
+                client_id: decode(id),
+                secret: decode(secret),
+            }
+        } else if let Some(assertion) = assertion {
+            if params.get("client_assertion_type").map(String::as_str)
+                != Some(CLIENT_ASSERTION_TYPE)
+            {
+                return Err(invalid_client("unsupported client_assertion_type").into());
+            }
+            // client_id 可以省略，此时取断言的 sub
+            let client_id = match client_id {
+                Some(client_id) => client_id,
+                None => assertion
+                    .split('.')
+                    .nth(1)
+                    .and_then(|payload| decode64url(payload).ok())
+                    .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok())
+                    .and_then(|claims| claims["sub"].as_str().map(str::to_string))
+                    .ok_or_else(|| invalid_client("malformed client assertion"))?,
+            };
+            ClientCredentials::Assertion {
+                client_id,
+                assertion: assertion.clone(),
+            }
+        } else {
+            let client_id =
+                client_id.ok_or_else(|| invalid_client("client authentication is lacked"))?;
+            match secret {
+                Some(secret) => ClientCredentials::Post {
+                    client_id,
+                    secret: secret.clone(),
+                },
+                None => ClientCredentials::None { client_id },
+            }
+        };
+        if let Some(client_id) = params.get("client_id") {
+            if client_id != credentials.client_id() {
+                return Err(invalid_client("client_id mismatched").into());
+            }
+        }
+        Ok(credentials)
+    }
+
+    pub fn client_id(&self) -> &str {
+        match self {
+            ClientCredentials::Basic { client_id, .. }
+            | ClientCredentials::Post { client_id, .. }
+            | ClientCredentials::Assertion { client_id, .. }
+            | ClientCredentials::None { client_id } => client_id,
+        }
+    }
+
+    // 校验凭证与客户端注册的认证方式，返回断言中需要防重放的 jti 与过期时间
+    pub fn verify(
+        &self,
+        client: &ClientConfig,
+        audiences: &[String],
+        certificate: Option<&PeerCertificate>,
+    ) -> Result<Option<AssertionClaims>> {
+        let method = client.token_endpoint_auth_method;
+        let matched = match self {
+            ClientCredentials::Basic { secret, .. } => {
+                method == TokenEndpointAuthMethod::ClientSecretBasic && client.verify_secret(secret)
+            }
+            ClientCredentials::Post { secret, .. } => {
+                method == TokenEndpointAuthMethod::ClientSecretPost && client.verify_secret(secret)
+            }
+            ClientCredentials::None { .. } => match method {
+                TokenEndpointAuthMethod::None => true,
+                TokenEndpointAuthMethod::TlsClientAuth
+                | TokenEndpointAuthMethod::SelfSignedTlsClientAuth => {
+                    certificate.is_some_and(|c| client.verify_certificate(c))
+                }
+                _ => false,
+            },
+            ClientCredentials::Assertion { assertion, .. } => {
+                return client.verify_assertion(assertion, audiences).map(Some);
+            }
+        };
+        if !matched {
+            return Err(invalid_client("client authentication failed").into());
+        }
+        Ok(None)
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7523#section-3
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssertionClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(with = "jwt::numeric_date")]
+    pub exp: DateTime<Utc>,
+    pub jti: String,
+}
+
+impl ClientConfig {
+    pub fn active_secrets(&self) -> impl Iterator<Item = &ClientSecret> {
+        let now = Utc::now();
+        self.secrets
+            .iter()
+            .filter(move |secret| secret.active(now))
+            .take(MAX_ACTIVE_SECRETS)
+    }
+
+    pub fn verify_secret(&self, secret: &str) -> bool {
+        self.active_secrets().any(|s| s.verify(secret))
+    }
+
+    pub fn verify_certificate(&self, certificate: &PeerCertificate) -> bool {
+        match self.token_endpoint_auth_method {
+            TokenEndpointAuthMethod::TlsClientAuth => {
+                certificate.trusted
+                    && self
+                        .tls_client_auth_subject_dn
+                        .as_deref()
+                        .is_some_and(|dn| tls::same_dn(dn, &certificate.subject_dn))
+            }
+            TokenEndpointAuthMethod::SelfSignedTlsClientAuth => self
+                .tls_client_certificate
+                .as_deref()
+                .and_then(|pem| X509::from_pem(pem.as_bytes()).ok())
+                .and_then(|registered| tls::thumbprint(&registered))
+                .is_some_and(|thumbprint| {
+                    verify_slices_are_equal(
+                        thumbprint.as_bytes(),
+                        certificate.thumbprint.as_bytes(),
+                    )
+                    .is_ok()
+                }),
+            _ => false,
+        }
+    }
+
+    pub fn verify_assertion(
+        &self,
+        assertion: &str,
+        audiences: &[String],
+    ) -> Result<AssertionClaims> {
+        let header = jsonwebtoken::decode_header(assertion)
+            .map_err(|_| invalid_client("malformed client assertion"))?;
+        let keys = match (self.token_endpoint_auth_method, header.alg) {
+            (
+                TokenEndpointAuthMethod::ClientSecretJwt,
+                Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512,
+            ) => self
+                .active_secrets()
+                .filter_map(ClientSecret::unseal)
+                .map(|secret| DecodingKey::from_secret(&secret))
+                .collect::<Vec<_>>(),
+            (TokenEndpointAuthMethod::PrivateKeyJwt, alg) => {
+                let pem = self
+                    .public_key
+                    .as_deref()
+                    .ok_or_else(|| invalid_client("client public key is not registered"))?
+                    .as_bytes();
+                vec![match alg {
+                    Algorithm::RS256
+                    | Algorithm::RS384
+                    | Algorithm::RS512
+                    | Algorithm::PS256
+                    | Algorithm::PS384
+                    | Algorithm::PS512 => DecodingKey::from_rsa_pem(pem),
+                    Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem),
+                    Algorithm::EdDSA => DecodingKey::from_ed_pem(pem),
+                    _ => return Err(invalid_client("unsupported assertion algorithm").into()),
+                }
+                .map_err(|_| invalid_client("client public key is malformed"))?]
+            }
+            _ => Vec::new(),
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(audiences);
+        validation.set_issuer(&[&self.client_id]);
+        validation.set_required_spec_claims(&["iss", "sub", "aud", "exp"]);
+        validation.leeway = jwt::leeway();
+        validation.validate_nbf = true;
+        let mut failure = invalid_client("client authentication failed");
+        for key in keys {
+            match jsonwebtoken::decode::<AssertionClaims>(assertion, &key, &validation) {
+                Ok(data) if data.claims.sub == self.client_id && !data.claims.jti.is_empty() => {
+                    return Ok(data.claims)
+                }
+                Ok(_) => failure = invalid_client("invalid client assertion"),
+                Err(e) => failure = invalid_client(format!("invalid client assertion: {e}")),
+            }
+        }
+        Err(failure.into())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -41,6 +453,26 @@ impl Debug for ClientConfig {
             .field("logo", &self.logo)
             .field("description", &self.description)
             .field("redirect_url", &self.redirect_url)
+            .field("mfa_required", &self.mfa_required)
+            .field("secrets", &self.secrets.len())
+            .field(
+                "token_endpoint_auth_method",
+                &self.token_endpoint_auth_method,
+            )
+            .field(
+                "authorization_encryption_key",
+                &self.authorization_encryption_key.is_some(),
+            )
+            .field(
+                "tls_client_auth_subject_dn",
+                &self.tls_client_auth_subject_dn,
+            )
+            .field(
+                "tls_client_certificate_bound_access_tokens",
+                &self.tls_client_certificate_bound_access_tokens,
+            )
+            .field("dpop_bound_access_tokens", &self.dpop_bound_access_tokens)
+            .field("token_exchange_audiences", &self.token_exchange_audiences)
             .finish()
     }
 }
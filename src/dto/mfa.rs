@@ -0,0 +1,85 @@
+use ring::{
+    constant_time::verify_slices_are_equal,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::{encode32, hmac_sha256};
+
+// secret 经过加密后保存在 forum-server
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TotpCredential {
+    pub openid: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    pub uri: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TotpRequest {
+    pub code: String,
+}
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// 恢复码只保存带密钥的摘要，明文只在生成时展示一次
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecoveryCodes {
+    pub openid: String,
+    pub hashes: Vec<String>,
+}
+
+impl RecoveryCodes {
+    pub fn generate(openid: &str, key: &[u8]) -> (Self, Vec<String>) {
+        let codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let mut bytes = [0u8; 10];
+                SystemRandom::new().fill(&mut bytes).unwrap();
+                encode32(&bytes)
+                    .to_lowercase()
+                    .as_bytes()
+                    .chunks(4)
+                    .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+                    .collect::<Vec<String>>()
+                    .join("-")
+            })
+            .collect::<Vec<String>>();
+        let recovery_codes = Self {
+            openid: openid.to_string(),
+            hashes: codes.iter().map(|code| Self::digest(key, code)).collect(),
+        };
+        (recovery_codes, codes)
+    }
+
+    // 忽略大小写、空白和分隔符
+    pub fn digest(key: &[u8], code: &str) -> String {
+        let normalized = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+        hex::encode(hmac_sha256(key, normalized.as_bytes()))
+    }
+
+    pub fn find(&self, key: &[u8], code: &str) -> Option<String> {
+        let digest = Self::digest(key, code);
+        self.hashes
+            .iter()
+            .find(|hash| verify_slices_are_equal(hash.as_bytes(), digest.as_bytes()).is_ok())
+            .cloned()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryCodeRequest {
+    pub code: String,
+}
@@ -1,6 +1,17 @@
+use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::common::srp::{
+    errors::SrpError,
+    groups::select_group,
+    server::SrpServerVerifier,
+    types::{SrpGroupId, SrpHash, SrpProofMode},
+};
+
+const SALT_MIN_LENGTH: usize = 16;
+const SALT_MAX_LENGTH: usize = 64;
+
 #[derive(Deserialize, Serialize)]
 pub struct PreSrpRequest {
     #[serde(rename = "i")]
@@ -14,6 +25,11 @@ pub struct PreSrpRespose {
     pub salt: String,
     #[serde(rename = "b_pub")]
     pub b_pub: String,
+    pub handshake: String,
+    // 客户端按用户记录的参数计算
+    pub group: SrpGroupId,
+    pub hash: SrpHash,
+    pub mode: SrpProofMode,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -22,6 +38,39 @@ pub struct SrpRequest {
     pub identity: String,
     #[serde(rename = "m1")]
     pub proof: String,
+    pub handshake: String,
+}
+
+// 每次登录尝试独立的握手状态，绑定 flow 和用户，只能校验一次
+#[derive(Deserialize, Serialize)]
+pub struct SrpHandshake {
+    pub flow_id: String,
+    pub identifier: String,
+    pub verifier: SrpServerVerifier,
+}
+
+impl SrpHandshake {
+    pub fn verify(
+        &self,
+        flow_id: &str,
+        identifier: &str,
+        m1: &[u8],
+    ) -> Result<&SrpServerVerifier, SrpError> {
+        if self.flow_id != flow_id || self.identifier != identifier {
+            return Err(SrpError::IllegalParameter("handshake".to_owned()));
+        }
+        self.verifier.verify_client(m1)?;
+        Ok(&self.verifier)
+    }
+}
+
+// m2 供客户端认证服务端，confirmation 由会话密钥 K 派生并绑定当前 flow
+#[derive(Deserialize, Serialize)]
+pub struct SrpResponse {
+    #[serde(rename = "m2")]
+    pub proof: String,
+    pub confirmation: String,
+    pub next_uri: String,
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
@@ -29,4 +78,69 @@ pub struct SrpPassword {
     pub identifier: String,
     pub verifier: String,
     pub salt: String,
+    // 未指定的历史记录沿用 legacy 证明
+    #[serde(default)]
+    pub mode: SrpProofMode,
+    #[serde(default)]
+    pub group: SrpGroupId,
+    #[serde(default)]
+    pub hash: SrpHash,
+}
+
+impl SrpPassword {
+    // 校验 salt 与 verifier 的编码、长度和取值范围，拒绝平凡值
+    pub fn validate(&self) -> Result<(), SrpError> {
+        if self.identifier.is_empty() {
+            return Err(SrpError::IllegalParameter("identifier".to_owned()));
+        }
+        let salt =
+            hex::decode(&self.salt).map_err(|_| SrpError::IllegalParameter("salt".to_owned()))?;
+        if !(SALT_MIN_LENGTH..=SALT_MAX_LENGTH).contains(&salt.len())
+            || salt.iter().all(|b| *b == 0)
+        {
+            return Err(SrpError::IllegalParameter("salt".to_owned()));
+        }
+
+        let group = select_group(self.group, self.hash);
+        let n_len = (group.n.bits() as usize).div_ceil(8);
+        let verifier = hex::decode(&self.verifier)
+            .map_err(|_| SrpError::IllegalParameter("verifier".to_owned()))?;
+        // g^x mod N 短于 N 一半长度的概率可以忽略
+        if verifier.len() > n_len || verifier.len() < n_len / 2 {
+            return Err(SrpError::IllegalParameter("verifier".to_owned()));
+        }
+        let v = BigUint::from_bytes_be(&verifier);
+        let one = BigUint::from(1u8);
+        if v <= one || v >= &group.n - &one || v == group.g {
+            return Err(SrpError::IllegalParameter("verifier".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+// 修改口令时用旧口令完成的 SRP 握手证明身份
+#[derive(Deserialize, Serialize)]
+pub struct SrpChangeRequest {
+    pub handshake: String,
+    #[serde(rename = "m1")]
+    pub proof: String,
+    pub password: SrpPassword,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetRequest {
+    pub identifier: String,
+}
+
+// 重置凭证只保存摘要
+#[derive(Deserialize, Serialize)]
+pub struct PasswordReset {
+    pub identifier: String,
+    pub openid: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub password: SrpPassword,
 }
@@ -0,0 +1,28 @@
+use actix_web::HttpRequest;
+
+use super::auth::Flow;
+use crate::common::proxy::client_ip;
+
+pub const SRP_SCOPE: &str = "srp";
+pub const CHALLENGE_SCOPE: &str = "challenge";
+pub const MFA_SCOPE: &str = "mfa";
+
+// 一次认证尝试，按账号、来源 IP 和 client 三个维度限流
+pub struct Attempt {
+    pub scope: &'static str,
+    pub identifier: String,
+    pub ip: String,
+    pub client_id: String,
+}
+
+impl Attempt {
+    // 只有经过可信代理时才取转发的真实地址
+    pub fn new(req: &HttpRequest, flow: &Flow, scope: &'static str, identifier: &str) -> Self {
+        Self {
+            scope,
+            identifier: identifier.to_string(),
+            ip: client_ip(req.peer_addr(), req.headers()),
+            client_id: flow.request.client_id.clone(),
+        }
+    }
+}
@@ -19,6 +19,8 @@ pub struct UserProfile {
     pub avatar: String,
     pub gander: Gander,
     pub email: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
 }
 
 impl From<IdpUser> for UserProfile {
@@ -29,6 +31,7 @@ impl From<IdpUser> for UserProfile {
             avatar: value.avatar,
             gander: Gander::Unknown,
             email: value.email,
+            roles: Vec::new(),
         }
     }
 }
@@ -49,6 +52,8 @@ pub struct SubjectProfile {
     pub avatar: String,
     pub email: Option<String>,
     pub gander: Gander,
+    #[serde(default)]
+    pub roles: Vec<String>,
     pub associations: Vec<UserAssociation>,
 }
 
@@ -60,6 +65,7 @@ impl From<SubjectProfile> for UserProfile {
             avatar: value.avatar,
             gander: value.gander,
             email: value.email,
+            roles: value.roles,
         }
     }
 }
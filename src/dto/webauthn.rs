@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+// 保存在 forum-server 的凭证，id 和公钥均为 base64url
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebauthnCredential {
+    pub openid: String,
+    pub credential_id: String,
+    pub public_key: String,
+    pub sign_count: u32,
+    pub aaguid: String,
+    #[serde(default)]
+    pub backup_eligible: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebauthnChallenge {
+    pub challenge: String,
+    pub openid: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub credential_type: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: &'static str,
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    pub fn new(id: &str) -> Self {
+        Self {
+            credential_type: "public-key",
+            id: id.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+// https://www.w3.org/TR/webauthn-2/#dictdef-publickeycredentialcreationoptions
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+// https://www.w3.org/TR/webauthn-2/#dictdef-publickeycredentialrequestoptions
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: &'static str,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RegistrationRequest {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssertionRequest {
+    pub id: String,
+    pub response: AssertionResponse,
+}
@@ -6,7 +6,12 @@ use dotenvy::dotenv;
 use tracing_actix_web::TracingLogger;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 
-use crate::common::nacos::init_nacos;
+use crate::common::{
+    jwt,
+    middleware::{self, RateLimiter},
+    nacos::init_nacos,
+    proxy, tls,
+};
 
 mod common;
 mod controller;
@@ -27,18 +32,55 @@ async fn main() -> std::io::Result<()> {
             .with(tracing_subscriber::fmt::Layer::default().with_writer(non_blocking)),
     );
     init_nacos().await;
-    HttpServer::new(move || {
+    proxy::init_trusted_proxies()?;
+    let rate_limit_rules = middleware::load_rules()?;
+    service::mfa_service::secret_key().map_err(|e| std::io::Error::other(e.to_string()))?;
+    jwt::init_active_key()?;
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap(RateLimiter::new(rate_limit_rules.clone()))
             .wrap(TracingLogger::default())
             .service(controller::authorize_controller::query_authorize)
             .service(controller::authorize_controller::form_authorize)
+            .service(controller::authorize_controller::consent)
             .service(controller::authenticate_controller::pre_login)
             .service(controller::authenticate_controller::form_login)
+            .service(controller::authenticate_controller::registry)
+            .service(controller::authenticate_controller::change_password)
+            .service(controller::authenticate_controller::password_reset)
+            .service(controller::authenticate_controller::password_reset_confirm)
+            .service(controller::challenge_controller::code_challenge)
+            .service(controller::challenge_controller::challenge_continous)
+            .service(controller::challenge_controller::link_confirm)
+            .service(controller::challenge_controller::link_continous)
+            .service(controller::challenge_controller::link_status)
+            .service(controller::challenge_controller::link_events)
+            .service(controller::mfa_controller::totp_enrollment)
+            .service(controller::mfa_controller::totp_confirm)
+            .service(controller::mfa_controller::totp_verify)
+            .service(controller::mfa_controller::recovery_codes)
+            .service(controller::mfa_controller::recovery_regenerate)
+            .service(controller::mfa_controller::recovery_verify)
+            .service(controller::webauthn_controller::registration_options)
+            .service(controller::webauthn_controller::registration)
+            .service(controller::webauthn_controller::assertion_options)
+            .service(controller::webauthn_controller::assertion)
+            .service(controller::token_controller::token)
+            .service(controller::token_controller::introspect)
+            .service(controller::token_controller::revoke)
+            .service(controller::token_controller::pushed_authorize)
+            .service(controller::admin_controller::unlock)
+            .service(controller::admin_controller::provision_secret)
     })
-    .bind((
+    .on_connect(tls::on_connect);
+    let address = (
         env_var::<String>("SERVER_HOST"),
         env_var::<u16>("SERVER_PORT"),
-    ))?
+    );
+    match tls::acceptor_from_env()? {
+        Some(acceptor) => server.bind_openssl(address, acceptor)?,
+        None => server.bind(address)?,
+    }
     .run()
     .await
 }
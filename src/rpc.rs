@@ -1,3 +1,5 @@
 pub mod client_rpc;
+pub mod mfa_rpc;
 pub mod password_rpc;
 pub mod user_rpc;
+pub mod webauthn_rpc;
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use anyhow::Context;
 use http::{Method, StatusCode};
-use reqwest::Request;
+use reqwest::{Request, Response};
 
 use crate::{
     common::{
@@ -12,7 +12,7 @@ use crate::{
     },
     dto::{
         challenge::ChallengeCofig,
-        client::{ClientConfig, ClientIdpConfig, ClientIdpConfigs},
+        client::{ClientConfig, ClientIdpConfig, ClientIdpConfigs, ClientSecret},
     },
     service::connection::IdpType,
 };
@@ -91,3 +91,14 @@ pub async fn fetch_challenge_config(client_id: &str) -> Result<Option<ChallengeC
         Some(resp.json::<ChallengeCofig>().await?)
     })
 }
+
+// 整体替换客户端的 secret 列表，forum-server 只保存摘要与加密后的原文
+pub async fn save_client_secrets(client_id: &str, secrets: &[ClientSecret]) -> Result<()> {
+    WEB_CLIENT
+        .put(nacos::rpc(format!("http://forum-server/clients/{client_id}/secrets").as_str()).await?)
+        .json(secrets)
+        .send()
+        .await
+        .and_then(Response::error_for_status)?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use anyhow::Context;
+use http::StatusCode;
+use reqwest::Response;
+
+use crate::{
+    common::{client::WEB_CLIENT, errors::Result, nacos},
+    dto::mfa::{RecoveryCodes, TotpCredential},
+};
+
+pub async fn fetch_totp(openid: &str) -> Result<Option<TotpCredential>> {
+    let resp = WEB_CLIENT
+        .get(nacos::rpc(format!("http://forum-server/mfa/{openid}/totp").as_str()).await?)
+        .send()
+        .await?;
+    if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        Ok(None)
+    } else {
+        Ok(Some(
+            resp.error_for_status()?
+                .json::<TotpCredential>()
+                .await
+                .with_context(|| {
+                    let msg = format!("fetch totp credential failed, openid: {openid}");
+                    tracing::error!(msg);
+                    msg
+                })?,
+        ))
+    }
+}
+
+pub async fn save_totp(credential: &TotpCredential) -> Result<()> {
+    WEB_CLIENT
+        .post(nacos::rpc("http://forum-server/mfa/totp").await?)
+        .json(credential)
+        .send()
+        .await
+        .and_then(Response::error_for_status)?;
+    Ok(())
+}
+
+pub async fn fetch_recovery_codes(openid: &str) -> Result<Option<RecoveryCodes>> {
+    let resp = WEB_CLIENT
+        .get(nacos::rpc(format!("http://forum-server/mfa/{openid}/recovery").as_str()).await?)
+        .send()
+        .await?;
+    if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        Ok(None)
+    } else {
+        Ok(Some(
+            resp.error_for_status()?
+                .json::<RecoveryCodes>()
+                .await
+                .with_context(|| {
+                    let msg = format!("fetch recovery codes failed, openid: {openid}");
+                    tracing::error!(msg);
+                    msg
+                })?,
+        ))
+    }
+}
+
+// 覆盖保存，旧的恢复码全部失效
+pub async fn save_recovery_codes(codes: &RecoveryCodes) -> Result<()> {
+    WEB_CLIENT
+        .put(nacos::rpc("http://forum-server/mfa/recovery").await?)
+        .json(codes)
+        .send()
+        .await
+        .and_then(Response::error_for_status)?;
+    Ok(())
+}
+
+// 由 forum-server 原子删除，已被使用的恢复码返回 false
+pub async fn burn_recovery_code(openid: &str, hash: &str) -> Result<bool> {
+    let resp = WEB_CLIENT
+        .delete(
+            nacos::rpc(format!("http://forum-server/mfa/{openid}/recovery/{hash}").as_str())
+                .await?,
+        )
+        .send()
+        .await?;
+    if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        Ok(false)
+    } else {
+        resp.error_for_status()?;
+        Ok(true)
+    }
+}
@@ -4,7 +4,7 @@ use tracing::info;
 
 use crate::{
     common::{client::WEB_CLIENT, constant::FORUM_SERVER, errors::Result, nacos::rpc},
-    dto::user::SubjectProfile,
+    dto::user::{SubjectProfile, UserProfile},
 };
 
 pub async fn registry() -> Result<()> {
@@ -40,3 +40,21 @@ pub async fn get_user_associations(openid: &str, idp: bool) -> Result<Option<Sub
         Some(resp.error_for_status()?.json::<SubjectProfile>().await?)
     })
 }
+
+pub async fn get_user_profile(identifier: &str) -> Result<Option<UserProfile>> {
+    let resp = RequestBuilder::from_parts(
+        WEB_CLIENT.clone(),
+        Request::new(
+            Method::GET,
+            rpc(format!("http://{FORUM_SERVER}/users/{identifier}").as_str()).await?,
+        ),
+    )
+    .send()
+    .await?;
+    Ok(if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        info!("用户不存在 identifier: {}", identifier);
+        None
+    } else {
+        Some(resp.error_for_status()?.json::<UserProfile>().await?)
+    })
+}
@@ -0,0 +1,76 @@
+use anyhow::Context;
+use http::StatusCode;
+use reqwest::Response;
+use serde_json::json;
+
+use crate::{
+    common::{client::WEB_CLIENT, errors::Result, nacos},
+    dto::webauthn::WebauthnCredential,
+};
+
+pub async fn fetch_credentials(openid: &str) -> Result<Vec<WebauthnCredential>> {
+    let resp = WEB_CLIENT
+        .get(nacos::rpc(format!("http://forum-server/mfa/{openid}/webauthn").as_str()).await?)
+        .send()
+        .await?;
+    if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        Ok(Vec::new())
+    } else {
+        Ok(resp
+            .error_for_status()?
+            .json::<Vec<WebauthnCredential>>()
+            .await
+            .with_context(|| {
+                let msg = format!("fetch webauthn credentials failed, openid: {openid}");
+                tracing::error!(msg);
+                msg
+            })?)
+    }
+}
+
+pub async fn fetch_credential(credential_id: &str) -> Result<Option<WebauthnCredential>> {
+    let resp = WEB_CLIENT
+        .get(
+            nacos::rpc(format!("http://forum-server/mfa/webauthn/{credential_id}").as_str())
+                .await?,
+        )
+        .send()
+        .await?;
+    if StatusCode::NOT_FOUND.eq(&resp.status()) {
+        Ok(None)
+    } else {
+        Ok(Some(
+            resp.error_for_status()?
+                .json::<WebauthnCredential>()
+                .await
+                .with_context(|| {
+                    let msg = format!("fetch webauthn credential failed: {credential_id}");
+                    tracing::error!(msg);
+                    msg
+                })?,
+        ))
+    }
+}
+
+pub async fn save_credential(credential: &WebauthnCredential) -> Result<()> {
+    WEB_CLIENT
+        .post(nacos::rpc("http://forum-server/mfa/webauthn").await?)
+        .json(credential)
+        .send()
+        .await
+        .and_then(Response::error_for_status)?;
+    Ok(())
+}
+
+pub async fn update_sign_count(credential_id: &str, sign_count: u32) -> Result<()> {
+    WEB_CLIENT
+        .patch(
+            nacos::rpc(format!("http://forum-server/mfa/webauthn/{credential_id}").as_str())
+                .await?,
+        )
+        .json(&json!({ "sign_count": sign_count }))
+        .send()
+        .await
+        .and_then(Response::error_for_status)?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
 pub mod auth_service;
+pub mod mfa_service;
 pub mod sms_service;
+pub mod throttle_service;
 pub mod user_service;
+pub mod webauthn_service;
 
 pub mod challenge_service;
+pub mod client_service;
 pub mod connection;
+pub mod dpop_service;
 pub mod token_service;
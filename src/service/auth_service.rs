@@ -1,7 +1,20 @@
 use std::collections::HashMap;
 
+use chrono::Duration;
+
 use super::connection::{self};
-use crate::{common::errors::Result, dto::auth::Flow};
+use crate::{
+    common::{
+        cache::redis::{redis_getdel, redis_setex},
+        config::env_var_default,
+        errors::{ApiError, Result},
+        utils::gen_id,
+    },
+    dto::{
+        auth::{AuthError, AuthRequest, AuthorizationError, Flow},
+        client::ClientConfig,
+    },
+};
 
 // 生成 idp 认证链接
 pub async fn build_idp(flow: &Flow) -> Result<HashMap<String, String>> {
@@ -18,3 +31,66 @@ pub async fn build_idp(flow: &Flow) -> Result<HashMap<String, String>> {
     }
     Ok(idp_links.clone())
 }
+
+pub const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+fn pushed_key(id: &str) -> String {
+    format!("forum:auth:par:{id}")
+}
+
+fn pushed_expires_in() -> Duration {
+    Duration::seconds(env_var_default::<i64>("PAR_EXPIRES", 90))
+}
+
+// 推送的授权请求在此完成校验，错误直接返回给客户端而不是回跳
+// https://datatracker.ietf.org/doc/html/rfc9126#section-2.1
+pub async fn push_request(
+    client: &ClientConfig,
+    mut params: HashMap<String, String>,
+) -> Result<(String, i64)> {
+    if params.contains_key("request_uri") {
+        return Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            "request_uri is not allowed in pushed authorization requests",
+        )
+        .into());
+    }
+    params.insert("client_id".to_string(), client.client_id.clone());
+    let mut flow = Flow::new(AuthRequest::from_params(params)?);
+    flow.with_client(client.clone());
+    flow.verify_redirect_uri()?;
+    flow.validate().map_err(|e| match e {
+        ApiError::Authorization(mut e) => {
+            e.redirect = None;
+            ApiError::Authorization(e)
+        }
+        e => e,
+    })?;
+    let id = gen_id(32);
+    let expires_in = pushed_expires_in();
+    redis_setex(pushed_key(&id).as_str(), &flow.request, expires_in).await?;
+    Ok((
+        format!("{REQUEST_URI_PREFIX}{id}"),
+        expires_in.num_seconds(),
+    ))
+}
+
+// request_uri 只能使用一次，且必须由同一个客户端发起
+pub async fn pushed_request(client_id: &str, request_uri: &str) -> Result<AuthRequest> {
+    let invalid = || {
+        AuthorizationError::local(
+            AuthError::InvalidRequestUri,
+            "request_uri is invalid or expired",
+        )
+    };
+    let id = request_uri
+        .strip_prefix(REQUEST_URI_PREFIX)
+        .ok_or_else(invalid)?;
+    let request = redis_getdel::<AuthRequest>(pushed_key(id).as_str())
+        .await?
+        .ok_or_else(invalid)?;
+    if request.client_id != client_id {
+        return Err(invalid().into());
+    }
+    Ok(request)
+}
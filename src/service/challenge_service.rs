@@ -1,9 +1,220 @@
-use crate::common::errors::Result;
+use actix_web::error::{ErrorBadRequest, ErrorTooManyRequests, ErrorUnauthorized};
+use chrono::{DateTime, Duration, Utc};
+use ring::constant_time::verify_slices_are_equal;
+use tera::Context;
 
-pub async fn code_challenge() -> Result<()> {
-    Ok(())
+use crate::{
+    common::{
+        cache::{
+            moka,
+            redis::{redis_del, redis_get, redis_getdel, redis_incr, redis_setex},
+        },
+        config::{env_var, env_var_default},
+        constant::{AMR_EMAIL, EMAIL_CODE_TEMPLATE, EMAIL_LINK_TEMPLATE},
+        errors::{ApiError, Result},
+        utils::{encode64url, gen_digits, gen_id, hmac_sha256, sha256},
+    },
+    dto::{
+        auth::{load_flow, persist_flow, Flow, FlowStage},
+        challenge::{CodeChallenge, LinkChallenge, LinkStatus},
+    },
+    rpc::user_rpc,
+    service::{mfa_service, sms_service},
+};
+
+const CODE_LENGTH: usize = 6;
+const CODE_MAX_ATTEMPTS: i64 = 5;
+
+fn code_key(flow: &Flow) -> String {
+    format!("forum:auth:challenge:code:{}", flow.id)
+}
+
+fn code_expires() -> Duration {
+    Duration::seconds(env_var_default::<i64>("CHALLENGE_CODE_EXPIRES", 300))
+}
+
+// 生成验证码，只保存摘要并通过邮件发送明文
+pub async fn code_challenge(flow: &Flow, identifier: &str) -> Result<()> {
+    let code = gen_digits(CODE_LENGTH);
+    let key = code_key(flow);
+    redis_setex(
+        key.as_str(),
+        CodeChallenge::new(identifier, &code),
+        code_expires(),
+    )
+    .await?;
+    // 重新发送验证码时重置尝试次数
+    redis_del(format!("{key}:attempts").as_str()).await?;
+
+    let mut context = Context::new();
+    context.insert("code", &code);
+    context.insert("expires_in", &code_expires().num_minutes());
+    sms_service::send_msg(EMAIL_CODE_TEMPLATE, identifier, context).await
+}
+
+// 校验验证码，成功后 flow 进入已认证阶段
+pub async fn verify_code(flow: &mut Flow, identifier: &str, proof: &str) -> Result<()> {
+    let key = code_key(flow);
+    let challenge = redis_get::<CodeChallenge>(key.as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized(
+            "challenge is expired",
+        )))?;
+
+    let attempts = redis_incr(format!("{key}:attempts").as_str(), code_expires()).await?;
+    if attempts > CODE_MAX_ATTEMPTS {
+        redis_del(key.as_str()).await?;
+        return Err(ApiError::Response(ErrorTooManyRequests(
+            "too many attempts, request a new code",
+        )));
+    }
+
+    if !challenge.verify(identifier, proof) {
+        tracing::warn!("email code verify failed, flow: {}", flow.id);
+        return Err(ApiError::Response(ErrorUnauthorized("invalid code")));
+    }
+    redis_del(key.as_str()).await?;
+    redis_del(format!("{key}:attempts").as_str()).await?;
+
+    flow.subject = Some(
+        user_rpc::get_user_profile(identifier)
+            .await?
+            .ok_or(ApiError::Response(ErrorUnauthorized("invalid_identifier")))?,
+    );
+    mfa_service::complete_factor(flow, AMR_EMAIL).await
+}
+
+fn link_key(nonce: &str) -> String {
+    format!(
+        "forum:auth:challenge:link:{}",
+        hex::encode(sha256(nonce.as_bytes()))
+    )
+}
+
+fn link_expires() -> Duration {
+    Duration::seconds(env_var_default::<i64>("CHALLENGE_LINK_EXPIRES", 600))
+}
+
+// 签名绑定 flow，防止伪造或把链接挪用到其他 flow
+fn sign_link(flow_id: &str, nonce: &str) -> String {
+    encode64url(&hmac_sha256(
+        env_var::<String>("CHALLENGE_LINK_SECRET").as_bytes(),
+        format!("{flow_id}.{nonce}").as_bytes(),
+    ))
+}
+
+fn split_link(token: &str) -> Result<(&str, &str)> {
+    token
+        .split_once('.')
+        .ok_or(ApiError::Response(ErrorBadRequest("malformed link")))
+}
+
+// 保存一次性登录链接并返回 token，同一 flow 重新发送时旧链接作废
+pub async fn store_link(challenge: &LinkChallenge) -> Result<String> {
+    let nonce = gen_id(32);
+    let flow_key = format!("forum:auth:challenge:link:flow:{}", challenge.flow_id);
+    if let Some(previous) = redis_get::<String>(flow_key.as_str()).await? {
+        redis_del(link_key(&previous).as_str()).await?;
+    }
+    redis_setex(link_key(&nonce).as_str(), challenge, link_expires()).await?;
+    redis_setex(flow_key.as_str(), &nonce, link_expires()).await?;
+    Ok(format!(
+        "{}.{}",
+        nonce,
+        sign_link(&challenge.flow_id, &nonce)
+    ))
+}
+
+// 发送一次性登录链接
+pub async fn link_challenge(flow: &Flow, identifier: &str, ip: &str) -> Result<()> {
+    let client_name = match moka::get_client_config(&flow.request.client_id).await? {
+        Some(client) if !client.name.is_empty() => client.name,
+        _ => flow.request.client_id.clone(),
+    };
+    let now = Utc::now();
+    let token = store_link(&LinkChallenge {
+        flow_id: flow.id.clone(),
+        identifier: identifier.to_string(),
+        client_name,
+        ip: ip.to_string(),
+        requested_at: now,
+        expires_at: now + link_expires(),
+    })
+    .await?;
+    let mut context = Context::new();
+    context.insert(
+        "link",
+        &format!(
+            "{}?token={}",
+            env_var_default::<String>(
+                "CHALLENGE_LINK_ENDPOINT",
+                "https://auth.heliannuuthus.com/api/challenge/link".to_string()
+            ),
+            token
+        ),
+    );
+    context.insert("expires_in", &link_expires().num_minutes());
+    sms_service::send_msg(EMAIL_LINK_TEMPLATE, identifier, context).await
+}
+
+// 校验签名与有效期，Redis 过期之外再以 expires_at 兜底
+pub fn verify_link(
+    challenge: Option<LinkChallenge>,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<LinkChallenge> {
+    let (nonce, signature) = split_link(token)?;
+    let challenge = challenge
+        .filter(|challenge| challenge.expires_at > now)
+        .ok_or(ApiError::Response(ErrorUnauthorized("link is expired")))?;
+    verify_slices_are_equal(
+        sign_link(&challenge.flow_id, nonce).as_bytes(),
+        signature.as_bytes(),
+    )
+    .map_err(|_| {
+        tracing::warn!("link signature mismatched, flow: {}", challenge.flow_id);
+        ApiError::Response(ErrorUnauthorized("invalid link"))
+    })?;
+    Ok(challenge)
+}
+
+// 只校验链接，不消费。邮件扫描器预取 GET 请求时链接仍然有效
+pub async fn inspect_link(token: &str) -> Result<LinkChallenge> {
+    let (nonce, _) = split_link(token)?;
+    let challenge = redis_get::<LinkChallenge>(link_key(nonce).as_str()).await?;
+    verify_link(challenge, token, Utc::now())
+}
+
+// 校验通过后原子地删除链接，并发确认时只有一个请求成功
+pub async fn take_link(token: &str) -> Result<LinkChallenge> {
+    inspect_link(token).await?;
+    let (nonce, _) = split_link(token)?;
+    redis_getdel::<LinkChallenge>(link_key(nonce).as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized("link is consumed")))
+}
+
+// 消费链接并完成发起设备上的 flow 认证
+pub async fn consume_link(token: &str) -> Result<Flow> {
+    let challenge = take_link(token).await?;
+    let mut flow = load_flow(&challenge.flow_id).await?;
+    flow.subject = Some(
+        user_rpc::get_user_profile(&challenge.identifier)
+            .await?
+            .ok_or(ApiError::Response(ErrorUnauthorized("invalid_identifier")))?,
+    );
+    mfa_service::complete_factor(&mut flow, AMR_EMAIL).await?;
+    persist_flow(&flow).await?;
+    Ok(flow)
 }
 
-pub async fn link_challenge() -> Result<()> {
-    Ok(())
+pub fn link_status(flow: &Flow) -> LinkStatus {
+    let authenticated = matches!(
+        flow.stage,
+        FlowStage::Authenticated | FlowStage::Authorized | FlowStage::Completed
+    );
+    LinkStatus {
+        authenticated,
+        next_uri: authenticated.then(|| flow.next_uri()),
+    }
 }
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorNotFound},
+    http::header,
+    HttpRequest,
+};
+use chrono::{Duration, Utc};
+
+use crate::{
+    common::{
+        cache::{moka, redis::redis_setnx},
+        config::{auth_server_endpoint, env_var_default},
+        errors::{ApiError, Result},
+        jwt::issuer,
+        tls::PeerCertificate,
+        utils::gen_id,
+    },
+    dto::{
+        auth::{AuthError, AuthorizationError},
+        client::{
+            ClientConfig, ClientCredentials, ClientSecret, TokenEndpointAuthMethod,
+            MAX_ACTIVE_SECRETS,
+        },
+    },
+    rpc::client_rpc,
+};
+
+// 断言的 aud 可以是 issuer、token 端点或当前端点
+// https://datatracker.ietf.org/doc/html/rfc7523#section-3
+fn audiences(client_id: &str, path: &str) -> Vec<String> {
+    let endpoint = auth_server_endpoint();
+    vec![
+        issuer(client_id),
+        format!("{endpoint}/token"),
+        format!("{endpoint}{path}"),
+    ]
+}
+
+// 认证 token、introspection、revocation 与 PAR 端点的调用方
+pub async fn authenticate(
+    req: &HttpRequest,
+    params: &HashMap<String, String>,
+) -> Result<ClientConfig> {
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let credentials = ClientCredentials::extract(authorization, params)?;
+    let client = moka::get_client_config(credentials.client_id())
+        .await?
+        .ok_or_else(|| AuthorizationError::local(AuthError::InvalidClient, "unknown client"))?;
+    let audiences = audiences(&client.client_id, req.path());
+    if let Some(claims) =
+        credentials.verify(&client, &audiences, req.conn_data::<PeerCertificate>())?
+    {
+        // 断言只能使用一次，jti 保留到断言过期
+        let expires_in =
+            Duration::seconds((claims.exp.timestamp() - Utc::now().timestamp()).max(1));
+        let key = format!("forum:auth:client:jti:{}:{}", client.client_id, claims.jti);
+        if !redis_setnx(key.as_str(), claims.exp.timestamp(), expires_in).await? {
+            tracing::warn!("client assertion replayed: {}", client.client_id);
+            return Err(AuthorizationError::local(
+                AuthError::InvalidClient,
+                "client assertion is replayed",
+            )
+            .into());
+        }
+    }
+    Ok(client)
+}
+
+fn rotation_grace() -> Duration {
+    Duration::seconds(env_var_default::<i64>(
+        "CLIENT_SECRET_ROTATION_GRACE",
+        604800,
+    ))
+}
+
+// 客户端配置由 forum-server 保存
+#[async_trait::async_trait(?Send)]
+pub trait ClientSecretStore {
+    async fn fetch_client(&self, client_id: &str) -> Result<Option<ClientConfig>>;
+    async fn save_secrets(&self, client_id: &str, secrets: &[ClientSecret]) -> Result<()>;
+}
+
+pub struct RemoteSecretStore;
+
+#[async_trait::async_trait(?Send)]
+impl ClientSecretStore for RemoteSecretStore {
+    // 绕过缓存读取最新的 secret 列表
+    async fn fetch_client(&self, client_id: &str) -> Result<Option<ClientConfig>> {
+        client_rpc::fetch_client_config(client_id).await
+    }
+
+    async fn save_secrets(&self, client_id: &str, secrets: &[ClientSecret]) -> Result<()> {
+        client_rpc::save_client_secrets(client_id, secrets).await?;
+        moka::invalidate_client_config(client_id).await;
+        Ok(())
+    }
+}
+
+// 签发新 secret 并保存，仍有效的旧 secret 在宽限期后过期，已过期的被清理，
+// 有效的 secret 不超过 MAX_ACTIVE_SECRETS 个。明文只返回这一次
+pub async fn rotate_secret(store: &impl ClientSecretStore, client_id: &str) -> Result<String> {
+    let client = store
+        .fetch_client(client_id)
+        .await?
+        .ok_or(ApiError::Response(ErrorNotFound("client is nonexistent")))?;
+    let method = client.token_endpoint_auth_method;
+    if !matches!(
+        method,
+        TokenEndpointAuthMethod::ClientSecretBasic
+            | TokenEndpointAuthMethod::ClientSecretPost
+            | TokenEndpointAuthMethod::ClientSecretJwt
+    ) {
+        return Err(ApiError::Response(ErrorBadRequest(
+            "client does not authenticate with a secret",
+        )));
+    }
+    let plaintext = gen_id(43);
+    let mut secret = ClientSecret::hash(&plaintext, None);
+    if method == TokenEndpointAuthMethod::ClientSecretJwt {
+        secret = secret.seal(&plaintext)?;
+    }
+    let retire_at = Utc::now() + rotation_grace();
+    let previous = client
+        .active_secrets()
+        .take(MAX_ACTIVE_SECRETS - 1)
+        .cloned()
+        .map(|mut previous| {
+            previous.expires_at = Some(
+                previous
+                    .expires_at
+                    .map_or(retire_at, |at| at.min(retire_at)),
+            );
+            previous
+        });
+    let secrets = std::iter::once(secret).chain(previous).collect::<Vec<_>>();
+    store.save_secrets(client_id, &secrets).await?;
+    Ok(plaintext)
+}
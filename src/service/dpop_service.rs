@@ -0,0 +1,102 @@
+use actix_web::HttpRequest;
+use chrono::{Duration, Utc};
+
+use crate::{
+    common::{
+        cache::redis::{redis_get, redis_setex, redis_setnx},
+        config::{auth_server_endpoint, env_var_default},
+        constant::DPOP_HEADER,
+        dpop::{self, DpopProof},
+        errors::Result,
+        utils::gen_id,
+    },
+    dto::{
+        auth::{AuthError, AuthorizationError},
+        client::ClientConfig,
+    },
+};
+
+fn nonce_key(nonce: &str) -> String {
+    format!("forum:auth:dpop:nonce:{nonce}")
+}
+
+fn jti_key(jkt: &str, jti: &str) -> String {
+    format!("forum:auth:dpop:jti:{jkt}:{jti}")
+}
+
+fn proof_window() -> i64 {
+    env_var_default::<i64>("DPOP_PROOF_WINDOW", 300)
+}
+
+fn nonce_required() -> bool {
+    env_var_default::<bool>("DPOP_NONCE_REQUIRED", false)
+}
+
+fn invalid_proof(description: &str) -> AuthorizationError {
+    AuthorizationError::local(AuthError::InvalidDpopProof, description)
+}
+
+// 开启 DPOP_NONCE_REQUIRED 时下发新的 nonce，有效期内可以重复使用
+// https://datatracker.ietf.org/doc/html/rfc9449#section-8
+pub async fn issue_nonce() -> Result<Option<String>> {
+    if !nonce_required() {
+        return Ok(None);
+    }
+    let nonce = gen_id(32);
+    redis_setex(
+        nonce_key(&nonce).as_str(),
+        Utc::now().timestamp(),
+        Duration::seconds(env_var_default::<i64>("DPOP_NONCE_EXPIRES", 300)),
+    )
+    .await?;
+    Ok(Some(nonce))
+}
+
+// 请求未携带 proof 且客户端不要求 DPoP 时返回 None
+pub async fn verify(req: &HttpRequest, client: &ClientConfig) -> Result<Option<DpopProof>> {
+    let mut proofs = req.headers().get_all(DPOP_HEADER);
+    let proof = match (proofs.next(), proofs.next()) {
+        (None, _) if client.dpop_bound_access_tokens => {
+            return Err(invalid_proof("DPoP proof is required").into())
+        }
+        (None, _) => return Ok(None),
+        (Some(proof), None) => proof
+            .to_str()
+            .map_err(|_| invalid_proof("malformed DPoP proof"))?,
+        _ => return Err(invalid_proof("multiple DPoP proofs").into()),
+    };
+    let proof = dpop::verify_proof(
+        proof,
+        req.method().as_str(),
+        &format!("{}{}", auth_server_endpoint(), req.path()),
+        Utc::now().timestamp(),
+        proof_window(),
+    )?;
+    if nonce_required() {
+        let fresh = match &proof.nonce {
+            Some(nonce) => redis_get::<i64>(nonce_key(nonce).as_str()).await?.is_some(),
+            None => false,
+        };
+        if !fresh {
+            let nonce = issue_nonce().await?.unwrap_or_default();
+            return Err(AuthorizationError::local(
+                AuthError::UseDpopNonce,
+                "authorization server requires nonce in DPoP proof",
+            )
+            .with_dpop_nonce(nonce)
+            .into());
+        }
+    }
+    // proof 只能使用一次，jti 保留到超出校验窗口
+    if !redis_setnx(
+        jti_key(&proof.jkt, &proof.jti).as_str(),
+        proof.iat,
+        Duration::seconds(proof_window() * 2),
+    )
+    .await?
+    {
+        tracing::warn!("DPoP proof replayed: {}", client.client_id);
+        return Err(invalid_proof("DPoP proof is replayed").into());
+    }
+    Ok(Some(proof))
+}
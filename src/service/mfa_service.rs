@@ -0,0 +1,306 @@
+use actix_web::error::{ErrorBadRequest, ErrorNotFound, ErrorTooManyRequests, ErrorUnauthorized};
+use chrono::{Duration, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use tera::Context;
+
+use crate::{
+    common::{
+        cache::{
+            moka,
+            redis::{redis_del, redis_get, redis_getdel, redis_incr, redis_setex, redis_setnx},
+        },
+        config::env_var_default,
+        constant::{
+            AMR_EMAIL, AMR_OTP, MFA_REQUIRED_ROLES, RECOVERY_REGENERATED_TEMPLATE,
+            RECOVERY_USED_TEMPLATE,
+        },
+        errors::{ApiError, Result},
+        totp::Totp,
+        utils::{encode32, seal, unseal},
+    },
+    dto::{
+        auth::{Flow, FlowStage},
+        mfa::{RecoveryCodes, RecoveryCodesResponse, TotpCredential, TotpEnrollment},
+        user::UserProfile,
+    },
+    rpc::{mfa_rpc, webauthn_rpc},
+    service::sms_service,
+};
+
+const TOTP_SECRET_LENGTH: usize = 20;
+// 允许前后各一个周期的时钟偏差
+const TOTP_SKEW: u64 = 1;
+const TOTP_MAX_ATTEMPTS: i64 = 5;
+const RECOVERY_MAX_ATTEMPTS: i64 = 5;
+
+// MFA_SECRET_KEY 为 hex 编码的 32 字节 AES-256-GCM 密钥，启动时先校验一次
+pub fn secret_key() -> Result<Vec<u8>> {
+    std::env::var("MFA_SECRET_KEY")
+        .ok()
+        .and_then(|key| hex::decode(key).ok())
+        .filter(|key| key.len() == 32)
+        .ok_or_else(|| {
+            ApiError::Internal(anyhow::anyhow!(
+                "MFA_SECRET_KEY must be a hex encoded 32 bytes key"
+            ))
+        })
+}
+
+pub fn subject(flow: &Flow) -> Result<&UserProfile> {
+    flow.subject
+        .as_ref()
+        .ok_or(ApiError::Response(ErrorUnauthorized("login_required")))
+}
+
+// client 强制、特权角色或用户已绑定第二因子时需要多因子认证
+pub async fn mfa_required(flow: &Flow) -> Result<bool> {
+    if moka::get_client_config(&flow.request.client_id)
+        .await?
+        .map(|config| config.mfa_required)
+        .unwrap_or_default()
+    {
+        return Ok(true);
+    }
+    let subject = match &flow.subject {
+        Some(subject) => subject,
+        None => return Ok(false),
+    };
+    if subject
+        .roles
+        .iter()
+        .any(|role| MFA_REQUIRED_ROLES.contains(&role.as_str()))
+    {
+        return Ok(true);
+    }
+    enrolled(&subject.openid).await
+}
+
+// 用户是否已绑定任意第二因子
+pub async fn enrolled(openid: &str) -> Result<bool> {
+    Ok(mfa_rpc::fetch_totp(openid).await?.is_some()
+        || !webauthn_rpc::fetch_credentials(openid).await?.is_empty())
+}
+
+// 完成一个认证因子，决定 flow 是否还需要第二因子
+pub async fn complete_factor(flow: &mut Flow, method: &str) -> Result<()> {
+    let required = mfa_required(flow).await?;
+    flow.authenticated(method, required);
+    Ok(())
+}
+
+fn enrollment_key(flow: &Flow) -> String {
+    format!("forum:auth:mfa:totp:enrollment:{}", flow.id)
+}
+
+// 已完成认证时允许绑定。需要多因子但尚未绑定任何因子时，只凭密码不能自行绑定，
+// 必须先通过邮箱验证码证明对账号的控制，否则知道密码即可绕过多因子认证
+pub fn enrollment_allowed(flow: &Flow, enrolled: bool) -> bool {
+    match flow.stage {
+        FlowStage::Authenticated => true,
+        FlowStage::MultiFactor => !enrolled && flow.amr.iter().any(|m| m == AMR_EMAIL),
+        _ => false,
+    }
+}
+
+pub async fn enrollable(flow: &Flow) -> Result<()> {
+    let subject = subject(flow)?;
+    let enrolled = match flow.stage {
+        FlowStage::MultiFactor => enrolled(&subject.openid).await?,
+        _ => false,
+    };
+    if !enrollment_allowed(flow, enrolled) {
+        return Err(ApiError::Response(ErrorUnauthorized(
+            "email verification is required before enrollment",
+        )));
+    }
+    Ok(())
+}
+
+pub async fn enroll_totp(flow: &Flow) -> Result<TotpEnrollment> {
+    enrollable(flow).await?;
+    let subject = subject(flow)?;
+    let mut secret = vec![0u8; TOTP_SECRET_LENGTH];
+    SystemRandom::new().fill(&mut secret).unwrap();
+    let totp = Totp::new(secret.clone());
+
+    redis_setex(
+        enrollment_key(flow).as_str(),
+        TotpCredential {
+            openid: subject.openid.clone(),
+            secret: seal(&secret_key()?, &secret)?,
+        },
+        Duration::minutes(10),
+    )
+    .await?;
+    let uri = totp.otpauth_uri(
+        &env_var_default::<String>("TOTP_ISSUER", "heliannuuthus".to_string()),
+        subject.email.as_deref().unwrap_or(&subject.openid),
+    );
+    Ok(TotpEnrollment {
+        uri,
+        secret: encode32(&secret),
+    })
+}
+
+// 用户输入一次验证码确认绑定。绑定不计为第二因子，MultiFactor 阶段仍需再完成一次验证
+pub async fn confirm_totp(flow: &Flow, code: &str) -> Result<()> {
+    enrollable(flow).await?;
+    let key = enrollment_key(flow);
+    let credential = redis_get::<TotpCredential>(key.as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorBadRequest("enrollment is expired")))?;
+    check_totp(flow, &credential, code).await?;
+    mfa_rpc::save_totp(&credential).await?;
+    redis_del(key.as_str()).await?;
+    issue_recovery_codes(flow).await
+}
+
+pub async fn verify_totp(flow: &mut Flow, code: &str) -> Result<()> {
+    if !matches!(flow.stage, FlowStage::MultiFactor) {
+        return Err(ApiError::Response(ErrorBadRequest("mfa is not required")));
+    }
+    let credential = mfa_rpc::fetch_totp(&subject(flow)?.openid)
+        .await?
+        .ok_or(ApiError::Response(ErrorBadRequest("totp is not enrolled")))?;
+    check_totp(flow, &credential, code).await?;
+    flow.authenticated(AMR_OTP, true);
+    Ok(())
+}
+
+async fn check_totp(flow: &Flow, credential: &TotpCredential, code: &str) -> Result<()> {
+    let attempts = redis_incr(
+        format!("forum:auth:mfa:totp:attempts:{}", flow.id).as_str(),
+        Duration::minutes(10),
+    )
+    .await?;
+    if attempts > TOTP_MAX_ATTEMPTS {
+        return Err(ApiError::Response(ErrorTooManyRequests(
+            "too many attempts",
+        )));
+    }
+
+    let totp = Totp::new(unseal(&secret_key()?, &credential.secret)?);
+    let counter = totp
+        .verify(code, Utc::now().timestamp() as u64, TOTP_SKEW)
+        .ok_or_else(|| {
+            tracing::warn!("totp verify failed, flow: {}", flow.id);
+            ApiError::Response(ErrorUnauthorized("invalid code"))
+        })?;
+    // 同一个验证码在有效窗口内只能使用一次
+    let fresh = redis_setnx(
+        format!("forum:auth:mfa:totp:used:{}:{}", credential.openid, counter).as_str(),
+        1,
+        Duration::seconds((totp.period * (2 * TOTP_SKEW + 1)) as i64),
+    )
+    .await?;
+    if !fresh {
+        tracing::warn!("totp code replayed, flow: {}", flow.id);
+        return Err(ApiError::Response(ErrorUnauthorized("invalid code")));
+    }
+    Ok(())
+}
+
+fn issued_key(flow: &Flow) -> String {
+    format!("forum:auth:mfa:recovery:issued:{}", flow.id)
+}
+
+async fn generate_recovery_codes(openid: &str) -> Result<Vec<String>> {
+    let (recovery_codes, codes) = RecoveryCodes::generate(openid, &secret_key()?);
+    mfa_rpc::save_recovery_codes(&recovery_codes).await?;
+    Ok(codes)
+}
+
+// 通知发送失败不影响认证结果
+async fn notify(subject: &UserProfile, template_id: &str, context: Context) {
+    if let Some(email) = &subject.email {
+        if let Err(e) = sms_service::send_msg(template_id, email, context).await {
+            tracing::error!(
+                "send recovery notification failed, openid: {}, {e}",
+                subject.openid
+            );
+        }
+    }
+}
+
+// 首次绑定第二因子时生成恢复码，明文加密暂存到用户查看为止
+pub async fn issue_recovery_codes(flow: &Flow) -> Result<()> {
+    let openid = &subject(flow)?.openid;
+    if mfa_rpc::fetch_recovery_codes(openid)
+        .await?
+        .map(|recovery_codes| !recovery_codes.hashes.is_empty())
+        .unwrap_or_default()
+    {
+        return Ok(());
+    }
+    let codes = generate_recovery_codes(openid).await?;
+    redis_setex(
+        issued_key(flow).as_str(),
+        seal(&secret_key()?, codes.join("\n").as_bytes())?,
+        Duration::minutes(10),
+    )
+    .await
+}
+
+// 恢复码只展示一次
+pub async fn issued_recovery_codes(flow: &Flow) -> Result<RecoveryCodesResponse> {
+    let sealed = redis_getdel::<String>(issued_key(flow).as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorNotFound(
+            "recovery codes are not available",
+        )))?;
+    let codes = String::from_utf8(unseal(&secret_key()?, &sealed)?).unwrap_or_default();
+    Ok(RecoveryCodesResponse {
+        codes: codes.lines().map(str::to_string).collect(),
+    })
+}
+
+// 重新生成要求本次登录已经完成多因子认证，旧的恢复码全部作废
+pub async fn regenerate_recovery_codes(flow: &Flow) -> Result<RecoveryCodesResponse> {
+    if !matches!(flow.stage, FlowStage::Authenticated) || flow.amr.len() < 2 {
+        return Err(ApiError::Response(ErrorUnauthorized("mfa_required")));
+    }
+    let subject = subject(flow)?;
+    if !enrolled(&subject.openid).await? {
+        return Err(ApiError::Response(ErrorBadRequest("mfa is not enrolled")));
+    }
+    let codes = generate_recovery_codes(&subject.openid).await?;
+    notify(subject, RECOVERY_REGENERATED_TEMPLATE, Context::new()).await;
+    Ok(RecoveryCodesResponse { codes })
+}
+
+// 恢复码作为第二因子的替代，使用后立即销毁
+pub async fn verify_recovery_code(flow: &mut Flow, code: &str) -> Result<()> {
+    if !matches!(flow.stage, FlowStage::MultiFactor) {
+        return Err(ApiError::Response(ErrorBadRequest("mfa is not required")));
+    }
+    let attempts = redis_incr(
+        format!("forum:auth:mfa:recovery:attempts:{}", flow.id).as_str(),
+        Duration::minutes(10),
+    )
+    .await?;
+    if attempts > RECOVERY_MAX_ATTEMPTS {
+        return Err(ApiError::Response(ErrorTooManyRequests(
+            "too many attempts",
+        )));
+    }
+
+    let subject = subject(flow)?.clone();
+    let recovery_codes = mfa_rpc::fetch_recovery_codes(&subject.openid)
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized("invalid code")))?;
+    let hash = recovery_codes.find(&secret_key()?, code).ok_or_else(|| {
+        tracing::warn!("recovery code verify failed, flow: {}", flow.id);
+        ApiError::Response(ErrorUnauthorized("invalid code"))
+    })?;
+    // 并发使用同一个恢复码时只有一个请求能够销毁成功
+    if !mfa_rpc::burn_recovery_code(&subject.openid, &hash).await? {
+        tracing::warn!("recovery code replayed, flow: {}", flow.id);
+        return Err(ApiError::Response(ErrorUnauthorized("invalid code")));
+    }
+
+    let mut context = Context::new();
+    context.insert("remaining", &(recovery_codes.hashes.len() - 1));
+    notify(&subject, RECOVERY_USED_TEMPLATE, context).await;
+    flow.authenticated(AMR_OTP, true);
+    Ok(())
+}
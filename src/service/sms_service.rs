@@ -4,6 +4,7 @@ use lettre::{
     transport::smtp::{authentication::Credentials, AsyncSmtpTransport},
     AsyncTransport, Message, Tokio1Executor,
 };
+use tera::Context as TemplateContext;
 
 use crate::{
     common::{
@@ -39,8 +40,12 @@ async fn get_sms_config(tempalte_id: &str) -> Result<SmsConfig> {
         .context("sms config deserialize failed")?)
 }
 
-pub async fn send_msg(template_id: &str) -> Result<()> {
+pub async fn send_msg(template_id: &str, receiver: &str, extra: TemplateContext) -> Result<()> {
     let mut context = get_sms_config(template_id).await.map(SmsContext::from)?;
+    context.receiver = receiver.to_string();
+    context.subject = context.title.clone();
+    context.context.insert("receiver", receiver);
+    context.context.extend(extra);
     let message = context
         .render()
         .context(format!("sms template reader error: {}", template_id))?;
@@ -55,7 +60,10 @@ pub async fn send_msg(template_id: &str) -> Result<()> {
         .send(
             Message::builder()
                 .from(env_var::<String>("SMTP_SENDER").parse().unwrap())
-                .to(context.receiver.parse().unwrap())
+                .to(context
+                    .receiver
+                    .parse()
+                    .context(format!("invalid receiver: {}", receiver))?)
                 .subject(context.subject)
                 .header(ContentType::TEXT_HTML)
                 .body(message)
@@ -0,0 +1,148 @@
+use std::future::Future;
+
+use chrono::{Duration, Utc};
+use http::StatusCode;
+
+use crate::{
+    common::{
+        cache::redis::{redis_del, redis_incr, redis_setex, redis_slide, redis_ttl},
+        config::env_var_default,
+        errors::{ApiError, Result},
+    },
+    dto::throttle::Attempt,
+};
+
+// 连续失败达到该次数后开始渐进延迟
+const DELAY_AFTER_FAILURES: i64 = 3;
+
+fn window() -> Duration {
+    Duration::seconds(env_var_default::<i64>("THROTTLE_WINDOW", 900))
+}
+
+fn lock_key(identifier: &str) -> String {
+    format!("forum:auth:throttle:lock:{identifier}")
+}
+
+fn delay_key(identifier: &str) -> String {
+    format!("forum:auth:throttle:delay:{identifier}")
+}
+
+fn failures_key(identifier: &str) -> String {
+    format!("forum:auth:throttle:failures:{identifier}")
+}
+
+// 第 DELAY_AFTER_FAILURES 次失败起每次翻倍，最长 max_delay 秒
+pub fn progressive_delay(failures: i64, max_delay: i64) -> Option<i64> {
+    if failures < DELAY_AFTER_FAILURES {
+        return None;
+    }
+    let exponent = (failures - DELAY_AFTER_FAILURES).min(32) as u32;
+    Some(2i64.saturating_pow(exponent).min(max_delay))
+}
+
+// 窗口中最早一次请求滑出窗口所需的秒数，向上取整
+pub fn retry_after(oldest: i64, now: i64, window: Duration) -> u64 {
+    let remaining = oldest + window.num_milliseconds() - now;
+    ((remaining.max(0) + 999) / 1000).max(1) as u64
+}
+
+// 账号被锁定或处于延迟期时拒绝
+async fn check_locked(attempt: &Attempt) -> Result<()> {
+    for key in [
+        lock_key(&attempt.identifier),
+        delay_key(&attempt.identifier),
+    ] {
+        if let Some(ttl) = redis_ttl(key.as_str()).await? {
+            return Err(ApiError::Throttled(ttl.max(1) as u64));
+        }
+    }
+    Ok(())
+}
+
+// 认证入口调用：检查锁定，并按账号、IP、client 的滑动窗口计数
+pub async fn check(attempt: &Attempt) -> Result<()> {
+    check_locked(attempt).await?;
+    let window = window();
+    let now = Utc::now().timestamp_millis();
+    for (dimension, value, limit) in [
+        (
+            "identifier",
+            &attempt.identifier,
+            env_var_default::<i64>("THROTTLE_IDENTIFIER_LIMIT", 10),
+        ),
+        (
+            "ip",
+            &attempt.ip,
+            env_var_default::<i64>("THROTTLE_IP_LIMIT", 100),
+        ),
+        (
+            "client",
+            &attempt.client_id,
+            env_var_default::<i64>("THROTTLE_CLIENT_LIMIT", 1000),
+        ),
+    ] {
+        let key = format!("forum:auth:throttle:{}:{dimension}:{value}", attempt.scope);
+        let (count, oldest) = redis_slide(key.as_str(), now, window).await?;
+        if count > limit {
+            tracing::warn!(
+                "{} attempts throttled by {dimension}: {value}",
+                attempt.scope
+            );
+            return Err(ApiError::Throttled(retry_after(oldest, now, window)));
+        }
+    }
+    Ok(())
+}
+
+// 包裹一次凭证校验：凭证错误（401）累计失败次数，成功后清零
+pub async fn guard<T, F>(attempt: &Attempt, verify: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    check_locked(attempt).await?;
+    match verify.await {
+        Ok(value) => {
+            succeeded(&attempt.identifier).await?;
+            Ok(value)
+        }
+        Err(e) => {
+            if actix_web::ResponseError::status_code(&e) == StatusCode::UNAUTHORIZED {
+                failed(attempt).await?;
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn failed(attempt: &Attempt) -> Result<()> {
+    let lockout = Duration::seconds(env_var_default::<i64>("LOCKOUT_DURATION", 900));
+    let failures = redis_incr(failures_key(&attempt.identifier).as_str(), lockout).await?;
+    if failures >= env_var_default::<i64>("LOCKOUT_THRESHOLD", 10) {
+        tracing::warn!(
+            "account locked after {failures} failures, {}",
+            attempt.scope
+        );
+        redis_setex(lock_key(&attempt.identifier).as_str(), failures, lockout).await?;
+    } else if let Some(delay) =
+        progressive_delay(failures, env_var_default::<i64>("THROTTLE_MAX_DELAY", 60))
+    {
+        redis_setex(
+            delay_key(&attempt.identifier).as_str(),
+            failures,
+            Duration::seconds(delay),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn succeeded(identifier: &str) -> Result<()> {
+    redis_del(failures_key(identifier).as_str()).await?;
+    redis_del(delay_key(identifier).as_str()).await
+}
+
+// 管理员解锁账号，同时清空失败计数
+pub async fn unlock(identifier: &str) -> Result<()> {
+    redis_del(lock_key(identifier).as_str()).await?;
+    succeeded(identifier).await
+}
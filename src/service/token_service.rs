@@ -1 +1,550 @@
-pub async fn generate_token() {}
+use std::collections::HashMap;
+
+use actix_web::error::ErrorUnauthorized;
+use chrono::{Duration, Utc};
+use ring::constant_time::verify_slices_are_equal;
+
+use crate::{
+    common::{
+        cache::redis::{redis_del, redis_get, redis_getdel, redis_setex},
+        config::env_var_default,
+        constant::{
+            ResponseType, TokenType, ACCESS_TOKEN_TYPE, JWT_TOKEN_TYPE, OFFLINE_ACCESS_SCOPE,
+            OPENID_SCOPE,
+        },
+        dpop::DpopProof,
+        errors::{ApiError, Result},
+        jwt::{self, AccessToken, Actor, Confirmation, ACCESS_TOKEN_JWT_TYPE},
+        tls::PeerCertificate,
+        utils::{encode64url, gen_id, sha256},
+    },
+    dto::{
+        auth::{
+            load_flow, persist_flow, token_expires_in, AuthError, AuthorizationCode,
+            AuthorizationError, Flow, FlowStage, RefreshToken, Tokens,
+        },
+        client::{ClientConfig, TokenEndpointAuthMethod},
+    },
+};
+
+fn revoked_key(openid: &str) -> String {
+    format!("forum:auth:token:revoked:{openid}")
+}
+
+fn code_key(code: &str) -> String {
+    format!("forum:auth:code:{code}")
+}
+
+fn jti_key(jti: &str) -> String {
+    format!("forum:auth:token:jti:{jti}")
+}
+
+fn refresh_key(token: &str) -> String {
+    format!("forum:auth:token:refresh:{token}")
+}
+
+fn refresh_token_expires_in() -> Duration {
+    Duration::seconds(env_var_default::<i64>("REFRESH_TOKEN_EXPIRES", 2592000))
+}
+
+fn invalid_grant(description: &str) -> AuthorizationError {
+    AuthorizationError::local(AuthError::InvalidGrant, description)
+}
+
+// 记录撤销时间，此前签发的会话和 refresh token 全部失效
+pub async fn revoke_all(openid: &str) -> Result<()> {
+    redis_setex(
+        revoked_key(openid).as_str(),
+        Utc::now().timestamp(),
+        refresh_token_expires_in(),
+    )
+    .await
+}
+
+// iat 精确到秒，与撤销同一秒签发的 token（包括修改密码后的新会话）仍然有效
+pub fn revoked_since(issued_at: i64, revoked_at: i64) -> bool {
+    issued_at < revoked_at
+}
+
+pub async fn revoked(openid: &str, issued_at: i64) -> Result<bool> {
+    Ok(redis_get::<i64>(revoked_key(openid).as_str())
+        .await?
+        .map(|revoked_at| revoked_since(issued_at, revoked_at))
+        .unwrap_or_default())
+}
+
+// 授权码指向 flow，兑换时一次性删除
+pub async fn issue_code(flow: &mut Flow) -> Result<()> {
+    let authorization_code = AuthorizationCode::new(gen_id(32), flow.request.state.clone());
+    redis_setex(
+        code_key(&authorization_code.code).as_str(),
+        &flow.id,
+        Duration::minutes(10),
+    )
+    .await?;
+    flow.authorization_code = Some(authorization_code);
+    Ok(())
+}
+
+// 用户同意授权后签发授权码，flow 进入 Authorized 阶段等待回跳客户端
+pub async fn grant(flow: &mut Flow) -> Result<()> {
+    if !matches!(flow.stage, FlowStage::Authenticated) {
+        return Err(ApiError::Response(ErrorUnauthorized("login_required")));
+    }
+    if flow
+        .hinted_subject
+        .as_ref()
+        .is_some_and(|hinted| flow.subject.as_ref().map(|s| &s.openid) != Some(hinted))
+    {
+        return Err(flow.fail(
+            AuthError::LoginRequired,
+            "authenticated user mismatched id_token_hint",
+        ));
+    }
+    if flow.request.response_type.contains(&ResponseType::Code) {
+        issue_code(flow).await?;
+    }
+    flow.stage = FlowStage::Authorized;
+    Ok(())
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7636#section-4.6
+pub fn verify_pkce(flow: &Flow, verifier: Option<&String>) -> bool {
+    let Some(challenge) = &flow.request.code_challenge else {
+        return verifier.is_none();
+    };
+    let Some(verifier) = verifier.filter(|v| (43..=128).contains(&v.len())) else {
+        return false;
+    };
+    let computed = match flow.request.code_challenge_method.as_deref() {
+        Some("S256") => encode64url(&sha256(verifier.as_bytes())),
+        None | Some("plain") => verifier.clone(),
+        Some(_) => return false,
+    };
+    verify_slices_are_equal(computed.as_bytes(), challenge.as_bytes()).is_ok()
+}
+
+// 注册了证书绑定的客户端必须在 TLS 握手中出示证书，携带 DPoP proof 时绑定到 jkt
+// https://datatracker.ietf.org/doc/html/rfc8705#section-3
+// https://datatracker.ietf.org/doc/html/rfc9449#section-6
+pub fn confirmation(
+    client: &ClientConfig,
+    certificate: Option<&PeerCertificate>,
+    dpop: Option<&DpopProof>,
+) -> Result<Option<Confirmation>> {
+    let x5t_s256 = match (
+        client.tls_client_certificate_bound_access_tokens,
+        certificate,
+    ) {
+        (false, _) => None,
+        (true, Some(certificate)) => Some(certificate.thumbprint.clone()),
+        (true, None) => {
+            return Err(AuthorizationError::local(
+                AuthError::InvalidRequest,
+                "client certificate is required for bound access tokens",
+            )
+            .into())
+        }
+    };
+    let jkt = dpop.map(|proof| proof.jkt.clone());
+    Ok((x5t_s256.is_some() || jkt.is_some()).then_some(Confirmation { x5t_s256, jkt }))
+}
+
+fn token_type(cnf: &Option<Confirmation>) -> TokenType {
+    match cnf.as_ref().and_then(|cnf| cnf.jkt.as_ref()) {
+        Some(_) => TokenType::DPoP,
+        None => TokenType::Bearer,
+    }
+}
+
+async fn issue_refresh_token(refresh_token: &RefreshToken) -> Result<String> {
+    let token = gen_id(43);
+    redis_setex(
+        refresh_key(&token).as_str(),
+        refresh_token,
+        refresh_token_expires_in(),
+    )
+    .await?;
+    Ok(token)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.3
+pub async fn exchange_code(
+    client: &ClientConfig,
+    params: &HashMap<String, String>,
+    cnf: Option<Confirmation>,
+) -> Result<Tokens> {
+    let code = params
+        .get("code")
+        .ok_or_else(|| AuthorizationError::local(AuthError::InvalidRequest, "code is lacked"))?;
+    let flow_id = redis_getdel::<String>(code_key(code).as_str())
+        .await?
+        .ok_or_else(|| invalid_grant("code is invalid or expired"))?;
+    let mut flow = load_flow(&flow_id)
+        .await
+        .map_err(|_| invalid_grant("code is invalid or expired"))?;
+    if flow.request.client_id != client.client_id
+        || params.get("redirect_uri") != Some(&flow.request.redirect_uri)
+        || !matches!(flow.stage, FlowStage::Authorized)
+    {
+        return Err(invalid_grant("code was not issued to this client").into());
+    }
+    if !verify_pkce(&flow, params.get("code_verifier")) {
+        return Err(invalid_grant("code_verifier mismatched").into());
+    }
+    let token_type = token_type(&cnf);
+    let jkt = cnf.as_ref().and_then(|cnf| cnf.jkt.clone());
+    let access_token = flow.access_token(cnf)?;
+    let id_token = match flow.request.scope.iter().any(|s| s == OPENID_SCOPE) {
+        true => Some(flow.id_token(None, None)?),
+        false => None,
+    };
+    let refresh_token = match (
+        flow.request.scope.iter().any(|s| s == OFFLINE_ACCESS_SCOPE),
+        &flow.subject,
+    ) {
+        (true, Some(subject)) => Some(
+            issue_refresh_token(&RefreshToken {
+                client_id: client.client_id.clone(),
+                subject: subject.openid.clone(),
+                audience: flow
+                    .request
+                    .audience
+                    .clone()
+                    .unwrap_or_else(|| client.client_id.clone()),
+                scope: flow.request.scope.clone(),
+                issued_at: Utc::now().timestamp(),
+                auth_time: flow.auth_time.map(|t| t.timestamp()),
+                // 公开客户端无法认证自身，refresh token 只能由持有 DPoP 私钥的一方使用
+                jkt: jkt
+                    .filter(|_| client.token_endpoint_auth_method == TokenEndpointAuthMethod::None),
+            })
+            .await?,
+        ),
+        _ => None,
+    };
+    flow.stage = FlowStage::Completed;
+    persist_flow(&flow).await?;
+    Ok(Tokens {
+        token_type,
+        access_token,
+        expires_in: token_expires_in().as_secs(),
+        id_token,
+        scope: Some(flow.request.scope.join(" ")),
+        refresh_token,
+        issued_token_type: None,
+    })
+}
+
+// 每次使用都轮换 refresh token，scope 只能缩小
+// https://datatracker.ietf.org/doc/html/rfc6749#section-6
+pub async fn refresh(
+    client: &ClientConfig,
+    params: &HashMap<String, String>,
+    cnf: Option<Confirmation>,
+) -> Result<Tokens> {
+    let token = params.get("refresh_token").ok_or_else(|| {
+        AuthorizationError::local(AuthError::InvalidRequest, "refresh_token is lacked")
+    })?;
+    let refresh_token = redis_get::<RefreshToken>(refresh_key(token).as_str())
+        .await?
+        .ok_or_else(|| invalid_grant("refresh token is invalid or expired"))?;
+    if refresh_token.client_id != client.client_id {
+        return Err(invalid_grant("refresh token was not issued to this client").into());
+    }
+    if let Some(jkt) = &refresh_token.jkt {
+        if cnf.as_ref().and_then(|cnf| cnf.jkt.as_ref()) != Some(jkt) {
+            return Err(invalid_grant("refresh token is bound to another DPoP key").into());
+        }
+    }
+    if revoked(&refresh_token.subject, refresh_token.issued_at).await? {
+        return Err(invalid_grant("refresh token is revoked").into());
+    }
+    let scope = narrow_scope(params.get("scope"), &refresh_token.scope)?;
+    // 并发使用同一个 refresh token 时只有一个请求成功
+    if redis_getdel::<RefreshToken>(refresh_key(token).as_str())
+        .await?
+        .is_none()
+    {
+        return Err(invalid_grant("refresh token is invalid or expired").into());
+    }
+    let token_type = token_type(&cnf);
+    let mut access_token = AccessToken::new(
+        &refresh_token.subject,
+        &refresh_token.audience,
+        &refresh_token.client_id,
+        token_expires_in(),
+        scope.clone(),
+    );
+    access_token.auth_time = refresh_token.auth_time;
+    access_token.cnf = cnf;
+    Ok(Tokens {
+        token_type,
+        access_token: sign_access_token(&access_token)?,
+        expires_in: token_expires_in().as_secs(),
+        id_token: None,
+        scope: Some(scope.join(" ")),
+        refresh_token: Some(issue_refresh_token(&refresh_token).await?),
+        issued_token_type: None,
+    })
+}
+
+// 请求的 scope 必须是已授权 scope 的子集，未指定时沿用
+fn narrow_scope(requested: Option<&String>, granted: &[String]) -> Result<Vec<String>> {
+    let Some(requested) = requested else {
+        return Ok(granted.to_vec());
+    };
+    let scope = requested.split(' ').map(str::to_string).collect::<Vec<_>>();
+    if !scope.iter().all(|s| granted.contains(s)) {
+        return Err(AuthorizationError::local(
+            AuthError::InvalidScope,
+            "scope exceeds the original grant",
+        )
+        .into());
+    }
+    Ok(scope)
+}
+
+fn sign_access_token(access_token: &AccessToken) -> Result<String> {
+    jwt::generate_typed_jws(access_token, jwt::active_key(), ACCESS_TOKEN_JWT_TYPE)
+        .map_err(|e| ApiError::Internal(anyhow::anyhow!("sign access token failed: {e}")))
+}
+
+fn invalid_token(name: &str) -> AuthorizationError {
+    AuthorizationError::local(
+        AuthError::InvalidRequest,
+        format!("{name} is invalid or expired"),
+    )
+}
+
+fn token_of_type(params: &HashMap<String, String>, name: &str) -> Result<Option<String>> {
+    let Some(token) = params.get(name) else {
+        return Ok(None);
+    };
+    match params.get(&format!("{name}_type")).map(String::as_str) {
+        Some(ACCESS_TOKEN_TYPE | JWT_TOKEN_TYPE) => Ok(Some(token.clone())),
+        Some(token_type) => Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            format!("unsupported {name}_type {token_type}"),
+        )
+        .into()),
+        None => Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            format!("{name}_type is lacked"),
+        )
+        .into()),
+    }
+}
+
+// 只接受签发给调用方的 subject token，新 token 的 audience 受客户端策略约束，
+// 有效期不超过 subject token，调用方记录在 act 中
+// https://datatracker.ietf.org/doc/html/rfc8693#section-2.1
+pub fn exchange_claims(
+    client: &ClientConfig,
+    subject: &AccessToken,
+    actor: Option<&AccessToken>,
+    params: &HashMap<String, String>,
+) -> Result<AccessToken> {
+    if client.token_endpoint_auth_method == TokenEndpointAuthMethod::None {
+        return Err(AuthorizationError::local(
+            AuthError::UnauthorizedClient,
+            "public client is not allowed to exchange tokens",
+        )
+        .into());
+    }
+    if subject.token.aud != client.client_id && subject.client_id != client.client_id {
+        return Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            "subject_token was not issued to this client",
+        )
+        .into());
+    }
+    if params
+        .get("requested_token_type")
+        .is_some_and(|token_type| token_type != ACCESS_TOKEN_TYPE)
+    {
+        return Err(AuthorizationError::local(
+            AuthError::InvalidRequest,
+            "only access_token can be requested",
+        )
+        .into());
+    }
+    let audience = params.get("audience").ok_or_else(|| {
+        AuthorizationError::local(AuthError::InvalidRequest, "audience is lacked")
+    })?;
+    if !client.token_exchange_audiences.contains(audience) {
+        return Err(AuthorizationError::local(
+            AuthError::InvalidTarget,
+            format!("client is not allowed to exchange into {audience}"),
+        )
+        .into());
+    }
+    let scope = narrow_scope(params.get("scope"), &subject.scope)?;
+    let actor = match actor {
+        Some(actor) if actor.client_id != client.client_id => {
+            return Err(AuthorizationError::local(
+                AuthError::InvalidRequest,
+                "actor_token was not issued to this client",
+            )
+            .into())
+        }
+        Some(actor) => actor.token.sub.clone(),
+        None => client.client_id.clone(),
+    };
+    let remaining = (subject.token.exp - Utc::now())
+        .to_std()
+        .map_err(|_| invalid_token("subject_token"))?;
+    let mut access_token = AccessToken::new(
+        &subject.token.sub,
+        audience,
+        &client.client_id,
+        remaining.min(token_expires_in()),
+        scope,
+    );
+    access_token.token.exp = access_token.token.exp.min(subject.token.exp);
+    access_token.auth_time = subject.auth_time;
+    access_token.acr = subject.acr.clone();
+    access_token.act = Some(Actor {
+        sub: actor,
+        act: subject.act.clone().map(Box::new),
+    });
+    Ok(access_token)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc8693#section-2.2
+pub async fn exchange_token(
+    client: &ClientConfig,
+    params: &HashMap<String, String>,
+    cnf: Option<Confirmation>,
+) -> Result<Tokens> {
+    let subject_token = token_of_type(params, "subject_token")?.ok_or_else(|| {
+        AuthorizationError::local(AuthError::InvalidRequest, "subject_token is lacked")
+    })?;
+    let subject = active_access_token(&subject_token)
+        .await?
+        .ok_or_else(|| invalid_token("subject_token"))?;
+    let actor = match token_of_type(params, "actor_token")? {
+        Some(actor_token) => Some(
+            active_access_token(&actor_token)
+                .await?
+                .ok_or_else(|| invalid_token("actor_token"))?,
+        ),
+        None => None,
+    };
+    let mut access_token = exchange_claims(client, &subject, actor.as_ref(), params)?;
+    let token_type = token_type(&cnf);
+    access_token.cnf = cnf;
+    let expires_in = (access_token.token.exp - Utc::now()).num_seconds().max(0) as u64;
+    Ok(Tokens {
+        token_type,
+        access_token: sign_access_token(&access_token)?,
+        expires_in,
+        id_token: None,
+        scope: Some(access_token.scope.join(" ")),
+        refresh_token: None,
+        issued_token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+    })
+}
+
+fn verify_access_token(token: &str) -> Option<AccessToken> {
+    let key = jwt::active_key();
+    let mut validation = jsonwebtoken::Validation::new(key.export_alg().ok()?);
+    validation.leeway = jwt::leeway();
+    validation.validate_nbf = true;
+    jwt::verify_access_token(token, key, validation).ok()
+}
+
+// 签名有效、未过期且未被撤销的 access token
+async fn active_access_token(token: &str) -> Result<Option<AccessToken>> {
+    let Some(access_token) = verify_access_token(token) else {
+        return Ok(None);
+    };
+    let claims = &access_token.token;
+    if redis_get::<i64>(jti_key(&claims.jti).as_str())
+        .await?
+        .is_some()
+        || revoked(&claims.sub, claims.iat.timestamp()).await?
+    {
+        return Ok(None);
+    }
+    Ok(Some(access_token))
+}
+
+// 只向 token 的签发对象或 audience 披露内容，其他客户端得到 active: false
+// https://datatracker.ietf.org/doc/html/rfc7662#section-4
+pub fn introspection(
+    client: &ClientConfig,
+    access_token: Option<&AccessToken>,
+) -> serde_json::Value {
+    let Some(access_token) = access_token.filter(|access_token| {
+        access_token.client_id == client.client_id || access_token.token.aud == client.client_id
+    }) else {
+        return serde_json::json!({ "active": false });
+    };
+    let claims = &access_token.token;
+    let mut introspection = serde_json::json!({
+        "active": true,
+        "scope": access_token.scope.join(" "),
+        "client_id": access_token.client_id,
+        "token_type": token_type(&access_token.cnf),
+        "exp": claims.exp.timestamp(),
+        "iat": claims.iat.timestamp(),
+        "nbf": claims.nbf.timestamp(),
+        "sub": claims.sub,
+        "aud": claims.aud,
+        "iss": claims.iss,
+        "jti": claims.jti,
+    });
+    if let Some(cnf) = &access_token.cnf {
+        introspection["cnf"] = serde_json::json!(cnf);
+    }
+    if let Some(act) = &access_token.act {
+        introspection["act"] = serde_json::json!(act);
+    }
+    introspection
+}
+
+// 无效、过期或已撤销的 token 一律返回 active: false
+// https://datatracker.ietf.org/doc/html/rfc7662#section-2.2
+pub async fn introspect(client: &ClientConfig, token: &str) -> Result<serde_json::Value> {
+    Ok(introspection(
+        client,
+        active_access_token(token).await?.as_ref(),
+    ))
+}
+
+// 只撤销签发给该客户端的 token，未知 token 同样视为成功
+// https://datatracker.ietf.org/doc/html/rfc7009#section-2.2
+pub async fn revoke(client: &ClientConfig, token: &str) -> Result<()> {
+    if let Some(refresh_token) = redis_get::<RefreshToken>(refresh_key(token).as_str()).await? {
+        if refresh_token.client_id != client.client_id {
+            return Err(AuthorizationError::local(
+                AuthError::UnauthorizedClient,
+                "token was not issued to this client",
+            )
+            .into());
+        }
+        return redis_del(refresh_key(token).as_str()).await;
+    }
+    let Some(access_token) = verify_access_token(token) else {
+        return Ok(());
+    };
+    if access_token.client_id != client.client_id {
+        return Err(AuthorizationError::local(
+            AuthError::UnauthorizedClient,
+            "token was not issued to this client",
+        )
+        .into());
+    }
+    let claims = access_token.token;
+    let expires_in = claims.exp - Utc::now();
+    if expires_in > Duration::zero() {
+        redis_setex(
+            jti_key(&claims.jti).as_str(),
+            claims.exp.timestamp(),
+            expires_in,
+        )
+        .await?;
+    }
+    Ok(())
+}
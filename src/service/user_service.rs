@@ -1,70 +1,320 @@
-use actix_web::error::{ErrorBadRequest, ErrorUnauthorized};
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorUnauthorized};
 use anyhow::Context;
 use chrono::Duration;
 use ring::rand::{SecureRandom, SystemRandom};
+use tera::Context as TemplateContext;
 
 use crate::{
     common::{
-        cache::redis::{redis_get, redis_setex},
+        cache::redis::{redis_del, redis_get, redis_getdel, redis_incr, redis_setex},
+        config::env_var_default,
+        constant::{AMR_PWD, PASSWORD_CHANGED_TEMPLATE, PASSWORD_RESET_TEMPLATE},
         errors::{ApiError, Result},
         srp::{
-            groups::G_2048,
-            server::{SrpServer, SrpServerVerifier},
+            groups::select_group, server::SrpServer, types::SrpHash, utils::session_confirmation,
         },
+        utils::{gen_id, sha256},
     },
-    dto::password::SrpPassword,
-    rpc::password_rpc,
+    dto::{
+        auth::{Flow, FlowStage},
+        password::{
+            PasswordReset, PasswordResetConfirm, PreSrpRespose, SrpChangeRequest, SrpHandshake,
+            SrpPassword, SrpResponse,
+        },
+    },
+    rpc::{password_rpc, user_rpc},
+    service::{mfa_service, sms_service, token_service},
 };
 
-pub async fn pre_srp_login(i: &str, a_pub_str: &str) -> Result<(String, String)> {
+fn handshake_key(handshake: &str) -> String {
+    format!("forum:auth:srp:handshake:{handshake}")
+}
+
+fn check_authenticating(flow: &Flow) -> Result<()> {
+    if matches!(
+        flow.stage,
+        FlowStage::Initialized | FlowStage::Authenticating
+    ) {
+        Ok(())
+    } else {
+        Err(ApiError::Response(ErrorBadRequest(
+            "authentication is completed",
+        )))
+    }
+}
+
+// 每次尝试生成独立的握手 id，同一用户的并发登录互不覆盖
+pub async fn pre_srp_login(flow: &Flow, i: &str, a_pub_str: &str) -> Result<PreSrpRespose> {
     let srp_meta = match password_rpc::fetch_srp_password(i).await? {
         Some(meta) => meta,
         None => return Err(ApiError::Response(ErrorUnauthorized("invalid_identifier"))),
     };
-    let srp_server = SrpServer::new(&G_2048);
+    let group = select_group(srp_meta.group, srp_meta.hash);
+    let srp_server = SrpServer::with_mode(&group, srp_meta.mode);
     let rng = SystemRandom::new();
     let mut b = [0u8; 64];
     rng.fill(&mut b).unwrap();
-    tracing::info!("b: {}", hex::encode(b));
     let verifier = hex::decode(&srp_meta.verifier).with_context(|| {
         tracing::error!("verifier decode failed");
         format!("verifier decode failed")
     })?;
+    let salt = hex::decode(&srp_meta.salt).with_context(|| {
+        tracing::error!("salt decode failed");
+        "salt decode failed".to_string()
+    })?;
     let a_pub = hex::decode(a_pub_str).with_context(|| {
         tracing::error!("a_pub decode failed");
         format!("a_pub decode failed")
     })?;
-    let server_verifier: SrpServerVerifier =
-        srp_server.process_reply(&b, &verifier, &a_pub).unwrap();
+    let handshake = gen_id(32);
     redis_setex(
-        format!("forum:auth:srp:{i}").as_str(),
-        server_verifier,
+        handshake_key(&handshake).as_str(),
+        SrpHandshake {
+            flow_id: flow.id.clone(),
+            identifier: i.to_string(),
+            verifier: srp_server.process_reply(i.as_bytes(), &salt, &b, &verifier, &a_pub)?,
+        },
         Duration::minutes(1),
     )
     .await?;
-    Ok((
-        srp_meta.salt.clone(),
-        hex::encode(srp_server.compute_public_ephemeral(&b, &verifier)),
-    ))
+    Ok(PreSrpRespose {
+        salt: srp_meta.salt.clone(),
+        b_pub: hex::encode(srp_server.compute_public_ephemeral(&b, &verifier)),
+        group: srp_meta.group,
+        hash: srp_meta.hash,
+        mode: srp_meta.mode,
+        handshake,
+    })
 }
 
-pub async fn srp_login(identifier: &str, m1: &str) -> Result<()> {
-    let server_verifier =
-        redis_get::<SrpServerVerifier>(format!("forum:auth:srp:{identifier}").as_str())
-            .await?
-            .ok_or(ApiError::Response(ErrorBadRequest("pre login first")))?;
+// 握手状态取出即删除，无论校验成功与否都不能重放
+async fn verify_handshake(
+    flow: &Flow,
+    handshake: &str,
+    identifier: &str,
+    m1: &str,
+) -> Result<SrpHandshake> {
+    let handshake = redis_getdel::<SrpHandshake>(handshake_key(handshake).as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorBadRequest("pre login first")))?;
     let m1 = hex::decode(m1).with_context(|| {
         tracing::error!("client m1 decode failed");
         format!("client m1 decode failed")
     })?;
-    server_verifier.verify_client(&m1).map_err(|e| {
-        tracing::error!("verify client m1 failed, {:?}", e);
+    handshake.verify(&flow.id, identifier, &m1).map_err(|e| {
+        tracing::warn!("verify client m1 failed, flow: {}, {:?}", flow.id, e);
         ApiError::Response(ErrorUnauthorized("verify failed"))
     })?;
+    Ok(handshake)
+}
+
+// 校验 M1 后把口令认证记到 flow 上，返回 M2 让客户端反向认证服务端
+pub async fn srp_login(
+    flow: &mut Flow,
+    handshake: &str,
+    identifier: &str,
+    m1: &str,
+) -> Result<SrpResponse> {
+    check_authenticating(flow)?;
+    let server_verifier = verify_handshake(flow, handshake, identifier, m1)
+        .await?
+        .verifier;
+
+    flow.subject = Some(
+        user_rpc::get_user_profile(identifier)
+            .await?
+            .ok_or(ApiError::Response(ErrorUnauthorized("invalid_identifier")))?,
+    );
+    mfa_service::complete_factor(flow, AMR_PWD).await?;
+    Ok(SrpResponse {
+        proof: hex::encode(server_verifier.proof()),
+        confirmation: hex::encode(session_confirmation(server_verifier.key(), &flow.id)),
+        next_uri: flow.next_uri(),
+    })
+}
+
+// 只约束新注册的口令，已有记录仍按原参数登录
+fn check_srp_policy(srp: &SrpPassword) -> Result<()> {
+    if srp.group.bits() < env_var_default::<usize>("SRP_MIN_GROUP_BITS", 2048) {
+        return Err(ApiError::Response(ErrorBadRequest("srp group is too weak")));
+    }
+    if srp.hash == SrpHash::Sha1 {
+        return Err(ApiError::Response(ErrorBadRequest("srp hash is too weak")));
+    }
+    Ok(())
+}
+
+// 只能为当前已认证的用户注册口令，已有口令需要走修改流程
+pub async fn create_srp(flow: &Flow, srp: &SrpPassword) -> Result<()> {
+    let subject = mfa_service::subject(flow)?;
+    if !matches!(flow.stage, FlowStage::Authenticated) {
+        return Err(ApiError::Response(ErrorUnauthorized("login_required")));
+    }
+    if srp.identifier != subject.openid && subject.email.as_ref() != Some(&srp.identifier) {
+        return Err(ApiError::Response(ErrorForbidden("identifier mismatched")));
+    }
+    check_srp_policy(srp)?;
+    srp.validate()?;
+    if password_rpc::fetch_srp_password(&srp.identifier)
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::Response(ErrorConflict("password is registered")));
+    }
+    password_rpc::save_srp_password(srp).await?;
     Ok(())
 }
 
-pub async fn create_srp(srp: &SrpPassword) -> Result<()> {
+// 用旧口令完成一次 SRP 握手后才能修改，修改后撤销该用户已有的会话和 refresh token
+pub async fn change_srp(flow: &Flow, request: &SrpChangeRequest) -> Result<()> {
+    let srp = &request.password;
+    check_srp_policy(srp)?;
+    srp.validate()?;
+    verify_handshake(flow, &request.handshake, &srp.identifier, &request.proof).await?;
+    let subject = user_rpc::get_user_profile(&srp.identifier)
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized("invalid_identifier")))?;
     password_rpc::save_srp_password(srp).await?;
+    token_service::revoke_all(&subject.openid).await
+}
+
+const RESET_MAX_REQUESTS: i64 = 3;
+
+fn reset_key(token: &str) -> String {
+    format!(
+        "forum:auth:password:reset:{}",
+        hex::encode(sha256(token.as_bytes()))
+    )
+}
+
+fn reset_expires() -> Duration {
+    Duration::seconds(env_var_default::<i64>("PASSWORD_RESET_EXPIRES", 900))
+}
+
+// 无论用户是否存在都立即返回，查询和发信放到后台，避免通过响应内容或耗时枚举账号
+pub fn request_reset(identifier: &str) {
+    let identifier = identifier.to_string();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = send_reset(&identifier).await {
+            tracing::error!("send password reset failed, {e}");
+        }
+    });
+}
+
+async fn send_reset(identifier: &str) -> Result<()> {
+    let requests = redis_incr(
+        format!("forum:auth:password:reset:requests:{identifier}").as_str(),
+        Duration::hours(1),
+    )
+    .await?;
+    if requests > RESET_MAX_REQUESTS {
+        tracing::warn!("too many password reset requests");
+        return Ok(());
+    }
+    let subject = match user_rpc::get_user_profile(identifier).await? {
+        Some(subject) => subject,
+        None => return Ok(()),
+    };
+    let email = match &subject.email {
+        Some(email) => email,
+        None => return Ok(()),
+    };
+
+    // 同一用户重新申请时旧的重置链接作废
+    let token = gen_id(32);
+    let user_key = format!("forum:auth:password:reset:user:{}", subject.openid);
+    if let Some(previous) = redis_get::<String>(user_key.as_str()).await? {
+        redis_del(reset_key(&previous).as_str()).await?;
+    }
+    redis_setex(
+        reset_key(&token).as_str(),
+        PasswordReset {
+            identifier: identifier.to_string(),
+            openid: subject.openid.clone(),
+        },
+        reset_expires(),
+    )
+    .await?;
+    redis_setex(user_key.as_str(), &token, reset_expires()).await?;
+
+    let mut context = TemplateContext::new();
+    context.insert(
+        "link",
+        &format!(
+            "{}?token={}",
+            env_var_default::<String>(
+                "PASSWORD_RESET_ENDPOINT",
+                "https://auth.heliannuuthus.com/password/reset".to_string()
+            ),
+            token
+        ),
+    );
+    context.insert("expires_in", &reset_expires().num_minutes());
+    sms_service::send_msg(PASSWORD_RESET_TEMPLATE, email, context).await
+}
+
+// 重置依赖的存储与下游服务，测试时替换为内存实现
+#[async_trait::async_trait(?Send)]
+pub trait PasswordResetBackend {
+    // 一次性取出重置凭证
+    async fn take_reset(&self, token: &str) -> Result<Option<PasswordReset>>;
+    async fn save_password(&self, srp: &SrpPassword) -> Result<()>;
+    async fn revoke_all(&self, openid: &str) -> Result<()>;
+}
+
+pub struct RemoteResetBackend;
+
+#[async_trait::async_trait(?Send)]
+impl PasswordResetBackend for RemoteResetBackend {
+    async fn take_reset(&self, token: &str) -> Result<Option<PasswordReset>> {
+        let reset = redis_getdel::<PasswordReset>(reset_key(token).as_str()).await?;
+        if let Some(reset) = &reset {
+            redis_del(format!("forum:auth:password:reset:user:{}", reset.openid).as_str()).await?;
+        }
+        Ok(reset)
+    }
+
+    async fn save_password(&self, srp: &SrpPassword) -> Result<()> {
+        password_rpc::save_srp_password(srp).await
+    }
+
+    async fn revoke_all(&self, openid: &str) -> Result<()> {
+        token_service::revoke_all(openid).await
+    }
+}
+
+// 使用重置凭证重新登记 salt 和 verifier，完成后撤销已有会话
+pub async fn redeem_reset(
+    backend: &impl PasswordResetBackend,
+    request: &PasswordResetConfirm,
+) -> Result<PasswordReset> {
+    let srp = &request.password;
+    check_srp_policy(srp)?;
+    srp.validate()?;
+    let reset = backend
+        .take_reset(&request.token)
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized("invalid token")))?;
+    if reset.identifier != srp.identifier {
+        return Err(ApiError::Response(ErrorBadRequest("identifier mismatched")));
+    }
+    backend.save_password(srp).await?;
+    backend.revoke_all(&reset.openid).await?;
+    Ok(reset)
+}
+
+// 重置完成后通知用户
+pub async fn confirm_reset(request: &PasswordResetConfirm) -> Result<()> {
+    let reset = redeem_reset(&RemoteResetBackend, request).await?;
+    if let Some(subject) = user_rpc::get_user_profile(&reset.identifier).await? {
+        if let Some(email) = &subject.email {
+            if let Err(e) =
+                sms_service::send_msg(PASSWORD_CHANGED_TEMPLATE, email, TemplateContext::new())
+                    .await
+            {
+                tracing::error!("send password changed notification failed, {e}");
+            }
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,229 @@
+use actix_web::error::{ErrorBadRequest, ErrorUnauthorized};
+use chrono::Duration;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::{
+    common::{
+        cache::redis::{redis_getdel, redis_setex},
+        config::env_var_default,
+        constant::{AMR_HWK, AMR_MFA, AMR_SWK},
+        errors::{ApiError, Result},
+        utils::{decode64url, encode64url},
+        webauthn::{AuthenticatorData, RelyingParty, COSE_EDDSA, COSE_ES256, COSE_RS256},
+    },
+    dto::{
+        auth::{Flow, FlowStage},
+        webauthn::{
+            AssertionRequest, AuthenticatorSelection, CreationOptions, CredentialDescriptor,
+            CredentialParameter, RegistrationRequest, RelyingPartyEntity, RequestOptions,
+            UserEntity, WebauthnChallenge, WebauthnCredential,
+        },
+    },
+    rpc::{user_rpc, webauthn_rpc},
+    service::mfa_service,
+};
+
+const CEREMONY_TIMEOUT: i64 = 300;
+
+fn rp_id() -> String {
+    env_var_default::<String>("WEBAUTHN_RP_ID", "auth.heliannuuthus.com".to_string())
+}
+
+fn relying_party() -> RelyingParty {
+    RelyingParty::new(
+        &rp_id(),
+        &env_var_default::<String>(
+            "WEBAUTHN_RP_ORIGIN",
+            "https://auth.heliannuuthus.com".to_string(),
+        ),
+    )
+}
+
+fn challenge_key(ceremony: &str, flow: &Flow) -> String {
+    format!("forum:auth:webauthn:{ceremony}:{}", flow.id)
+}
+
+async fn new_challenge(ceremony: &str, flow: &Flow, openid: Option<String>) -> Result<String> {
+    let mut challenge = [0u8; 32];
+    SystemRandom::new().fill(&mut challenge).unwrap();
+    let challenge = encode64url(&challenge);
+    redis_setex(
+        challenge_key(ceremony, flow).as_str(),
+        WebauthnChallenge {
+            challenge: challenge.clone(),
+            openid,
+        },
+        Duration::seconds(CEREMONY_TIMEOUT),
+    )
+    .await?;
+    Ok(challenge)
+}
+
+// 挑战只能使用一次
+async fn take_challenge(ceremony: &str, flow: &Flow) -> Result<WebauthnChallenge> {
+    redis_getdel::<WebauthnChallenge>(challenge_key(ceremony, flow).as_str())
+        .await?
+        .ok_or(ApiError::Response(ErrorBadRequest("ceremony is expired")))
+}
+
+// 可同步的 passkey 视为软件密钥
+fn authentication_method(authenticator_data: &AuthenticatorData) -> &'static str {
+    if authenticator_data.backup_eligible() {
+        AMR_SWK
+    } else {
+        AMR_HWK
+    }
+}
+
+pub async fn registration_options(flow: &Flow) -> Result<CreationOptions> {
+    mfa_service::enrollable(flow).await?;
+    let subject = mfa_service::subject(flow)?;
+    let challenge = new_challenge("registration", flow, Some(subject.openid.clone())).await?;
+    let exclude_credentials = webauthn_rpc::fetch_credentials(&subject.openid)
+        .await?
+        .iter()
+        .map(|credential| CredentialDescriptor::new(&credential.credential_id))
+        .collect();
+    Ok(CreationOptions {
+        challenge,
+        rp: RelyingPartyEntity {
+            id: rp_id(),
+            name: env_var_default::<String>("WEBAUTHN_RP_NAME", "heliannuuthus".to_string()),
+        },
+        user: UserEntity {
+            id: encode64url(subject.openid.as_bytes()),
+            name: subject.email.clone().unwrap_or(subject.openid.clone()),
+            display_name: subject.nickname.clone(),
+        },
+        pub_key_cred_params: [COSE_ES256, COSE_EDDSA, COSE_RS256]
+            .into_iter()
+            .map(|alg| CredentialParameter {
+                credential_type: "public-key",
+                alg,
+            })
+            .collect(),
+        timeout: CEREMONY_TIMEOUT as u64 * 1000,
+        exclude_credentials,
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred",
+            user_verification: "preferred",
+        },
+        attestation: "none",
+    })
+}
+
+// 注册只绑定认证器，不计为第二因子，MultiFactor 阶段仍需再完成一次断言
+pub async fn register(flow: &Flow, request: &RegistrationRequest) -> Result<()> {
+    mfa_service::enrollable(flow).await?;
+    let challenge = take_challenge("registration", flow).await?;
+    let openid = mfa_service::subject(flow)?.openid.clone();
+    if challenge.openid.as_deref() != Some(openid.as_str()) {
+        return Err(ApiError::Response(ErrorUnauthorized("ceremony mismatched")));
+    }
+    let authenticator_data = relying_party().verify_registration(
+        &challenge.challenge,
+        &decode64url(&request.response.client_data_json)?,
+        &decode64url(&request.response.attestation_object)?,
+        false,
+    )?;
+    let credential = authenticator_data
+        .attested_credential
+        .as_ref()
+        .ok_or(ApiError::Response(ErrorBadRequest("credential is lacked")))?;
+    let credential_id = encode64url(&credential.credential_id);
+    if request.id != credential_id {
+        return Err(ApiError::Response(ErrorBadRequest(
+            "credential id mismatched",
+        )));
+    }
+    webauthn_rpc::save_credential(&WebauthnCredential {
+        openid,
+        credential_id,
+        public_key: encode64url(&credential.public_key),
+        sign_count: authenticator_data.sign_count,
+        aaguid: hex::encode(&credential.aaguid),
+        backup_eligible: authenticator_data.backup_eligible(),
+    })
+    .await?;
+    mfa_service::issue_recovery_codes(flow).await
+}
+
+// 未认证时作为免密码的第一因子（可发现凭证），MultiFactor 阶段作为第二因子
+pub async fn assertion_options(flow: &Flow) -> Result<RequestOptions> {
+    let (openid, allow_credentials) = match flow.stage {
+        FlowStage::Initialized | FlowStage::Authenticating => (None, Vec::new()),
+        FlowStage::MultiFactor => {
+            let openid = mfa_service::subject(flow)?.openid.clone();
+            let credentials = webauthn_rpc::fetch_credentials(&openid)
+                .await?
+                .iter()
+                .map(|credential| CredentialDescriptor::new(&credential.credential_id))
+                .collect();
+            (Some(openid), credentials)
+        }
+        _ => {
+            return Err(ApiError::Response(ErrorBadRequest(
+                "authentication is completed",
+            )))
+        }
+    };
+    let user_verification = if openid.is_none() {
+        "required"
+    } else {
+        "discouraged"
+    };
+    Ok(RequestOptions {
+        challenge: new_challenge("assertion", flow, openid).await?,
+        rp_id: rp_id(),
+        timeout: CEREMONY_TIMEOUT as u64 * 1000,
+        allow_credentials,
+        user_verification,
+    })
+}
+
+pub async fn authenticate(flow: &mut Flow, request: &AssertionRequest) -> Result<()> {
+    let challenge = take_challenge("assertion", flow).await?;
+    let credential = webauthn_rpc::fetch_credential(&request.id)
+        .await?
+        .ok_or(ApiError::Response(ErrorUnauthorized("unknown credential")))?;
+    if let Some(openid) = &challenge.openid {
+        if credential.openid != *openid {
+            return Err(ApiError::Response(ErrorUnauthorized("unknown credential")));
+        }
+    }
+    if let Some(user_handle) = &request.response.user_handle {
+        if decode64url(user_handle)? != credential.openid.as_bytes() {
+            return Err(ApiError::Response(ErrorUnauthorized(
+                "user handle mismatched",
+            )));
+        }
+    }
+    let first_factor = challenge.openid.is_none();
+    let authenticator_data = relying_party().verify_assertion(
+        &challenge.challenge,
+        &decode64url(&credential.public_key)?,
+        credential.sign_count,
+        &decode64url(&request.response.client_data_json)?,
+        &decode64url(&request.response.authenticator_data)?,
+        &decode64url(&request.response.signature)?,
+        first_factor,
+    )?;
+    webauthn_rpc::update_sign_count(&credential.credential_id, authenticator_data.sign_count)
+        .await?;
+
+    let method = authentication_method(&authenticator_data);
+    if !first_factor {
+        flow.authenticated(method, true);
+        return Ok(());
+    }
+    flow.subject = Some(
+        user_rpc::get_user_profile(&credential.openid)
+            .await?
+            .ok_or(ApiError::Response(ErrorUnauthorized("invalid_identifier")))?,
+    );
+    // 经过用户验证的 passkey 本身即为多因子
+    if authenticator_data.user_verified() && !flow.amr.iter().any(|m| m == AMR_MFA) {
+        flow.amr.push(AMR_MFA.to_string());
+    }
+    mfa_service::complete_factor(flow, method).await
+}
@@ -0,0 +1,144 @@
+mod auth_request_test {
+    use actix_web::{http::header, test::TestRequest, ResponseError};
+    use forum_api::{
+        common::{
+            constant::{PromptType, ResponseType},
+            errors::Result,
+        },
+        dto::auth::{form_params, require_form, AuthRequest},
+    };
+    use proptest::{prelude::*, sample::subsequence};
+    use url::form_urlencoded::Serializer;
+
+    const RESPONSE_TYPES: &[(&str, ResponseType)] = &[
+        ("code", ResponseType::Code),
+        ("token", ResponseType::Token),
+        ("id_token", ResponseType::IdToken),
+    ];
+
+    fn encode(params: &[(&str, &str)]) -> String {
+        Serializer::new(String::new()).extend_pairs(params).finish()
+    }
+
+    // 与授权端点相同：先解析表单，再转换为授权请求
+    fn from_urlencoded(query: &str) -> Result<AuthRequest> {
+        AuthRequest::from_params(form_params(query.as_bytes())?)
+    }
+
+    fn parse(query: &str) -> AuthRequest {
+        from_urlencoded(query).unwrap()
+    }
+
+    #[test]
+    fn test_space_delimited_parameters() {
+        let request = parse(
+            "client_id=forum&redirect_uri=https%3A%2F%2Fclient.example.org%2Fcb&\
+             response_type=code%20id_token&scope=openid+profile+email&prompt=login&\
+             unknown=ignored&state=",
+        );
+        assert_eq!(
+            request.response_type,
+            vec![ResponseType::Code, ResponseType::IdToken]
+        );
+        assert_eq!(request.scope, vec!["openid", "profile", "email"]);
+        assert!(matches!(request.prompt, Some(PromptType::Login)));
+        assert_eq!(request.state, None);
+
+        // 持久化后的 flow 仍能读回
+        let json = serde_json::to_string(&request).unwrap();
+        let restored: AuthRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.scope, request.scope);
+        assert_eq!(restored.response_type, request.response_type);
+    }
+
+    #[test]
+    fn test_invalid_parameters_render_locally() {
+        for query in [
+            "client_id=forum&client_id=other&response_type=code&redirect_uri=https%3A%2F%2Fa",
+            "client_id=forum&response_type=code+device&redirect_uri=https%3A%2F%2Fa",
+            "client_id=forum&redirect_uri=https%3A%2F%2Fa",
+        ] {
+            let error = from_urlencoded(query).unwrap_err();
+            assert_eq!(error.status_code(), 400);
+            assert!(error.to_string().starts_with("invalid_request"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_form_content_type() {
+        for content_type in [
+            "application/x-www-form-urlencoded",
+            "application/x-www-form-urlencoded; charset=UTF-8",
+            "Application/X-WWW-Form-Urlencoded",
+        ] {
+            let req = TestRequest::post()
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .to_http_request();
+            assert!(require_form(&req).is_ok(), "{content_type}");
+        }
+        for content_type in ["application/json", "text/plain", "multipart/form-data"] {
+            let req = TestRequest::post()
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .to_http_request();
+            assert!(require_form(&req).is_err(), "{content_type}");
+        }
+        assert!(require_form(&TestRequest::post().to_http_request()).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_scope_round_trip(
+            scopes in prop::collection::vec("[\x21\x23-\x5b\x5d-\x7e]{1,12}", 0..6),
+            separators in prop::collection::vec(" {1,3}", 6),
+        ) {
+            let mut scope = String::new();
+            for (i, s) in scopes.iter().enumerate() {
+                if i > 0 {
+                    scope.push_str(&separators[i]);
+                }
+                scope.push_str(s);
+            }
+            let query = encode(&[
+                ("client_id", "forum"),
+                ("response_type", "code"),
+                ("redirect_uri", "https://client.example.org/cb"),
+                ("scope", &scope),
+            ]);
+            prop_assert_eq!(parse(&query).scope, scopes);
+        }
+
+        #[test]
+        fn prop_response_type_in_any_order(
+            types in subsequence(RESPONSE_TYPES.to_vec(), 1..=3).prop_shuffle(),
+            percent in any::<bool>(),
+        ) {
+            let value = types.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(" ");
+            let mut query = encode(&[
+                ("client_id", "forum"),
+                ("redirect_uri", "https://client.example.org/cb"),
+                ("response_type", &value),
+            ]);
+            if percent {
+                query = query.replace('+', "%20");
+            }
+            let expected = types.into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+            prop_assert_eq!(parse(&query).response_type, expected);
+        }
+
+        #[test]
+        fn prop_duplicated_parameter_rejected(
+            index in 0usize..4,
+            value in "[a-z]{1,8}",
+        ) {
+            let mut params = vec![
+                ("client_id", "forum"),
+                ("response_type", "code"),
+                ("redirect_uri", "https://client.example.org/cb"),
+                ("scope", "openid"),
+            ];
+            params.push((params[index].0, value.as_str()));
+            let error = from_urlencoded(&encode(&params)).unwrap_err();
+            prop_assert!(error.to_string().contains("is duplicated"));
+        }
+    }
+}
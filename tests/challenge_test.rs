@@ -0,0 +1,136 @@
+mod challenge_test {
+    use chrono::{Duration, Utc};
+    use forum_api::{
+        common::utils::{encode64url, gen_digits, hmac_sha256},
+        dto::challenge::{CodeChallenge, LinkChallenge},
+        service::challenge_service::{inspect_link, store_link, take_link, verify_link},
+    };
+
+    const LINK_SECRET: &str = "challenge-link-secret";
+
+    fn link_challenge(expires_in: Duration) -> LinkChallenge {
+        let now = Utc::now();
+        LinkChallenge {
+            flow_id: "flow-id".to_string(),
+            identifier: "alice@heliannuuthus.com".to_string(),
+            client_name: "forum-web".to_string(),
+            ip: "203.0.113.7".to_string(),
+            requested_at: now,
+            expires_at: now + expires_in,
+        }
+    }
+
+    fn link_token(flow_id: &str, nonce: &str) -> String {
+        std::env::set_var("CHALLENGE_LINK_SECRET", LINK_SECRET);
+        let signature = hmac_sha256(
+            LINK_SECRET.as_bytes(),
+            format!("{flow_id}.{nonce}").as_bytes(),
+        );
+        format!("{nonce}.{}", encode64url(&signature))
+    }
+
+    fn rejected(result: forum_api::common::errors::Result<LinkChallenge>, reason: &str) -> bool {
+        matches!(result, Err(e) if e.to_string().contains(reason))
+    }
+
+    #[test]
+    fn test_gen_digits() {
+        let code = gen_digits(6);
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_code_challenge_verify() {
+        let challenge = CodeChallenge::new("alice@heliannuuthus.com", "123456");
+        assert_ne!(challenge.code_hash, "123456");
+        assert!(challenge.verify("alice@heliannuuthus.com", "123456"));
+        assert!(!challenge.verify("alice@heliannuuthus.com", "654321"));
+        assert!(!challenge.verify("bob@heliannuuthus.com", "123456"));
+    }
+
+    #[test]
+    fn test_link_signature_tampering() {
+        let challenge = link_challenge(Duration::minutes(10));
+        let token = link_token("flow-id", "nonce");
+        let verified = verify_link(Some(challenge.clone()), &token, Utc::now()).unwrap();
+        assert_eq!(verified.ip, "203.0.113.7");
+
+        // 篡改签名
+        let (nonce, signature) = token.split_once('.').unwrap();
+        let mut tampered = signature.to_string();
+        tampered.replace_range(..1, if tampered.starts_with('A') { "B" } else { "A" });
+        assert!(rejected(
+            verify_link(
+                Some(challenge.clone()),
+                &format!("{nonce}.{tampered}"),
+                Utc::now()
+            ),
+            "invalid link"
+        ));
+        // 签给其他 flow 的链接不能挪用
+        assert!(rejected(
+            verify_link(
+                Some(challenge.clone()),
+                &link_token("other-flow", "nonce"),
+                Utc::now()
+            ),
+            "invalid link"
+        ));
+        assert!(rejected(
+            verify_link(Some(challenge), "nonce-without-signature", Utc::now()),
+            "malformed link"
+        ));
+    }
+
+    #[test]
+    fn test_link_expiry() {
+        let token = link_token("flow-id", "nonce");
+        let challenge = link_challenge(Duration::minutes(10));
+        assert!(rejected(
+            verify_link(
+                Some(challenge.clone()),
+                &token,
+                Utc::now() + Duration::minutes(11)
+            ),
+            "link is expired"
+        ));
+        assert!(rejected(
+            verify_link(
+                Some(link_challenge(Duration::seconds(-1))),
+                &token,
+                Utc::now()
+            ),
+            "link is expired"
+        ));
+        // Redis 中已过期删除
+        assert!(rejected(
+            verify_link(None, &token, Utc::now()),
+            "link is expired"
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_HOST and REDIS_PORT"]
+    async fn test_link_consumed_once() {
+        std::env::set_var("CHALLENGE_LINK_SECRET", LINK_SECRET);
+        let token = store_link(&link_challenge(Duration::minutes(10)))
+            .await
+            .unwrap();
+        // GET 确认页面只查看，不消费链接
+        for _ in 0..3 {
+            assert_eq!(inspect_link(&token).await.unwrap().flow_id, "flow-id");
+        }
+        assert_eq!(take_link(&token).await.unwrap().flow_id, "flow-id");
+        assert!(take_link(&token).await.is_err());
+        assert!(inspect_link(&token).await.is_err());
+
+        // 签名错误的确认请求不会把链接作废
+        let token = store_link(&link_challenge(Duration::minutes(10)))
+            .await
+            .unwrap();
+        let (nonce, _) = token.split_once('.').unwrap();
+        assert!(take_link(&format!("{nonce}.forged")).await.is_err());
+        assert!(take_link(&token).await.is_ok());
+    }
+}
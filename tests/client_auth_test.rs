@@ -0,0 +1,292 @@
+mod client_auth_test {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use actix_web::{http::header, test as actix_test, App, ResponseError};
+    use chrono::{Duration, Utc};
+    use forum_api::{
+        common::{
+            errors::{ApiError, Result},
+            utils::{encode64, seal},
+        },
+        controller::admin_controller::provision_secret,
+        dto::{
+            auth::{AuthRequest, Flow},
+            client::{
+                ClientConfig, ClientCredentials, ClientSecret, TokenEndpointAuthMethod,
+                CLIENT_ASSERTION_TYPE,
+            },
+        },
+        service::{
+            client_service::{rotate_secret, ClientSecretStore},
+            token_service::verify_pkce,
+        },
+    };
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+
+    const AUDIENCE: &str = "https://auth.heliannuuthus.com/token";
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    const SECRET: &str = "s3cr3t+/:";
+
+    fn client(method: TokenEndpointAuthMethod) -> ClientConfig {
+        ClientConfig {
+            client_id: "forum".to_string(),
+            secrets: vec![ClientSecret::hash_with(SECRET, 1000, None)],
+            token_endpoint_auth_method: method,
+            ..Default::default()
+        }
+    }
+
+    fn assertion(key: &EncodingKey, alg: Algorithm, aud: &str, exp: i64) -> String {
+        jsonwebtoken::encode(
+            &Header::new(alg),
+            &serde_json::json!({
+                "iss": "forum",
+                "sub": "forum",
+                "aud": aud,
+                "exp": Utc::now().timestamp() + exp,
+                "jti": "assertion-id",
+            }),
+            key,
+        )
+        .unwrap()
+    }
+
+    fn invalid_client(error: ApiError) -> bool {
+        error.status_code() == 401 && error.to_string().starts_with("invalid_client")
+    }
+
+    #[test]
+    fn test_extract_credentials() {
+        // client_id 与 secret 先 form 编码再 base64
+        let basic = format!("Basic {}", encode64(b"forum:s3cr3t%2B%2F%3A"));
+        let credentials = ClientCredentials::extract(Some(&basic), &params(&[])).unwrap();
+        assert_eq!(
+            credentials,
+            ClientCredentials::Basic {
+                client_id: "forum".to_string(),
+                secret: "s3cr3t+/:".to_string(),
+            }
+        );
+        let post = params(&[("client_id", "forum"), ("client_secret", "s3cr3t+/:")]);
+        assert!(matches!(
+            ClientCredentials::extract(None, &post).unwrap(),
+            ClientCredentials::Post { .. }
+        ));
+        assert!(matches!(
+            ClientCredentials::extract(None, &params(&[("client_id", "forum")])).unwrap(),
+            ClientCredentials::None { .. }
+        ));
+
+        let both = ClientCredentials::extract(Some(&basic), &post).unwrap_err();
+        assert!(both.to_string().starts_with("invalid_request"));
+        let mismatched =
+            ClientCredentials::extract(Some(&basic), &params(&[("client_id", "other")]))
+                .unwrap_err();
+        assert!(invalid_client(mismatched));
+        assert!(invalid_client(
+            ClientCredentials::extract(None, &params(&[])).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn test_secret_methods_enforced() {
+        let basic = ClientCredentials::Basic {
+            client_id: "forum".to_string(),
+            secret: "s3cr3t+/:".to_string(),
+        };
+        let basic_client = client(TokenEndpointAuthMethod::ClientSecretBasic);
+        assert!(basic.verify(&basic_client, &[], None).unwrap().is_none());
+        // 与注册的方式不一致时拒绝
+        assert!(invalid_client(
+            basic
+                .verify(
+                    &client(TokenEndpointAuthMethod::ClientSecretPost),
+                    &[],
+                    None
+                )
+                .unwrap_err()
+        ));
+        let wrong = ClientCredentials::Basic {
+            client_id: "forum".to_string(),
+            secret: "guess".to_string(),
+        };
+        assert!(invalid_client(
+            wrong.verify(&basic_client, &[], None).unwrap_err()
+        ));
+        let public = ClientCredentials::None {
+            client_id: "forum".to_string(),
+        };
+        assert!(invalid_client(
+            public.verify(&basic_client, &[], None).unwrap_err()
+        ));
+        assert!(public
+            .verify(&client(TokenEndpointAuthMethod::None), &[], None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_secret_rotation() {
+        let mut client = client(TokenEndpointAuthMethod::ClientSecretBasic);
+        assert!(!client.secrets[0].digest.contains(SECRET));
+        client.secrets = vec![
+            ClientSecret::hash_with("retired", 1000, Some(Utc::now() - Duration::hours(1))),
+            ClientSecret::hash_with("previous", 1000, Some(Utc::now() + Duration::days(7))),
+            ClientSecret::hash_with("current", 1000, None),
+            ClientSecret::hash_with("surplus", 1000, None),
+        ];
+        // 旧 secret 过期前与新 secret 同时有效
+        assert!(client.verify_secret("previous"));
+        assert!(client.verify_secret("current"));
+        assert!(!client.verify_secret("retired"));
+        assert!(!client.verify_secret("surplus"));
+        assert_eq!(client.active_secrets().count(), 2);
+        assert!(!ClientSecret::default().verify(""));
+    }
+
+    struct MemoryStore {
+        client: RefCell<ClientConfig>,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl ClientSecretStore for MemoryStore {
+        async fn fetch_client(&self, client_id: &str) -> Result<Option<ClientConfig>> {
+            let client = self.client.borrow();
+            Ok((client.client_id == client_id).then(|| client.clone()))
+        }
+
+        async fn save_secrets(&self, _: &str, secrets: &[ClientSecret]) -> Result<()> {
+            self.client.borrow_mut().secrets = secrets.to_vec();
+            Ok(())
+        }
+    }
+
+    // 新 secret 保存到客户端，旧 secret 进入宽限期，过期的被清理
+    #[actix_web::test]
+    async fn test_provision_secret() {
+        std::env::set_var("ADMIN_API_TOKEN", "admin-token");
+        let app = actix_test::init_service(App::new().service(provision_secret)).await;
+        let resp = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::post()
+                .uri("/admin/clients/forum/secrets")
+                .insert_header((header::AUTHORIZATION, "Bearer wrong"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        std::env::set_var("CLIENT_SECRET_KEY", hex::encode([7u8; 32]));
+        let mut client = client(TokenEndpointAuthMethod::ClientSecretJwt);
+        client.secrets.insert(
+            0,
+            ClientSecret::hash_with("retired", 1000, Some(Utc::now() - Duration::hours(1))),
+        );
+        let store = MemoryStore {
+            client: RefCell::new(client),
+        };
+        let first = rotate_secret(&store, "forum").await.unwrap();
+        let secrets = store.client.borrow().secrets.clone();
+        assert_eq!(secrets.len(), 2);
+        assert!(secrets[0].digest.starts_with("pbkdf2-sha256$310000$"));
+        assert!(secrets[0].verify(&first));
+        assert!(secrets[0].expires_at.is_none());
+        assert_eq!(secrets[0].unseal().unwrap(), first.as_bytes());
+        assert!(secrets[1].verify(SECRET));
+        assert!(secrets[1].expires_at.unwrap() > Utc::now());
+        assert!(store.client.borrow().verify_secret(SECRET));
+
+        // 再次轮换时最早的 secret 被移除，有效的 secret 不超过两个
+        let second = rotate_secret(&store, "forum").await.unwrap();
+        let client = store.client.borrow().clone();
+        assert_eq!(client.secrets.len(), 2);
+        assert!(client.verify_secret(&second));
+        assert!(client.verify_secret(&first));
+        assert!(!client.verify_secret(SECRET));
+        assert!(client.secrets[1].expires_at.is_some());
+
+        assert!(rotate_secret(&store, "unknown").await.is_err());
+        store.client.borrow_mut().token_endpoint_auth_method = TokenEndpointAuthMethod::None;
+        assert!(rotate_secret(&store, "forum").await.is_err());
+    }
+
+    #[test]
+    fn test_client_secret_jwt() {
+        let seal_key = [7u8; 32];
+        std::env::set_var("CLIENT_SECRET_KEY", hex::encode(seal_key));
+        let mut client = client(TokenEndpointAuthMethod::ClientSecretJwt);
+        client.secrets[0].sealed = Some(seal(&seal_key, SECRET.as_bytes()).unwrap());
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        let audiences = vec![AUDIENCE.to_string()];
+        let jwt = assertion(&key, Algorithm::HS256, AUDIENCE, 60);
+        let credentials = ClientCredentials::extract(
+            None,
+            &params(&[
+                ("client_assertion_type", CLIENT_ASSERTION_TYPE),
+                ("client_assertion", &jwt),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(credentials.client_id(), "forum");
+        let claims = credentials
+            .verify(&client, &audiences, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(claims.jti, "assertion-id");
+
+        let other = assertion(&key, Algorithm::HS256, "https://evil.example.org", 60);
+        assert!(invalid_client(
+            client.verify_assertion(&other, &audiences).unwrap_err()
+        ));
+        let expired = assertion(&key, Algorithm::HS256, AUDIENCE, -600);
+        assert!(invalid_client(
+            client.verify_assertion(&expired, &audiences).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn test_private_key_jwt() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let mut client = client(TokenEndpointAuthMethod::PrivateKeyJwt);
+        client.public_key = Some(String::from_utf8(rsa.public_key_to_pem().unwrap()).unwrap());
+        let key = EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap();
+        let audiences = vec![AUDIENCE.to_string()];
+        assert!(client
+            .verify_assertion(&assertion(&key, Algorithm::PS256, AUDIENCE, 60), &audiences)
+            .is_ok());
+
+        // 用 secret 签名的断言不能冒充 private_key_jwt
+        let forged = EncodingKey::from_secret(SECRET.as_bytes());
+        assert!(invalid_client(
+            client
+                .verify_assertion(
+                    &assertion(&forged, Algorithm::HS256, AUDIENCE, 60),
+                    &audiences
+                )
+                .unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn test_pkce() {
+        let mut flow = Flow::new(AuthRequest {
+            code_challenge: Some("8-Du3Clp4BvjEdR0VpCluaDFbgZJEGuf1ehP5rd9bao".to_string()),
+            code_challenge_method: Some("S256".to_string()),
+            ..Default::default()
+        });
+        let verifier = "dBjftJeZ4CVP-mJ92K9qwQ6gZ6TVQ6-pSXpvcMxA7E4".to_string();
+        assert!(verify_pkce(&flow, Some(&verifier)));
+        assert!(!verify_pkce(&flow, Some(&verifier.replace('d', "D"))));
+        assert!(!verify_pkce(&flow, None));
+        flow.request.code_challenge = None;
+        assert!(verify_pkce(&flow, None));
+        assert!(!verify_pkce(&flow, Some(&verifier)));
+    }
+}
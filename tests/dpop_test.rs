@@ -0,0 +1,226 @@
+mod dpop_test {
+    use actix_web::ResponseError;
+    use chrono::Utc;
+    use forum_api::{
+        common::{
+            constant::TokenType,
+            dpop::{jwk_thumbprint, verify_proof, DpopProof},
+            jwt::Confirmation,
+            utils::encode64url,
+        },
+        dto::{
+            auth::{AuthError, AuthorizationError, Tokens},
+            client::ClientConfig,
+        },
+        service::token_service::confirmation,
+    };
+    use jsonwebtoken::{jwk::Jwk, Algorithm, EncodingKey};
+    use openssl::{
+        bn::{BigNum, BigNumContext},
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::{PKey, Private},
+    };
+    use serde_json::{json, Value};
+
+    const HTU: &str = "https://auth.heliannuuthus.com/token";
+
+    struct ProofKey {
+        key: EcKey<Private>,
+    }
+
+    impl ProofKey {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            Self {
+                key: EcKey::generate(&group).unwrap(),
+            }
+        }
+
+        fn jwk(&self) -> Value {
+            let mut x = BigNum::new().unwrap();
+            let mut y = BigNum::new().unwrap();
+            self.key
+                .public_key()
+                .affine_coordinates(
+                    self.key.group(),
+                    &mut x,
+                    &mut y,
+                    &mut BigNumContext::new().unwrap(),
+                )
+                .unwrap();
+            json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "x": encode64url(&x.to_vec_padded(32).unwrap()),
+                "y": encode64url(&y.to_vec_padded(32).unwrap()),
+            })
+        }
+
+        fn sign(&self, header: &Value, claims: &Value) -> String {
+            let message = format!(
+                "{}.{}",
+                encode64url(header.to_string().as_bytes()),
+                encode64url(claims.to_string().as_bytes())
+            );
+            let pem = PKey::from_ec_key(self.key.clone())
+                .unwrap()
+                .private_key_to_pem_pkcs8()
+                .unwrap();
+            let key = EncodingKey::from_ec_pem(&pem).unwrap();
+            let signature =
+                jsonwebtoken::crypto::sign(message.as_bytes(), &key, Algorithm::ES256).unwrap();
+            format!("{message}.{signature}")
+        }
+
+        fn proof(&self, htm: &str, htu: &str, iat: i64) -> String {
+            self.sign(
+                &json!({ "typ": "dpop+jwt", "alg": "ES256", "jwk": self.jwk() }),
+                &json!({ "jti": "proof-id", "htm": htm, "htu": htu, "iat": iat }),
+            )
+        }
+    }
+
+    fn rejected(proof: &str, htm: &str, htu: &str) -> bool {
+        match verify_proof(proof, htm, htu, Utc::now().timestamp(), 300) {
+            Err(e) => e.to_string().starts_with("invalid_dpop_proof"),
+            Ok(_) => false,
+        }
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc7638#section-3.1
+    #[test]
+    fn test_jwk_thumbprint() {
+        let jwk: Jwk = serde_json::from_value(json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29",
+        }))
+        .unwrap();
+        assert_eq!(
+            jwk_thumbprint(&jwk).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn test_verify_proof() {
+        let key = ProofKey::new();
+        let now = Utc::now().timestamp();
+        let proof = verify_proof(&key.proof("POST", HTU, now), "POST", HTU, now, 300).unwrap();
+        let jwk: Jwk = serde_json::from_value(key.jwk()).unwrap();
+        assert_eq!(proof.jkt, jwk_thumbprint(&jwk).unwrap());
+        assert_eq!(proof.jti, "proof-id");
+
+        // htu 不比较 query 与 fragment
+        let proof = key.proof("POST", &format!("{HTU}?foo=bar#baz"), now);
+        assert!(verify_proof(&proof, "POST", HTU, now, 300).is_ok());
+    }
+
+    #[test]
+    fn test_reject_invalid_proof() {
+        let key = ProofKey::new();
+        let now = Utc::now().timestamp();
+        let proof = key.proof("POST", HTU, now);
+        assert!(rejected(&proof, "GET", HTU));
+        assert!(rejected(
+            &proof,
+            "POST",
+            "https://auth.heliannuuthus.com/introspect"
+        ));
+        assert!(rejected(&key.proof("POST", HTU, now - 600), "POST", HTU));
+        assert!(rejected(&key.proof("POST", HTU, now + 600), "POST", HTU));
+
+        let claims = json!({ "jti": "proof-id", "htm": "POST", "htu": HTU, "iat": now });
+        // typ 缺失
+        assert!(rejected(
+            &key.sign(&json!({ "alg": "ES256", "jwk": key.jwk() }), &claims),
+            "POST",
+            HTU
+        ));
+        // jwk 缺失
+        assert!(rejected(
+            &key.sign(&json!({ "typ": "dpop+jwt", "alg": "ES256" }), &claims),
+            "POST",
+            HTU
+        ));
+        // jwk 携带私钥
+        let mut private = key.jwk();
+        private["d"] = json!(encode64url(&key.key.private_key().to_vec()));
+        assert!(rejected(
+            &key.sign(
+                &json!({ "typ": "dpop+jwt", "alg": "ES256", "jwk": private }),
+                &claims
+            ),
+            "POST",
+            HTU
+        ));
+        // 声明的 jwk 与签名密钥不一致
+        let other = ProofKey::new();
+        assert!(rejected(
+            &other.sign(
+                &json!({ "typ": "dpop+jwt", "alg": "ES256", "jwk": key.jwk() }),
+                &claims
+            ),
+            "POST",
+            HTU
+        ));
+        // jti 缺失
+        assert!(rejected(
+            &key.sign(
+                &json!({ "typ": "dpop+jwt", "alg": "ES256", "jwk": key.jwk() }),
+                &json!({ "jti": "", "htm": "POST", "htu": HTU, "iat": now })
+            ),
+            "POST",
+            HTU
+        ));
+    }
+
+    #[test]
+    fn test_dpop_bound_token() {
+        let proof = DpopProof {
+            jkt: "thumbprint".to_string(),
+            jti: "proof-id".to_string(),
+            iat: Utc::now().timestamp(),
+            nonce: None,
+        };
+        let client = ClientConfig::default();
+        assert_eq!(confirmation(&client, None, None).unwrap(), None);
+        let cnf = confirmation(&client, None, Some(&proof)).unwrap().unwrap();
+        assert_eq!(
+            cnf,
+            Confirmation {
+                x5t_s256: None,
+                jkt: Some("thumbprint".to_string()),
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&cnf).unwrap(),
+            json!({ "jkt": "thumbprint" })
+        );
+
+        let tokens = Tokens {
+            token_type: TokenType::DPoP,
+            access_token: "access-token".to_string(),
+            expires_in: 3600,
+            id_token: None,
+            scope: None,
+            refresh_token: Some("refresh-token".to_string()),
+            issued_token_type: None,
+        };
+        let tokens = serde_json::to_value(&tokens).unwrap();
+        assert_eq!(tokens["token_type"], "DPoP");
+        assert_eq!(tokens["refresh_token"], "refresh-token");
+    }
+
+    #[test]
+    fn test_use_dpop_nonce() {
+        let error = AuthorizationError::local(AuthError::UseDpopNonce, "nonce is required")
+            .with_dpop_nonce("server-nonce".to_string());
+        let resp = error.error_response();
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.headers().get("DPoP-Nonce").unwrap(), "server-nonce");
+    }
+}
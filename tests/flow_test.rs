@@ -1,9 +1,385 @@
 mod flow_test {
-    use forum_api::dto::auth::Flow;
+    use std::collections::HashMap;
+
+    use actix_web::{cookie::Cookie, http::header, test as actix_test, App, ResponseError};
+    use forum_api::{
+        common::{
+            constant::{PromptType, ResponseMode, ResponseType},
+            errors::ApiError,
+            jwt,
+        },
+        controller::authorize_controller::consent,
+        dto::{
+            auth::{
+                persist_flow, AuthError, AuthRequest, AuthorizationCode, AuthorizationError,
+                AuthorizationResponse, Flow, FlowStage,
+            },
+            client::{ClientConfig, ClientSecret, TokenEndpointAuthMethod},
+            user::UserProfile,
+        },
+        service::token_service,
+    };
+
+    fn request(response_type: Vec<ResponseType>) -> AuthRequest {
+        // 测试中使用进程内的临时签名密钥
+        std::env::set_var("JWT_EPHEMERAL_KEY", "true");
+        AuthRequest {
+            client_id: "forum".to_string(),
+            response_type,
+            scope: vec!["openid".to_string()],
+            state: Some("af0i jf".to_string()),
+            redirect_uri: "https://client.example.org/cb?tenant=1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn flow(request: AuthRequest) -> Flow {
+        let mut flow = Flow::new(request);
+        flow.with_client(ClientConfig {
+            redirect_url: vec!["https://client.example.org/cb?tenant=1".to_string()],
+            ..Default::default()
+        });
+        flow
+    }
+
+    fn location(error: ApiError) -> String {
+        let resp = error.error_response();
+        assert_eq!(resp.status(), 302);
+        resp.headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
 
     #[test]
     fn test_gen_id() {
         let id = Flow::gen_id();
         assert_eq!(id.len(), 24);
     }
+
+    #[test]
+    fn test_error_redirects_with_query() {
+        let flow = flow(request(vec![ResponseType::Code]));
+        assert_eq!(
+            location(flow.fail(AuthError::InvalidScope, "malformed scope")),
+            "https://client.example.org/cb?tenant=1&error=invalid_scope&error_description=malformed+scope\
+             &state=af0i+jf&iss=https%3A%2F%2Fauth.heliannuuthus.com%2Fissuer%2Fforum"
+        );
+    }
+
+    #[test]
+    fn test_error_redirects_with_fragment() {
+        let mut flow = flow(request(vec![ResponseType::IdToken]));
+        flow.request.nonce = Some("n-0S6_WzA2Mj".to_string());
+        flow.request.prompt = Some(PromptType::None);
+        let location = location(flow.validate().unwrap_err());
+        assert!(
+            location.starts_with("https://client.example.org/cb?tenant=1#error=login_required&")
+        );
+    }
+
+    #[test]
+    fn test_unsupported_response_type() {
+        let mut flow = flow(request(vec![ResponseType::Code, ResponseType::Code]));
+        assert!(location(flow.validate().unwrap_err()).contains("error=unsupported_response_type"));
+    }
+
+    #[test]
+    fn test_unverified_redirect_uri_renders_locally() {
+        let mut request = request(vec![ResponseType::Code]);
+        request.redirect_uri = "https://attacker.example.org/cb".to_string();
+        let resp = flow(request)
+            .verify_redirect_uri()
+            .unwrap_err()
+            .error_response();
+        assert_eq!(resp.status(), 400);
+        assert!(resp.headers().get(header::LOCATION).is_none());
+
+        let resp =
+            AuthorizationError::local(AuthError::InvalidClient, "unknown client").error_response();
+        assert_eq!(resp.status(), 401);
+    }
+
+    #[test]
+    fn test_default_response_modes() {
+        assert_eq!(
+            request(vec![ResponseType::Code]).response_mode(),
+            ResponseMode::Query
+        );
+        assert_eq!(
+            request(vec![ResponseType::Code, ResponseType::Token]).response_mode(),
+            ResponseMode::Fragment
+        );
+        let mut explicit = request(vec![ResponseType::Code]);
+        explicit.response_mode = Some(ResponseMode::FormPost);
+        assert_eq!(explicit.response_mode(), ResponseMode::FormPost);
+    }
+
+    #[test]
+    fn test_query_mode_rejected_for_tokens() {
+        let mut request = request(vec![ResponseType::Token]);
+        request.response_mode = Some(ResponseMode::Query);
+        let mut flow = flow(request);
+        assert!(location(flow.validate().unwrap_err())
+            .starts_with("https://client.example.org/cb?tenant=1#error=invalid_request&"));
+    }
+
+    #[actix_web::test]
+    async fn test_form_post_response() {
+        let mut request = request(vec![ResponseType::Code]);
+        request.response_mode = Some(ResponseMode::FormPost);
+        request.state = Some("\"><script>".to_string());
+        let resp = AuthorizationResponse::new(&request)
+            .param("code", "SplxlOBeZQQYbYS6WxSbIA")
+            .write()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"action="https://client.example.org/cb?tenant=1""#));
+        assert!(body.contains(r#"name="code" value="SplxlOBeZQQYbYS6WxSbIA""#));
+        assert!(body.contains(r#"name="state" value="&quot;&gt;&lt;script&gt;""#));
+    }
+
+    #[test]
+    fn test_authorized_flow_writes_code() {
+        let mut flow = flow(request(vec![ResponseType::Code]));
+        flow.stage = FlowStage::Authorized;
+        flow.authorization_code = Some(AuthorizationCode::new("abc".to_string(), None));
+        let resp = flow.dispatch().unwrap();
+        assert_eq!(resp.status(), 302);
+        assert!(resp
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("https://client.example.org/cb?tenant=1&code=abc&state="));
+    }
+
+    #[test]
+    fn test_jarm_response() {
+        let mut request = request(vec![ResponseType::Code]);
+        request.response_mode = Some(ResponseMode::Jwt);
+        assert_eq!(request.response_mode(), ResponseMode::QueryJwt);
+        let response = AuthorizationResponse::new(&request).param("code", "abc");
+        let location = response.write().unwrap();
+        let location = location
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let url = url::Url::parse(location).unwrap();
+        let (name, token) = url.query_pairs().nth(1).unwrap();
+        assert_eq!(name, "response");
+
+        let validation = jwt::validation(jwt::active_key(), vec!["forum".to_string()]).unwrap();
+        let claims: serde_json::Value =
+            jwt::verify_jws(&token, jwt::active_key(), validation).unwrap();
+        assert_eq!(claims["code"], "abc");
+        assert_eq!(claims["state"], "af0i jf");
+        assert_eq!(claims["iss"], "https://auth.heliannuuthus.com/issuer/forum");
+    }
+
+    #[test]
+    fn test_query_jwt_requires_encryption_for_tokens() {
+        let mut request = request(vec![ResponseType::Token]);
+        request.response_mode = Some(ResponseMode::QueryJwt);
+        let mut flow = flow(request);
+        let location = location(flow.validate().unwrap_err());
+        assert!(location.starts_with("https://client.example.org/cb?tenant=1#response=ey"));
+    }
+
+    #[test]
+    fn test_token_hashes() {
+        // https://openid.net/specs/openid-connect-core-1_0.html#code-id_tokenExample
+        assert_eq!(
+            jwt::half_hash(
+                &jwt::JwtAlgorithm::RS256,
+                "Qcb0Orv1zh30vL1MPRsbm-diHiMwcLyZvn1arpZv-Jxf_11jnpEX3Tgfvk"
+            ),
+            "LDktKdoQak3Pk0cnXxCltA"
+        );
+        assert_eq!(
+            jwt::half_hash(
+                &jwt::JwtAlgorithm::RS256,
+                "jHkWEdUXMU1BwAsC4vtUsZwnNvTIxEl0z9K3vx5KF0Y"
+            ),
+            "77QmUPtjPfzWtF2AnpK9RQ"
+        );
+    }
+
+    #[test]
+    fn test_hybrid_requires_nonce() {
+        let mut flow = flow(request(vec![ResponseType::Code, ResponseType::IdToken]));
+        assert!(location(flow.validate().unwrap_err()).contains("error=invalid_request"));
+        flow.request.nonce = Some("n-0S6_WzA2Mj".to_string());
+        assert!(flow.validate().is_ok());
+    }
+
+    #[test]
+    fn test_hybrid_response() {
+        let mut request = request(vec![
+            ResponseType::Code,
+            ResponseType::IdToken,
+            ResponseType::Token,
+        ]);
+        request.nonce = Some("n-0S6_WzA2Mj".to_string());
+        let mut flow = flow(request);
+        flow.stage = FlowStage::Authorized;
+        flow.authorization_code = Some(AuthorizationCode::new("abc".to_string(), None));
+        flow.subject = Some(UserProfile {
+            openid: "openid".to_string(),
+            ..Default::default()
+        });
+        let response = flow.authorization_response().unwrap();
+        let param = |name: &str| {
+            response
+                .params
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        let key = jwt::active_key();
+        let validation = jwt::validation(key, vec!["forum".to_string()]).unwrap();
+        let id_token: serde_json::Value =
+            jwt::verify_jws(&param("id_token"), key, validation).unwrap();
+        assert_eq!(id_token["nonce"], "n-0S6_WzA2Mj");
+        assert_eq!(id_token["c_hash"], jwt::half_hash(key.alg(), "abc"));
+        assert_eq!(
+            id_token["at_hash"],
+            jwt::half_hash(key.alg(), &param("access_token"))
+        );
+        assert_eq!(param("token_type"), "Bearer");
+    }
+
+    #[test]
+    fn test_client_config_not_persisted() {
+        let mut flow = flow(request(vec![ResponseType::Code]));
+        flow.with_client(ClientConfig {
+            secrets: vec![ClientSecret::hash_with("secret", 1000, None)],
+            authorization_encryption_key: Some("public key".to_string()),
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&flow).unwrap();
+        assert!(!json.contains("pbkdf2"));
+        let restored: Flow = serde_json::from_str(&json).unwrap();
+        assert!(restored.client_config.is_none());
+        assert_eq!(restored.encryption_key.as_deref(), Some("public key"));
+    }
+
+    fn authenticated(mut flow: Flow) -> Flow {
+        flow.subject = Some(UserProfile {
+            openid: "openid".to_string(),
+            ..Default::default()
+        });
+        flow.authenticated("pwd", false);
+        flow
+    }
+
+    #[actix_web::test]
+    async fn test_consent_requires_authentication() {
+        let mut flow = flow(request(vec![ResponseType::Code]));
+        flow.stage = FlowStage::MultiFactor;
+        assert!(token_service::grant(&mut flow).await.is_err());
+        assert!(flow.authorization_code.is_none());
+        assert!(matches!(flow.stage, FlowStage::MultiFactor));
+    }
+
+    // id_token_hint 只接受签发给同一客户端的 ID token
+    #[test]
+    fn test_id_token_hint() {
+        let issued = authenticated(flow(request(vec![ResponseType::Code])));
+        let mut req = request(vec![ResponseType::Code]);
+        req.id_token_hint = Some(issued.id_token(None, None).unwrap());
+        let mut hinted = flow(req.clone());
+        hinted.validate().unwrap();
+        assert_eq!(hinted.hinted_subject.as_deref(), Some("openid"));
+
+        req.id_token_hint = Some(issued.access_token(None).unwrap());
+        assert!(
+            location(flow(req.clone()).validate().unwrap_err()).contains("error=invalid_request")
+        );
+
+        let mut other = request(vec![ResponseType::Code]);
+        other.client_id = "other".to_string();
+        req.id_token_hint = Some(authenticated(flow(other)).id_token(None, None).unwrap());
+        assert!(location(flow(req).validate().unwrap_err()).contains("error=invalid_request"));
+    }
+
+    #[actix_web::test]
+    async fn test_consent_rejects_other_user_than_hinted() {
+        let mut flow = authenticated(flow(request(vec![ResponseType::Code])));
+        flow.hinted_subject = Some("someone-else".to_string());
+        let error = token_service::grant(&mut flow).await.unwrap_err();
+        assert!(location(error).contains("error=login_required"));
+        assert!(flow.authorization_code.is_none());
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires REDIS_HOST and REDIS_PORT"]
+    async fn test_authorization_code_grant() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let mut request = request(vec![ResponseType::Code]);
+        request.code_challenge = Some("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_string());
+        request.code_challenge_method = Some("S256".to_string());
+        let flow = authenticated(flow(request));
+        persist_flow(&flow).await.unwrap();
+
+        let app = actix_test::init_service(App::new().service(consent)).await;
+        let resp = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::post()
+                .uri("/confirm")
+                .cookie(Cookie::new("auth_session", flow.id.clone()))
+                .set_json(serde_json::json!({ "approved": true }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 302);
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let code = location
+            .split_once("code=")
+            .and_then(|(_, rest)| rest.split('&').next())
+            .unwrap()
+            .to_string();
+
+        let client = ClientConfig {
+            client_id: "forum".to_string(),
+            token_endpoint_auth_method: TokenEndpointAuthMethod::None,
+            ..Default::default()
+        };
+        let params = HashMap::from([
+            ("grant_type".to_string(), "authorization_code".to_string()),
+            ("code".to_string(), code),
+            (
+                "redirect_uri".to_string(),
+                flow.request.redirect_uri.clone(),
+            ),
+            ("code_verifier".to_string(), verifier.to_string()),
+        ]);
+        let tokens = token_service::exchange_code(&client, &params, None)
+            .await
+            .unwrap();
+        assert!(tokens.id_token.is_some());
+        assert_eq!(tokens.scope.as_deref(), Some("openid"));
+        // 授权码只能兑换一次
+        assert!(token_service::exchange_code(&client, &params, None)
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,222 @@
+mod interop_test {
+    use std::{collections::HashSet, time::Duration};
+
+    use chrono::Utc;
+    use forum_api::common::{
+        jwt::{
+            generate_jws, generate_typed_jws, issuer, validation, verify_access_token, verify_jws,
+            AccessToken, JwKPair, JwtAlgorithm, TokenCalims, ACCESS_TOKEN_JWT_TYPE,
+        },
+        utils::decode64url,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use jwt_simple::prelude::{
+        ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair, HS256Key, MACLike,
+        NoCustomClaims, RS256KeyPair, RSAKeyPairLike, RSAPublicKeyLike, VerificationOptions,
+    };
+    use openssl::{
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::PKey,
+        rsa::Rsa,
+    };
+    use serde_json::{json, Value};
+
+    const CLIENT_ID: &str = "forum-web";
+    const AUDIENCE: &str = "forum-post";
+
+    fn access_token() -> AccessToken {
+        AccessToken::new(
+            "user-openid",
+            AUDIENCE,
+            CLIENT_ID,
+            Duration::from_secs(300),
+            vec!["post:read".to_string()],
+        )
+    }
+
+    fn options() -> VerificationOptions {
+        VerificationOptions {
+            allowed_issuers: Some(HashSet::from([issuer(CLIENT_ID)])),
+            allowed_audiences: Some(HashSet::from([AUDIENCE.to_string()])),
+            time_tolerance: Some(jwt_simple::prelude::Duration::from_secs(0)),
+            ..Default::default()
+        }
+    }
+
+    fn payload(token: &str) -> Value {
+        let payload = token.split('.').nth(1).unwrap();
+        serde_json::from_slice(&decode64url(payload).unwrap()).unwrap()
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc7519#section-2
+    fn assert_numeric_dates(token: &str) {
+        let claims = payload(token);
+        for claim in ["exp", "nbf", "iat"] {
+            assert!(claims[claim].is_i64(), "{claim} is not NumericDate");
+        }
+        let now = Utc::now().timestamp();
+        assert!((claims["iat"].as_i64().unwrap() - now).abs() <= 5);
+        assert_eq!(claims["nbf"], claims["iat"]);
+        assert_eq!(
+            claims["exp"].as_i64().unwrap() - claims["iat"].as_i64().unwrap(),
+            300
+        );
+    }
+
+    fn rsa_keys() -> (JwKPair, RS256KeyPair) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let ours = JwKPair::new(JwtAlgorithm::RS256, rsa.private_key_to_der().unwrap());
+        let theirs = RS256KeyPair::from_pem(
+            std::str::from_utf8(&rsa.private_key_to_pem().unwrap()).unwrap(),
+        )
+        .unwrap();
+        (ours, theirs)
+    }
+
+    fn ec_keys() -> (JwKPair, ES256KeyPair) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let ours = JwKPair::new(JwtAlgorithm::ES256, key.private_key_to_pkcs8().unwrap());
+        let theirs = ES256KeyPair::from_pem(
+            std::str::from_utf8(&key.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+        )
+        .unwrap();
+        (ours, theirs)
+    }
+
+    #[test]
+    fn test_issued_tokens_verified_externally() {
+        let (ours, theirs) = rsa_keys();
+        let token = generate_typed_jws(&access_token(), &ours, ACCESS_TOKEN_JWT_TYPE).unwrap();
+        assert_numeric_dates(&token);
+        let claims = theirs
+            .public_key()
+            .verify_token::<Value>(&token, Some(options()))
+            .unwrap();
+        assert_eq!(claims.subject.as_deref(), Some("user-openid"));
+        assert_eq!(claims.custom["client_id"], CLIENT_ID);
+
+        let (ours, theirs) = ec_keys();
+        let token = generate_jws(&access_token(), &ours).unwrap();
+        assert_numeric_dates(&token);
+        assert!(theirs
+            .public_key()
+            .verify_token::<Value>(&token, Some(options()))
+            .is_ok());
+
+        let secret = b"interop-shared-secret-of-32-bytes".to_vec();
+        let ours = JwKPair::new(JwtAlgorithm::HS256, secret.clone());
+        let token = generate_jws(&access_token(), &ours).unwrap();
+        assert_numeric_dates(&token);
+        assert!(HS256Key::from_bytes(&secret)
+            .verify_token::<Value>(&token, Some(options()))
+            .is_ok());
+
+        // 校验方没有 leeway 时 token 在签发当下即可使用
+        let (ours, theirs) = rsa_keys();
+        let token = generate_jws(&access_token(), &ours).unwrap();
+        let claims = theirs
+            .public_key()
+            .verify_token::<NoCustomClaims>(&token, Some(options()))
+            .unwrap();
+        assert_eq!(claims.invalid_before, claims.issued_at);
+    }
+
+    #[test]
+    fn test_external_tokens_verified() {
+        let (ours, theirs) = rsa_keys();
+        let claims =
+            jwt_simple::prelude::Claims::create(jwt_simple::prelude::Duration::from_mins(5))
+                .with_issuer("https://idp.example.com")
+                .with_subject("external-user")
+                .with_audience(AUDIENCE)
+                .with_jwt_id("external-jti");
+        let token = theirs.sign(claims).unwrap();
+        let claims = verify_jws::<TokenCalims>(
+            &token,
+            &ours,
+            validation(&ours, vec![AUDIENCE.to_string()]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(claims.sub, "external-user");
+        assert_eq!(claims.jti, "external-jti");
+        assert!((claims.iat.timestamp() - Utc::now().timestamp()).abs() <= 5);
+
+        let (ours, theirs) = ec_keys();
+        let claims =
+            jwt_simple::prelude::Claims::create(jwt_simple::prelude::Duration::from_mins(5))
+                .with_issuer("https://idp.example.com")
+                .with_subject("external-user")
+                .with_audience(AUDIENCE)
+                .with_jwt_id("external-jti");
+        let token = theirs.sign(claims).unwrap();
+        assert!(verify_jws::<TokenCalims>(
+            &token,
+            &ours,
+            validation(&ours, vec![AUDIENCE.to_string()]).unwrap(),
+        )
+        .is_ok());
+    }
+
+    fn sign_raw(secret: &[u8], typ: &str, claims: &Value) -> String {
+        let mut header = Header::new(jsonwebtoken::Algorithm::HS256);
+        header.typ = Some(typ.to_string());
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn raw_claims(iat: f64, nbf: f64, exp: f64) -> Value {
+        json!({
+            "iss": issuer(CLIENT_ID),
+            "sub": "user-openid",
+            "aud": AUDIENCE,
+            "client_id": CLIENT_ID,
+            "scope": "post:read post:write",
+            "jti": "raw-jti",
+            "iat": iat,
+            "nbf": nbf,
+            "exp": exp,
+        })
+    }
+
+    // NumericDate 允许小数秒
+    #[test]
+    fn test_fractional_numeric_date() {
+        let secret = b"interop-shared-secret-of-32-bytes".to_vec();
+        let key = JwKPair::new(JwtAlgorithm::HS256, secret.clone());
+        let now = Utc::now().timestamp() as f64;
+        let token = sign_raw(
+            &secret,
+            ACCESS_TOKEN_JWT_TYPE,
+            &raw_claims(now + 0.25, now + 0.25, now + 300.75),
+        );
+        let access_token = verify_access_token(
+            &token,
+            &key,
+            validation(&key, vec![AUDIENCE.to_string()]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(access_token.token.iat.timestamp(), now as i64);
+        assert_eq!(access_token.token.exp.timestamp(), now as i64 + 300);
+        assert_eq!(access_token.scope, vec!["post:read", "post:write"]);
+    }
+
+    // 默认 JWT_LEEWAY 为 60 秒
+    #[test]
+    fn test_leeway() {
+        let secret = b"interop-shared-secret-of-32-bytes".to_vec();
+        let key = JwKPair::new(JwtAlgorithm::HS256, secret.clone());
+        let now = Utc::now().timestamp() as f64;
+        let verify = |claims: Value| {
+            verify_access_token(
+                &sign_raw(&secret, ACCESS_TOKEN_JWT_TYPE, &claims),
+                &key,
+                validation(&key, vec![AUDIENCE.to_string()]).unwrap(),
+            )
+        };
+        assert!(verify(raw_claims(now - 330.0, now - 330.0, now - 30.0)).is_ok());
+        assert!(verify(raw_claims(now - 420.0, now - 420.0, now - 120.0)).is_err());
+        assert!(verify(raw_claims(now, now + 30.0, now + 300.0)).is_ok());
+        assert!(verify(raw_claims(now, now + 120.0, now + 300.0)).is_err());
+    }
+}
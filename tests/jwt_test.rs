@@ -1,6 +1,23 @@
 mod test_jwt {
     use chrono::Duration;
-    use forum_api::{common::jwt::IdToken, dto::user::UserProfile};
+    use forum_api::{
+        common::{
+            jwt::{
+                generate_jws, generate_typed_jws, genrate_key, load_active_key, validation,
+                verify_access_token, verify_id_token, AccessToken, IdToken, JwtAlgorithm,
+                ACCESS_TOKEN_JWT_TYPE,
+            },
+            utils::{decode64url, encode64},
+        },
+        dto::user::UserProfile,
+    };
+    use openssl::{
+        encrypt::Decrypter,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::{Padding, Rsa},
+        symm::{decrypt_aead, Cipher},
+    };
     use strum::IntoEnumIterator;
 
     #[test]
@@ -26,4 +43,123 @@ mod test_jwt {
             assert_eq!(verifed_claims.token.sub, "openid")
         }
     }
+
+    #[test]
+    fn test_jwe_round_trip() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let key = PKey::from_rsa(rsa).unwrap();
+        let jwe = forum_api::common::jwt::generate_jwe(
+            b"payload",
+            &key.public_key_to_pem().unwrap(),
+            "JWT",
+        )
+        .unwrap();
+        let parts = jwe
+            .split('.')
+            .map(|part| decode64url(part).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parts.len(), 5);
+        let header: serde_json::Value = serde_json::from_slice(&parts[0]).unwrap();
+        assert_eq!(header["alg"], "RSA-OAEP-256");
+        assert_eq!(header["enc"], "A256GCM");
+
+        let mut decrypter = Decrypter::new(&key).unwrap();
+        decrypter.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+        decrypter.set_rsa_oaep_md(MessageDigest::sha256()).unwrap();
+        decrypter.set_rsa_mgf1_md(MessageDigest::sha256()).unwrap();
+        let mut cek = vec![0u8; decrypter.decrypt_len(&parts[1]).unwrap()];
+        let len = decrypter.decrypt(&parts[1], &mut cek).unwrap();
+        cek.truncate(len);
+        let aad = jwe.split('.').next().unwrap();
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &cek,
+            Some(&parts[2]),
+            aad.as_bytes(),
+            &parts[3],
+            &parts[4],
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"payload");
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc9068#section-2
+    #[test]
+    fn test_access_token_profile() {
+        let key = genrate_key(JwtAlgorithm::ES256).unwrap();
+        let mut access_token = AccessToken::new(
+            "openid",
+            "forum-post",
+            "forum-web",
+            Duration::minutes(3).to_std().unwrap(),
+            vec!["openid".to_string(), "post:read".to_string()],
+        );
+        access_token.auth_time = Some(1_700_000_000);
+        let token = generate_typed_jws(&access_token, &key, ACCESS_TOKEN_JWT_TYPE).unwrap();
+
+        let parts = token
+            .split('.')
+            .take(2)
+            .map(|part| serde_json::from_slice::<serde_json::Value>(&decode64url(part).unwrap()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parts[0]["typ"], "at+jwt");
+        assert_eq!(parts[1]["client_id"], "forum-web");
+        assert_eq!(parts[1]["scope"], "openid post:read");
+        assert_eq!(parts[1]["auth_time"], 1_700_000_000);
+        assert!(parts[1].get("azp").is_none());
+
+        let audience = vec!["forum-post".to_string()];
+        let verified =
+            verify_access_token(&token, &key, validation(&key, audience.clone()).unwrap()).unwrap();
+        assert_eq!(verified.client_id, "forum-web");
+        assert_eq!(verified.scope, vec!["openid", "post:read"]);
+        // access token 不能当作 ID token
+        assert!(verify_id_token(&token, &key, validation(&key, audience).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_reject_id_token_as_access_token() {
+        let key = genrate_key(JwtAlgorithm::ES256).unwrap();
+        let user_profile = UserProfile {
+            openid: "openid".to_string(),
+            ..Default::default()
+        };
+        let id_token = IdToken::new(
+            "forum-web",
+            &user_profile,
+            Duration::minutes(3).to_std().unwrap(),
+        );
+        let token = generate_jws(&id_token, &key).unwrap();
+        let audience = vec!["forum-web".to_string()];
+        assert!(
+            verify_access_token(&token, &key, validation(&key, audience.clone()).unwrap()).is_err()
+        );
+        assert!(verify_id_token(&token, &key, validation(&key, audience).unwrap()).is_ok());
+    }
+
+    // 签名密钥缺失或无效时加载失败，只有显式允许时才使用临时密钥
+    #[test]
+    fn test_load_active_key() {
+        std::env::remove_var("JWT_EPHEMERAL_KEY");
+        std::env::remove_var("JWT_SIGNING_KEY");
+        std::env::set_var("JWT_SIGNING_ALG", "RS256");
+        assert!(load_active_key().is_err());
+
+        std::env::set_var("JWT_SIGNING_KEY", "not base64!");
+        assert!(load_active_key().is_err());
+        // ES256 私钥不能作为 RS256 密钥使用
+        let ec_key = genrate_key(JwtAlgorithm::ES256).unwrap();
+        std::env::set_var("JWT_SIGNING_KEY", encode64(&ec_key.export_prikey()));
+        assert!(load_active_key().is_err());
+        std::env::set_var("JWT_SIGNING_ALG", "ES256");
+        assert_eq!(load_active_key().unwrap().alg(), &JwtAlgorithm::ES256);
+        std::env::set_var("JWT_SIGNING_ALG", "XS256");
+        assert!(load_active_key().is_err());
+
+        std::env::remove_var("JWT_SIGNING_KEY");
+        std::env::set_var("JWT_SIGNING_ALG", "RS256");
+        std::env::set_var("JWT_EPHEMERAL_KEY", "true");
+        assert!(load_active_key().is_ok());
+    }
 }
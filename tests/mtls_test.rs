@@ -0,0 +1,230 @@
+mod mtls_test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use forum_api::{
+        common::{
+            jwt::{AccessToken, Confirmation},
+            tls::{self, PeerCertificate},
+            utils::{encode64url, sha256},
+        },
+        dto::client::{ClientConfig, ClientCredentials, TokenEndpointAuthMethod},
+        service::token_service::confirmation,
+    };
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        ssl::{SslConnector, SslMethod, SslVerifyMode},
+        x509::{extension::BasicConstraints, X509NameBuilder, X509},
+    };
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    // issuer 为空时生成自签名证书
+    fn certificate(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        ca: bool,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("O", "Heliannuuthus").unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        if ca {
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+        }
+        let (issuer_name, signing_key) = match issuer {
+            Some((issuer, issuer_key)) => (issuer.subject_name(), issuer_key),
+            None => (name.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn client(method: TokenEndpointAuthMethod) -> ClientConfig {
+        ClientConfig {
+            client_id: "internal-service".to_string(),
+            token_endpoint_auth_method: method,
+            ..Default::default()
+        }
+    }
+
+    fn authenticate(client: &ClientConfig, certificate: Option<&PeerCertificate>) -> bool {
+        ClientCredentials::None {
+            client_id: client.client_id.clone(),
+        }
+        .verify(client, &[], certificate)
+        .is_ok()
+    }
+
+    #[test]
+    fn test_tls_client_auth() {
+        let ca_key = key();
+        let ca = certificate("forum ca", &ca_key, None, true);
+        let leaf_key = key();
+        let leaf = certificate("service.internal", &leaf_key, Some((&ca, &ca_key)), false);
+        let peer = PeerCertificate::from_x509(&leaf, true).unwrap();
+        assert_eq!(peer.subject_dn, "CN=service.internal,O=Heliannuuthus");
+
+        let mut client = client(TokenEndpointAuthMethod::TlsClientAuth);
+        client.tls_client_auth_subject_dn =
+            Some("cn=service.internal, O=Heliannuuthus".to_string());
+        assert!(authenticate(&client, Some(&peer)));
+        assert!(!authenticate(&client, None));
+
+        // 链校验未通过的证书即使 DN 一致也拒绝
+        let untrusted = PeerCertificate {
+            trusted: false,
+            ..peer.clone()
+        };
+        assert!(!authenticate(&client, Some(&untrusted)));
+
+        client.tls_client_auth_subject_dn = Some("CN=other.internal,O=Heliannuuthus".to_string());
+        assert!(!authenticate(&client, Some(&peer)));
+    }
+
+    #[test]
+    fn test_self_signed_tls_client_auth() {
+        let key = key();
+        let registered = certificate("service.internal", &key, None, false);
+        let mut self_signed = client(TokenEndpointAuthMethod::SelfSignedTlsClientAuth);
+        self_signed.tls_client_certificate =
+            Some(String::from_utf8(registered.to_pem().unwrap()).unwrap());
+
+        let peer = PeerCertificate::from_x509(&registered, false).unwrap();
+        assert!(authenticate(&self_signed, Some(&peer)));
+
+        // 同一把密钥重新签发的证书指纹不同
+        let reissued = certificate("service.internal", &key, None, false);
+        let peer = PeerCertificate::from_x509(&reissued, false).unwrap();
+        assert!(!authenticate(&self_signed, Some(&peer)));
+        assert!(!authenticate(&self_signed, None));
+
+        // 未注册证书时拒绝
+        assert!(!authenticate(
+            &client(TokenEndpointAuthMethod::SelfSignedTlsClientAuth),
+            Some(&peer)
+        ));
+    }
+
+    #[test]
+    fn test_certificate_bound_token() {
+        let key = key();
+        let certificate = certificate("service.internal", &key, None, false);
+        let peer = PeerCertificate::from_x509(&certificate, false).unwrap();
+        assert_eq!(
+            peer.thumbprint,
+            encode64url(&sha256(&certificate.to_der().unwrap()))
+        );
+
+        let mut client = client(TokenEndpointAuthMethod::SelfSignedTlsClientAuth);
+        assert_eq!(confirmation(&client, Some(&peer), None).unwrap(), None);
+        client.tls_client_certificate_bound_access_tokens = true;
+        assert!(confirmation(&client, None, None).is_err());
+        let cnf = confirmation(&client, Some(&peer), None).unwrap();
+        assert_eq!(
+            cnf,
+            Some(Confirmation {
+                x5t_s256: Some(peer.thumbprint.clone()),
+                jkt: None,
+            })
+        );
+
+        let mut access_token = AccessToken::new(
+            "openid",
+            "internal-service",
+            "internal-service",
+            std::time::Duration::from_secs(60),
+            vec![],
+        );
+        assert!(serde_json::to_value(&access_token).unwrap()["cnf"].is_null());
+        access_token.cnf = cnf;
+        assert_eq!(
+            serde_json::to_value(&access_token).unwrap()["cnf"],
+            serde_json::json!({ "x5t#S256": peer.thumbprint })
+        );
+    }
+
+    // 通过本地握手确认客户端证书可选，且只有 CA 签发的证书被标记为可信
+    fn handshake(
+        ca: &X509,
+        client_certificate: Option<(&X509, &PKey<Private>)>,
+    ) -> Option<PeerCertificate> {
+        let server_key = key();
+        let server = certificate("localhost", &server_key, None, false);
+        let acceptor = tls::acceptor(
+            &server.to_pem().unwrap(),
+            &server_key.private_key_to_pem_pkcs8().unwrap(),
+            Some(&ca.to_pem().unwrap()),
+        )
+        .unwrap()
+        .build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(stream).unwrap();
+            let mut buf = [0u8; 1];
+            stream.read_exact(&mut buf).unwrap();
+            PeerCertificate::from_ssl(stream.ssl())
+        });
+
+        let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        if let Some((certificate, key)) = client_certificate {
+            connector.set_certificate(certificate).unwrap();
+            connector.set_private_key(key).unwrap();
+        }
+        let mut stream = connector
+            .build()
+            .connect("localhost", TcpStream::connect(address).unwrap())
+            .unwrap();
+        stream.write_all(b"x").unwrap();
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn test_optional_client_certificate() {
+        let ca_key = key();
+        let ca = certificate("forum ca", &ca_key, None, true);
+        let leaf_key = key();
+        let leaf = certificate("service.internal", &leaf_key, Some((&ca, &ca_key)), false);
+        let self_signed_key = key();
+        let self_signed = certificate("service.internal", &self_signed_key, None, false);
+
+        assert_eq!(handshake(&ca, None), None);
+        let peer = handshake(&ca, Some((&leaf, &leaf_key))).unwrap();
+        assert!(peer.trusted);
+        assert_eq!(peer, PeerCertificate::from_x509(&leaf, true).unwrap());
+        let peer = handshake(&ca, Some((&self_signed, &self_signed_key))).unwrap();
+        assert!(!peer.trusted);
+        assert_eq!(peer.thumbprint, tls::thumbprint(&self_signed).unwrap());
+    }
+}
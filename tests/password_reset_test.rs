@@ -0,0 +1,139 @@
+mod password_reset_test {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use actix_web::{http::StatusCode, test, App};
+    use forum_api::{
+        common::{
+            errors::Result,
+            srp::{
+                client::SrpClient,
+                groups::G_2048,
+                types::{SrpGroupId, SrpHash, SrpProofMode},
+            },
+        },
+        controller::authenticate_controller::password_reset,
+        dto::password::{PasswordReset, PasswordResetConfirm, SrpPassword},
+        service::user_service::{redeem_reset, PasswordResetBackend},
+    };
+
+    #[derive(Default)]
+    struct MemoryBackend {
+        resets: RefCell<HashMap<String, PasswordReset>>,
+        saved: RefCell<Vec<String>>,
+        revoked: RefCell<Vec<String>>,
+    }
+
+    impl MemoryBackend {
+        fn with_reset(token: &str, identifier: &str) -> Self {
+            let backend = Self::default();
+            backend.resets.borrow_mut().insert(
+                token.to_string(),
+                PasswordReset {
+                    identifier: identifier.to_string(),
+                    openid: format!("{identifier}-openid"),
+                },
+            );
+            backend
+        }
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl PasswordResetBackend for MemoryBackend {
+        async fn take_reset(&self, token: &str) -> Result<Option<PasswordReset>> {
+            Ok(self.resets.borrow_mut().remove(token))
+        }
+
+        async fn save_password(&self, srp: &SrpPassword) -> Result<()> {
+            self.saved.borrow_mut().push(srp.identifier.clone());
+            Ok(())
+        }
+
+        async fn revoke_all(&self, openid: &str) -> Result<()> {
+            self.revoked.borrow_mut().push(openid.to_string());
+            Ok(())
+        }
+    }
+
+    fn confirm(token: &str, identifier: &str) -> PasswordResetConfirm {
+        let salt = [7u8; 16];
+        let verifier = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054).compute_verifier(
+            identifier.as_bytes(),
+            b"new-password",
+            &salt,
+        );
+        PasswordResetConfirm {
+            token: token.to_string(),
+            password: SrpPassword {
+                identifier: identifier.to_string(),
+                verifier: hex::encode(verifier),
+                salt: hex::encode(salt),
+                mode: SrpProofMode::Rfc5054,
+                group: SrpGroupId::G2048,
+                hash: SrpHash::Sha256,
+            },
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reset_revokes_sessions() {
+        let backend = MemoryBackend::with_reset("token", "alice");
+        let reset = redeem_reset(&backend, &confirm("token", "alice"))
+            .await
+            .unwrap();
+        assert_eq!(reset.openid, "alice-openid");
+        assert_eq!(*backend.saved.borrow(), vec!["alice"]);
+        assert_eq!(*backend.revoked.borrow(), vec!["alice-openid"]);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_token_single_use() {
+        let backend = MemoryBackend::with_reset("token", "alice");
+        assert!(redeem_reset(&backend, &confirm("token", "alice"))
+            .await
+            .is_ok());
+        let replayed = redeem_reset(&backend, &confirm("token", "alice")).await;
+        assert!(replayed
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("invalid token"));
+        assert_eq!(backend.saved.borrow().len(), 1);
+        assert_eq!(backend.revoked.borrow().len(), 1);
+
+        // 凭证属于其他账号时同样作废，且不修改口令
+        let backend = MemoryBackend::with_reset("token", "alice");
+        assert!(redeem_reset(&backend, &confirm("token", "mallory"))
+            .await
+            .is_err());
+        assert!(redeem_reset(&backend, &confirm("token", "alice"))
+            .await
+            .is_err());
+        assert!(backend.saved.borrow().is_empty());
+        assert!(backend.revoked.borrow().is_empty());
+    }
+
+    // 账号是否存在都返回相同的响应
+    #[actix_web::test]
+    async fn test_reset_request_no_enumeration() {
+        std::env::set_var("REDIS_HOST", "127.0.0.1");
+        std::env::set_var("REDIS_PORT", "1");
+        let app = test::init_service(App::new().service(password_reset)).await;
+        let mut responses = Vec::new();
+        for identifier in ["alice@heliannuuthus.com", "nobody@heliannuuthus.com"] {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/password/reset")
+                    .set_json(serde_json::json!({ "identifier": identifier }))
+                    .to_request(),
+            )
+            .await;
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            let body = test::read_body(resp).await;
+            responses.push((status, headers.len(), body));
+        }
+        assert_eq!(responses[0].0, StatusCode::NO_CONTENT);
+        assert_eq!(responses[0], responses[1]);
+    }
+}
@@ -0,0 +1,155 @@
+mod ratelimit_test {
+    use std::io;
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test as actix_test, web, App, HttpResponse,
+    };
+    use forum_api::common::{
+        middleware::{
+            form_client_id, parse_rules, RateLimitKey, RateLimitRule, RateLimiter, TokenBucket,
+        },
+        utils::encode64url,
+    };
+
+    fn rule(capacity: u32, refill_rate: f64) -> RateLimitRule {
+        serde_json::from_value(serde_json::json!({
+            "route": "/login",
+            "method": "POST",
+            "by": "client_id",
+            "capacity": capacity,
+            "refill_rate": refill_rate,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bucket_drains_and_refills() {
+        let rule = rule(3, 1.0);
+        let mut bucket = TokenBucket::new(&rule, 0);
+        for remaining in (0..3).rev() {
+            let decision = bucket.take(&rule, 0);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let denied = bucket.take(&rule, 0);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, 1);
+        assert_eq!(denied.reset, 3);
+
+        // 1.5 秒后补充了一个令牌
+        let decision = bucket.take(&rule, 1500);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        // 长时间空闲也不会超过容量
+        assert_eq!(bucket.take(&rule, 60_000).remaining, 2);
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule = rule(3, 1.0);
+        assert_eq!(rule.by, RateLimitKey::ClientId);
+        assert!(rule.matches("POST", "/login"));
+        assert!(rule.matches("post", "/login/srp"));
+        assert!(!rule.matches("GET", "/login"));
+        assert!(!rule.matches("POST", "/logintoken"));
+
+        let global: RateLimitRule = serde_json::from_str(
+            r#"{"route": "/", "by": "remote_addr", "capacity": 1, "refill_rate": 0.5}"#,
+        )
+        .unwrap();
+        assert!(global.matches("GET", "/authorize"));
+        assert_eq!(global.window(), 2);
+    }
+
+    #[test]
+    fn test_strictest_decision() {
+        let loose = rule(10, 1.0);
+        let strict = rule(2, 1.0);
+        let mut a = TokenBucket::new(&loose, 0);
+        let mut b = TokenBucket::new(&strict, 0);
+        let chosen = a.take(&loose, 0).strictest(b.take(&strict, 0));
+        assert_eq!(chosen.limit, 2);
+        b.take(&strict, 0);
+        let denied = b.take(&strict, 0);
+        assert!(!a.take(&loose, 0).strictest(denied).allowed);
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules(
+            r#"[{"route": "/", "by": "remote_addr", "capacity": 120, "refill_rate": 2}]"#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].by, RateLimitKey::RemoteAddr);
+        // 配置错误作为启动错误返回而不是 panic，无效的规则不会被静默忽略
+        assert!(parse_rules(r#"{"route": "/"}"#).is_err());
+        assert!(parse_rules(r#"[{"route": "/", "by": "ip"}]"#).is_err());
+        for (capacity, refill_rate) in [("0", "1"), ("10", "0"), ("10", "-1")] {
+            let error = parse_rules(&format!(
+                r#"[{{"route": "/token", "by": "client_id", "capacity": {capacity}, "refill_rate": {refill_rate}}}]"#
+            ))
+            .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(error.to_string().contains("/token"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_form_client_id() {
+        assert_eq!(
+            form_client_id(b"grant_type=client_credentials&client_id=forum-web").as_deref(),
+            Some("forum-web")
+        );
+        let claims = encode64url(br#"{"iss":"forum-iss","sub":"forum-sub"}"#);
+        let body = format!(
+            "client_assertion_type=urn%3Aietf%3Aparams%3Aoauth%3Aclient-assertion-type%\
+             3Ajwt-bearer&client_assertion=eyJhbGciOiJIUzI1NiJ9.{claims}.signature"
+        );
+        assert_eq!(
+            form_client_id(body.as_bytes()).as_deref(),
+            Some("forum-sub")
+        );
+        assert_eq!(form_client_id(b"token=abc"), None);
+        assert_eq!(form_client_id(b"client_assertion=garbage"), None);
+    }
+
+    // 表单中携带 client_id 的客户端各自计数，处理器仍能读到完整的请求体
+    #[actix_web::test]
+    async fn test_client_id_from_form() {
+        std::env::set_var("REDIS_HOST", "127.0.0.1");
+        std::env::set_var("REDIS_PORT", "1");
+        let rules = parse_rules(
+            r#"[{"route": "/token", "by": "client_id", "capacity": 1, "refill_rate": 0.001}]"#,
+        )
+        .unwrap();
+        let app = actix_test::init_service(App::new().wrap(RateLimiter::new(rules)).route(
+            "/token",
+            web::post().to(|body: web::Bytes| async move { HttpResponse::Ok().body(body) }),
+        ))
+        .await;
+        let request = |client_id: &str| {
+            actix_test::TestRequest::post()
+                .uri("/token")
+                .insert_header((
+                    header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded; charset=UTF-8",
+                ))
+                .set_payload(format!(
+                    "grant_type=client_credentials&client_id={client_id}"
+                ))
+                .to_request()
+        };
+        let resp = actix_test::call_service(&app, request("form-client-a")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            actix_test::read_body(resp).await,
+            "grant_type=client_credentials&client_id=form-client-a"
+        );
+        let resp = actix_test::call_service(&app, request("form-client-a")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = actix_test::call_service(&app, request("form-client-b")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
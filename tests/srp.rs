@@ -1,5 +1,7 @@
 mod test_rsp {
-    use forum_api::common::srp::{client::SrpClient, groups::G_2048, server::SrpServer};
+    use forum_api::common::srp::{
+        client::SrpClient, groups::G_2048, server::SrpServer, types::SrpProofMode,
+    };
     use ring::rand::{SecureRandom, SystemRandom};
 
     fn auth_test(true_pwd: &[u8], auth_pwd: &[u8]) {
@@ -24,7 +26,7 @@ mod test_rsp {
         // User sends username
 
         // Server instance creation
-        let server = SrpServer::new(&G_2048);
+        let server = SrpServer::with_mode(&G_2048, SrpProofMode::Legacy);
 
         // Server retrieves verifier, salt and computes a public B value
         let mut b = [0u8; 64];
@@ -45,7 +47,9 @@ mod test_rsp {
         // Client sends a_pub and client_proof to server (M1)
 
         // Server processes verification data
-        let server_verifier = server.process_reply(&b, &verifier, &a_pub).unwrap();
+        let server_verifier = server
+            .process_reply(username, salt, &b, &verifier, &a_pub)
+            .unwrap();
         println!("Client verification on server");
         server_verifier.verify_client(client_proof).unwrap();
         let server_proof = server_verifier.proof();
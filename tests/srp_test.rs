@@ -1,13 +1,33 @@
 mod test_rsp {
-    use forum_api::common::srp::{client::SrpClient, groups::G_2048, server::SrpServer};
+    use forum_api::{
+        common::{
+            cache::redis::{redis_getdel, redis_setex},
+            srp::{
+                client::SrpClient,
+                groups::{select_group, SrpGroup, G_1024, G_2048},
+                server::SrpServer,
+                types::{SrpGroupId, SrpHash, SrpProofMode},
+                utils::{compute_k, compute_u, pad, session_confirmation},
+            },
+        },
+        dto::password::{SrpHandshake, SrpPassword},
+        service::token_service::revoked_since,
+    };
+    use futures_util::future::join_all;
+    use num_bigint::BigUint;
+    use openssl::sha::sha1;
     use ring::rand::{SecureRandom, SystemRandom};
 
     fn auth_test(true_pwd: &[u8], auth_pwd: &[u8]) {
+        auth_test_with_mode(SrpProofMode::Legacy, true_pwd, auth_pwd)
+    }
+
+    fn auth_test_with_mode(mode: SrpProofMode, true_pwd: &[u8], auth_pwd: &[u8]) {
         let rng = SystemRandom::new();
         let username = b"alice";
 
         // Client instance creation
-        let client = SrpClient::new(&G_2048);
+        let client = SrpClient::with_mode(&G_2048, mode);
 
         // Begin Registration
 
@@ -24,7 +44,7 @@ mod test_rsp {
         // User sends username
 
         // Server instance creation
-        let server = SrpServer::new(&G_2048);
+        let server = SrpServer::with_mode(&G_2048, mode);
 
         // Server retrieves verifier, salt and computes a public B value
         let mut b = [0u8; 64];
@@ -45,7 +65,9 @@ mod test_rsp {
         // Client sends a_pub and client_proof to server (M1)
 
         // Server processes verification data
-        let server_verifier = server.process_reply(&b, &verifier, &a_pub).unwrap();
+        let server_verifier = server
+            .process_reply(username, salt, &b, &verifier, &a_pub)
+            .unwrap();
         println!("Client verification on server");
         server_verifier.verify_client(client_proof).unwrap();
         let server_proof = server_verifier.proof();
@@ -75,4 +97,390 @@ mod test_rsp {
     fn bad_password() {
         auth_test(b"password", b"paSsword");
     }
+
+    #[test]
+    fn rfc5054_good_password() {
+        auth_test_with_mode(SrpProofMode::Rfc5054, b"password", b"password");
+    }
+
+    #[test]
+    #[should_panic]
+    fn rfc5054_bad_password() {
+        auth_test_with_mode(SrpProofMode::Rfc5054, b"password", b"paSsword");
+    }
+
+    fn h(hex: &str) -> Vec<u8> {
+        hex::decode(hex.replace([' ', '\n'], "")).unwrap()
+    }
+
+    // https://tools.ietf.org/html/rfc5054#appendix-B
+    const SALT: &str = "BEB25379 D1A8581E B5A72767 3A2441EE";
+    const VERIFIER: &str = "7E273DE8 696FFC4F 4E337D05 B4B375BE B0DDE156 9E8FA00A 9886D812 \
+                            9BADA1F1
+        822223CA 1A605B53 0E379BA4 729FDC59 F105B478 7E5186F5 C671085A 1447B52A
+        48CF1970 B4FB6F84 00BBF4CE BFBB1681 52E08AB5 EA53D15C 1AFF87B2 B9DA6E04
+        E058AD51 CC72BFC9 033B564E 26480D78 E955A5E2 9E7AB245 DB2BE315 E2099AFB";
+    const A: &str = "60975527 035CF2AD 1989806F 0407210B C81EDC04 E2762A56 AFD529DD DA2D4393";
+    const B: &str = "E487CB59 D31AC550 471E81F0 0F6928E0 1DDA08E9 74A004F4 9E61F5D1 05284D20";
+    const A_PUB: &str = "61D5E490 F6F1B795 47B0704C 436F523D D0E560F0 C64115BB 72557EC4 4352E890
+        3211C046 92272D8B 2D1A5358 A2CF1B6E 0BFCF99F 921530EC 8E393561 79EAE45E
+        42BA92AE ACED8251 71E1E8B9 AF6D9C03 E1327F44 BE087EF0 6530E69F 66615261
+        EEF54073 CA11CF58 58F0EDFD FE15EFEA B349EF5D 76988A36 72FAC47B 0769447B";
+    const B_PUB: &str = "BD0C6151 2C692C0C B6D041FA 01BB152D 4916A1E7 7AF46AE1 05393011 BAF38964
+        DC46A067 0DD125B9 5A981652 236F99D9 B681CBF8 7837EC99 6C6DA044 53728610
+        D0C6DDB5 8B318885 D7D82C7F 8DEB75CE 7BD4FBAA 37089E6F 9C6059F3 88838E7A
+        00030B33 1EB76840 910440B1 B27AAEAE EB4012B7 D7665238 A8E3FB00 4B117B58";
+    const PREMASTER_SECRET: &str = "B0DC82BA BCF30674 AE450C02 87745E79 90A3381F 63B387AA \
+                                    F271A10D 233861E3
+        59B48220 F7C4693C 9AE12B0A 6F67809F 0876E2D0 13800D6C 41BB59B6 D5979B5C
+        00A172B4 A2A5903A 0BDCAF8A 709585EB 2AFAFA8F 3499B200 210DCC1F 10EB3394
+        3CD67FC8 8A2F39A4 BE5BEC4E C0A3212D C346D7E4 74B29EDE 8A469FFE CA686E5A";
+
+    // https://tools.ietf.org/html/rfc5054#appendix-A
+    const N_1024: &str = "EEAF0AB9 ADB38DD6 9C33F80A FA8FC5E8 60726187 75FF3C0B 9EA2314C 9C256576
+        D674DF74 96EA81D3 383B4813 D692C6E0 E0D5D8E2 50B98BE4 8E495C1D 6089DAD1
+        5DC7D7B4 6154D6B6 CE8EF4AD 69B15D49 82559B29 7BCF1885 C529F566 660E57EC
+        68EDBC3C 05726CC0 2FD4CBF4 976EAA9A FD5138FE 8376435B 9FC61D2F C0EB06E3";
+
+    // RFC 5054 没有给出 K、M1、M2，按 RFC 2945 第 3 节的定义从附录 B 的 S 独立计算，
+    // 使用 openssl 的 SHA-1，不经过被测实现
+    // K = H(S), M1 = H(H(N) XOR H(g) | H(I) | s | A | B | K), M2 = H(A | M1 | K)
+    fn reference_proofs(username: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let key = sha1(&h(PREMASTER_SECRET)).to_vec();
+        let h_xor = sha1(&h(N_1024))
+            .iter()
+            .zip(sha1(&[2u8]))
+            .map(|(n, g)| n ^ g)
+            .collect::<Vec<u8>>();
+        let m1 = sha1(
+            &[
+                h_xor,
+                sha1(username).to_vec(),
+                h(SALT),
+                h(A_PUB),
+                h(B_PUB),
+                key.clone(),
+            ]
+            .concat(),
+        )
+        .to_vec();
+        let m2 = sha1(&[h(A_PUB), m1.clone(), key.clone()].concat()).to_vec();
+        (key, m1, m2)
+    }
+
+    #[test]
+    fn rfc5054_vectors() {
+        let (username, password) = (b"alice", b"password123");
+        let salt = h(SALT);
+        let client = SrpClient::with_mode(&G_1024, SrpProofMode::Rfc5054);
+        let server = SrpServer::with_mode(&G_1024, SrpProofMode::Rfc5054);
+
+        assert_eq!(G_1024.n.to_bytes_be(), h(N_1024));
+        assert_eq!(
+            compute_k(&G_1024).to_bytes_be(),
+            h("7556AA04 5AEF2CDD 07ABAF0F 665C3E81 8913186F")
+        );
+        let identity_hash = SrpClient::compute_identity_hash(&G_1024, username, password);
+        assert_eq!(
+            SrpClient::compute_x(&G_1024, &identity_hash, &salt).to_bytes_be(),
+            h("94B7555A ABE9127C C58CCF49 93DB6CF8 4D16C124")
+        );
+        let verifier = client.compute_verifier(username, password, &salt);
+        assert_eq!(verifier, h(VERIFIER));
+        assert_eq!(client.compute_public_ephemeral(&h(A)), h(A_PUB));
+        assert_eq!(server.compute_public_ephemeral(&h(B), &verifier), h(B_PUB));
+        let u = compute_u(&G_1024, SrpProofMode::Rfc5054, &h(A_PUB), &h(B_PUB));
+        assert_eq!(
+            u.to_bytes_be(),
+            h("CE38B959 3487DA98 554ED47D 70A7AE5F 462EF019")
+        );
+        let premaster_secret = server.compute_pre_master_secret(
+            &BigUint::from_bytes_be(&h(A_PUB)),
+            &BigUint::from_bytes_be(&verifier),
+            &u,
+            &BigUint::from_bytes_be(&h(B)),
+        );
+        assert_eq!(premaster_secret.to_bytes_be(), h(PREMASTER_SECRET));
+
+        let (key, m1, m2) = reference_proofs(username);
+        let server_verifier = server
+            .process_reply(username, &salt, &h(B), &verifier, &h(A_PUB))
+            .unwrap();
+        assert_eq!(server_verifier.key(), key);
+        assert_eq!(server_verifier.proof(), m2);
+        server_verifier.verify_client(&m1).unwrap();
+
+        let client_verifier = client
+            .process_reply(&h(A), username, password, &salt, &h(B_PUB))
+            .unwrap();
+        assert_eq!(client_verifier.key(), key);
+        assert_eq!(client_verifier.proof(), m1);
+        client_verifier
+            .verify_server(server_verifier.proof())
+            .unwrap();
+        // 双方由 K 派生出相同的会话确认值，且绑定到具体会话
+        assert_eq!(
+            session_confirmation(client_verifier.key(), "flow"),
+            session_confirmation(server_verifier.key(), "flow")
+        );
+        assert_ne!(
+            session_confirmation(client_verifier.key(), "flow"),
+            session_confirmation(server_verifier.key(), "other")
+        );
+    }
+
+    #[test]
+    fn rfc5054_pads_u() {
+        let short = [1u8; 16];
+        assert_eq!(pad(&G_1024, &short).len(), 128);
+        assert_ne!(
+            compute_u(&G_1024, SrpProofMode::Legacy, &short, &short),
+            compute_u(&G_1024, SrpProofMode::Rfc5054, &short, &short)
+        );
+        assert_eq!(
+            compute_u(&G_1024, SrpProofMode::Rfc5054, &short, &short),
+            compute_u(
+                &G_1024,
+                SrpProofMode::Rfc5054,
+                &pad(&G_1024, &short),
+                &pad(&G_1024, &short)
+            )
+        );
+    }
+
+    fn round_trip(group: &SrpGroup, mode: SrpProofMode) {
+        let rng = SystemRandom::new();
+        let (username, password) = (b"alice", b"password");
+        let mut salt = [0u8; 16];
+        rng.fill(&mut salt).unwrap();
+        let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+        rng.fill(&mut a).unwrap();
+        rng.fill(&mut b).unwrap();
+
+        let client = SrpClient::with_mode(group, mode);
+        let server = SrpServer::with_mode(group, mode);
+        let verifier = client.compute_verifier(username, password, &salt);
+        let b_pub = server.compute_public_ephemeral(&b, &verifier);
+        let client_verifier = client
+            .process_reply(&a, username, password, &salt, &b_pub)
+            .unwrap();
+        let server_verifier = server
+            .process_reply(
+                username,
+                &salt,
+                &b,
+                &verifier,
+                &client.compute_public_ephemeral(&a),
+            )
+            .unwrap();
+        server_verifier
+            .verify_client(client_verifier.proof())
+            .unwrap();
+        client_verifier
+            .verify_server(server_verifier.proof())
+            .unwrap();
+        // 双方由 K 派生出相同的会话确认值，且绑定到具体会话
+        assert_eq!(
+            session_confirmation(client_verifier.key(), "flow"),
+            session_confirmation(server_verifier.key(), "flow")
+        );
+        assert_ne!(
+            session_confirmation(client_verifier.key(), "flow"),
+            session_confirmation(server_verifier.key(), "other")
+        );
+    }
+
+    #[test]
+    fn all_groups_and_hashes() {
+        for group in [
+            SrpGroupId::G1024,
+            SrpGroupId::G1536,
+            SrpGroupId::G2048,
+            SrpGroupId::G3072,
+            SrpGroupId::G4096,
+            SrpGroupId::G6144,
+            SrpGroupId::G8192,
+        ] {
+            let params = select_group(group, SrpHash::Sha256);
+            assert_eq!(params.n.bits() as usize, group.bits());
+            round_trip(&params, SrpProofMode::Rfc5054);
+        }
+        for hash in [
+            SrpHash::Sha1,
+            SrpHash::Sha256,
+            SrpHash::Sha384,
+            SrpHash::Sha512,
+        ] {
+            let params = select_group(SrpGroupId::G2048, hash);
+            assert!(compute_k(&params).to_bytes_be().len() <= hash.algorithm().output_len);
+            round_trip(&params, SrpProofMode::Rfc5054);
+            round_trip(&params, SrpProofMode::Legacy);
+        }
+    }
+
+    #[test]
+    fn srp_password_defaults() {
+        // 历史记录沿用原来的 2048 位 SHA-256 legacy 参数
+        let srp: SrpPassword =
+            serde_json::from_str(r#"{"identifier":"alice","verifier":"00","salt":"00"}"#).unwrap();
+        assert_eq!(srp.group, SrpGroupId::G2048);
+        assert_eq!(srp.hash, SrpHash::Sha256);
+        assert_eq!(srp.mode, SrpProofMode::Legacy);
+        assert_eq!(select_group(srp.group, srp.hash), *G_2048);
+
+        let srp: SrpPassword = serde_json::from_str(
+            r#"{"identifier":"alice","verifier":"00","salt":"00","group":"4096","hash":"SHA-512","mode":"rfc5054"}"#,
+        )
+        .unwrap();
+        assert_eq!(srp.group, SrpGroupId::G4096);
+        assert_eq!(srp.hash, SrpHash::Sha512);
+        assert_eq!(srp.mode, SrpProofMode::Rfc5054);
+    }
+
+    // 模拟一次登录尝试：返回服务端握手状态和客户端的 M1
+    fn handshake(flow_id: &str, salt: &[u8], verifier: &[u8]) -> (SrpHandshake, Vec<u8>) {
+        let rng = SystemRandom::new();
+        let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+        rng.fill(&mut a).unwrap();
+        rng.fill(&mut b).unwrap();
+        let client = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054);
+        let server = SrpServer::with_mode(&G_2048, SrpProofMode::Rfc5054);
+        let b_pub = server.compute_public_ephemeral(&b, verifier);
+        let m1 = client
+            .process_reply(&a, b"alice", b"password", salt, &b_pub)
+            .unwrap()
+            .proof()
+            .to_vec();
+        let handshake = SrpHandshake {
+            flow_id: flow_id.to_string(),
+            identifier: "alice".to_string(),
+            verifier: server
+                .process_reply(
+                    b"alice",
+                    salt,
+                    &b,
+                    verifier,
+                    &client.compute_public_ephemeral(&a),
+                )
+                .unwrap(),
+        };
+        (handshake, m1)
+    }
+
+    #[test]
+    fn handshake_bound_to_flow() {
+        let salt = [1u8; 16];
+        let verifier = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054).compute_verifier(
+            b"alice",
+            b"password",
+            &salt,
+        );
+        let (handshake, m1) = handshake("flow", &salt, &verifier);
+        assert!(handshake.verify("other", "alice", &m1).is_err());
+        assert!(handshake.verify("flow", "bob", &m1).is_err());
+        assert!(handshake.verify("flow", "alice", &m1[1..]).is_err());
+        assert!(handshake.verify("flow", "alice", &m1).is_ok());
+    }
+
+    #[test]
+    fn concurrent_handshakes() {
+        // 同一用户的两次并发登录各自持有独立的握手，证明不能互换
+        let salt = [1u8; 16];
+        let verifier = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054).compute_verifier(
+            b"alice",
+            b"password",
+            &salt,
+        );
+        let (first, first_m1) = handshake("first", &salt, &verifier);
+        let (second, second_m1) = handshake("second", &salt, &verifier);
+        assert!(first.verify("first", "alice", &second_m1).is_err());
+        assert!(second.verify("second", "alice", &first_m1).is_err());
+        assert!(first.verify("first", "alice", &first_m1).is_ok());
+        assert!(second.verify("second", "alice", &second_m1).is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_HOST and REDIS_PORT"]
+    async fn handshake_consumed_once() {
+        let salt = [1u8; 16];
+        let verifier = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054).compute_verifier(
+            b"alice",
+            b"password",
+            &salt,
+        );
+        let (handshake, m1) = handshake("flow", &salt, &verifier);
+        let key = "forum:auth:srp:handshake:test";
+        redis_setex(key, handshake, chrono::Duration::minutes(1))
+            .await
+            .unwrap();
+        // 并发重放同一个握手只有一个请求能够取到
+        let consumed = join_all((0..8).map(|_| redis_getdel::<SrpHandshake>(key)))
+            .await
+            .into_iter()
+            .filter_map(|handshake| handshake.unwrap())
+            .collect::<Vec<SrpHandshake>>();
+        assert_eq!(consumed.len(), 1);
+        assert!(consumed[0].verify("flow", "alice", &m1).is_ok());
+        assert!(redis_getdel::<SrpHandshake>(key).await.unwrap().is_none());
+    }
+
+    fn srp_password(verifier: &[u8], salt: &[u8]) -> SrpPassword {
+        SrpPassword {
+            identifier: "alice".to_string(),
+            verifier: hex::encode(verifier),
+            salt: hex::encode(salt),
+            mode: SrpProofMode::Rfc5054,
+            group: SrpGroupId::G2048,
+            hash: SrpHash::Sha256,
+        }
+    }
+
+    #[test]
+    fn validate_srp_password() {
+        let salt = [7u8; 16];
+        let verifier = SrpClient::with_mode(&G_2048, SrpProofMode::Rfc5054).compute_verifier(
+            b"alice",
+            b"password",
+            &salt,
+        );
+        assert!(srp_password(&verifier, &salt).validate().is_ok());
+
+        // 平凡值与越界值
+        let n = &G_2048.n;
+        let one = BigUint::from(1u8);
+        for trivial in [
+            BigUint::default(),
+            one.clone(),
+            G_2048.g.clone(),
+            n - &one,
+            n.clone(),
+            n + &one,
+        ] {
+            assert!(srp_password(&pad(&G_2048, &trivial.to_bytes_be()), &salt)
+                .validate()
+                .is_err());
+        }
+        // 长度与编码
+        assert!(srp_password(&verifier[..64], &salt).validate().is_err());
+        assert!(srp_password(&[verifier.as_slice(), &[0]].concat(), &salt)
+            .validate()
+            .is_err());
+        assert!(srp_password(&verifier, &salt[..8]).validate().is_err());
+        assert!(srp_password(&verifier, &[0u8; 16]).validate().is_err());
+        let mut malformed = srp_password(&verifier, &salt);
+        malformed.verifier.push('z');
+        assert!(malformed.validate().is_err());
+        let mut anonymous = srp_password(&verifier, &salt);
+        anonymous.identifier.clear();
+        assert!(anonymous.validate().is_err());
+    }
+
+    // 修改密码后撤销此前签发的 token，同一秒内签发的新会话不受影响
+    #[test]
+    fn password_change_revocation_cutoff() {
+        let changed_at = 1_700_000_000;
+        assert!(revoked_since(changed_at - 1, changed_at));
+        assert!(!revoked_since(changed_at, changed_at));
+        assert!(!revoked_since(changed_at + 1, changed_at));
+    }
 }
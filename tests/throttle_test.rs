@@ -0,0 +1,92 @@
+mod throttle_test {
+    use std::net::IpAddr;
+
+    use actix_web::{error::ErrorUnauthorized, http::header, test::TestRequest, ResponseError};
+    use chrono::Duration;
+    use forum_api::{
+        common::{
+            errors::ApiError,
+            proxy::{parse_trusted_proxies, resolve_client_ip},
+        },
+        dto::{
+            auth::Flow,
+            throttle::{Attempt, SRP_SCOPE},
+        },
+        service::throttle_service::{progressive_delay, retry_after},
+    };
+    use http::StatusCode;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_progressive_delay() {
+        assert_eq!(progressive_delay(1, 60), None);
+        assert_eq!(progressive_delay(2, 60), None);
+        assert_eq!(progressive_delay(3, 60), Some(1));
+        assert_eq!(progressive_delay(4, 60), Some(2));
+        assert_eq!(progressive_delay(6, 60), Some(8));
+        assert_eq!(progressive_delay(9, 60), Some(60));
+        assert_eq!(progressive_delay(100, 60), Some(60));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let window = Duration::seconds(900);
+        assert_eq!(retry_after(0, 0, window), 900);
+        assert_eq!(retry_after(0, 899_001, window), 1);
+        assert_eq!(retry_after(0, 1_000, window), 899);
+        assert_eq!(retry_after(0, 1_500, window), 899);
+        // 时钟回拨或刚好滑出窗口时至少等待一秒
+        assert_eq!(retry_after(0, 901_000, window), 1);
+    }
+
+    #[test]
+    fn test_throttled_response() {
+        let resp = ApiError::Throttled(30).error_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+        let resp = ApiError::Response(ErrorUnauthorized("verify failed")).error_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_client_ip_ignores_untrusted_forwarding() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8, 192.168.1.1").unwrap();
+        // 直连的客户端伪造 X-Forwarded-For
+        assert_eq!(
+            resolve_client_ip(ip("203.0.113.7"), "198.51.100.1", &trusted),
+            ip("203.0.113.7")
+        );
+        // 可信代理追加的地址才是客户端，左侧客户端自带的值被忽略
+        assert_eq!(
+            resolve_client_ip(
+                ip("10.0.0.2"),
+                "198.51.100.1, 203.0.113.7, 192.168.1.1",
+                &trusted
+            ),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.2"), "garbage, 203.0.113.7", &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.2"), "", &trusted),
+            ip("10.0.0.2")
+        );
+        assert!(parse_trusted_proxies("10.0.0.0/33").is_err());
+        assert!(parse_trusted_proxies("").unwrap().is_empty());
+
+        // 未配置可信代理时按直连地址限流
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:40000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .to_http_request();
+        let attempt = Attempt::new(&req, &Flow::default(), SRP_SCOPE, "alice");
+        assert_eq!(attempt.ip, "203.0.113.7");
+    }
+}
@@ -0,0 +1,208 @@
+mod token_exchange_test {
+    use std::{collections::HashMap, time::Duration};
+
+    use forum_api::{
+        common::{
+            constant::ACCESS_TOKEN_TYPE,
+            jwt::{AccessToken, Actor},
+        },
+        dto::client::{ClientConfig, TokenEndpointAuthMethod},
+        service::token_service::{exchange_claims, introspection},
+    };
+
+    const POST_SERVICE: &str = "forum-post";
+    const NOTIFY_SERVICE: &str = "forum-notify";
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn client() -> ClientConfig {
+        ClientConfig {
+            client_id: POST_SERVICE.to_string(),
+            token_exchange_audiences: vec![NOTIFY_SERVICE.to_string()],
+            ..Default::default()
+        }
+    }
+
+    // 用户授权 forum-web 访问 forum-post 的 token
+    fn subject_token(expires_in: Duration) -> AccessToken {
+        AccessToken::new(
+            "user-openid",
+            POST_SERVICE,
+            "forum-web",
+            expires_in,
+            vec!["post:read".to_string(), "notify:write".to_string()],
+        )
+    }
+
+    fn error(result: forum_api::common::errors::Result<AccessToken>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_exchange_narrows_audience_and_scope() {
+        let subject = subject_token(Duration::from_secs(3600));
+        let exchanged = exchange_claims(
+            &client(),
+            &subject,
+            None,
+            &params(&[("audience", NOTIFY_SERVICE), ("scope", "notify:write")]),
+        )
+        .unwrap();
+        assert_eq!(exchanged.token.sub, "user-openid");
+        assert_eq!(exchanged.token.aud, NOTIFY_SERVICE);
+        assert_eq!(exchanged.client_id, POST_SERVICE);
+        assert_eq!(exchanged.scope, vec!["notify:write"]);
+        assert_eq!(
+            exchanged.act,
+            Some(Actor {
+                sub: POST_SERVICE.to_string(),
+                act: None,
+            })
+        );
+        assert!(exchanged.token.exp <= subject.token.exp);
+        let claims = serde_json::to_value(&exchanged).unwrap();
+        assert_eq!(claims["act"], serde_json::json!({ "sub": POST_SERVICE }));
+
+        // 未指定 scope 时沿用 subject token 的 scope
+        let exchanged = exchange_claims(
+            &client(),
+            &subject,
+            None,
+            &params(&[
+                ("audience", NOTIFY_SERVICE),
+                ("requested_token_type", ACCESS_TOKEN_TYPE),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(exchanged.scope, subject.scope);
+    }
+
+    #[test]
+    fn test_exchange_keeps_delegation_chain() {
+        let mut subject = subject_token(Duration::from_secs(60));
+        subject.act = Some(Actor {
+            sub: "forum-gateway".to_string(),
+            act: None,
+        });
+        let actor = AccessToken::new(
+            "post-worker",
+            "forum-auth",
+            POST_SERVICE,
+            Duration::from_secs(60),
+            vec![],
+        );
+        let exchanged = exchange_claims(
+            &client(),
+            &subject,
+            Some(&actor),
+            &params(&[("audience", NOTIFY_SERVICE)]),
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&exchanged.act).unwrap(),
+            serde_json::json!({ "sub": "post-worker", "act": { "sub": "forum-gateway" } })
+        );
+
+        // 其他客户端的 actor token 不能冒用
+        let foreign = AccessToken::new(
+            "other-worker",
+            "forum-auth",
+            "forum-other",
+            Duration::from_secs(60),
+            vec![],
+        );
+        assert!(error(exchange_claims(
+            &client(),
+            &subject,
+            Some(&foreign),
+            &params(&[("audience", NOTIFY_SERVICE)]),
+        ))
+        .starts_with("invalid_request"));
+    }
+
+    #[test]
+    fn test_exchange_policy() {
+        let subject = subject_token(Duration::from_secs(3600));
+        assert!(error(exchange_claims(
+            &client(),
+            &subject,
+            None,
+            &params(&[("audience", "forum-admin")]),
+        ))
+        .starts_with("invalid_target"));
+        assert!(
+            error(exchange_claims(&client(), &subject, None, &params(&[])))
+                .starts_with("invalid_request")
+        );
+        assert!(error(exchange_claims(
+            &client(),
+            &subject,
+            None,
+            &params(&[("audience", NOTIFY_SERVICE), ("scope", "admin")]),
+        ))
+        .starts_with("invalid_scope"));
+        assert!(error(exchange_claims(
+            &client(),
+            &subject,
+            None,
+            &params(&[
+                ("audience", NOTIFY_SERVICE),
+                (
+                    "requested_token_type",
+                    "urn:ietf:params:oauth:token-type:id_token"
+                ),
+            ]),
+        ))
+        .starts_with("invalid_request"));
+
+        // subject token 不是签发给调用方的
+        let mut other = client();
+        other.client_id = "forum-other".to_string();
+        assert!(error(exchange_claims(
+            &other,
+            &subject,
+            None,
+            &params(&[("audience", NOTIFY_SERVICE)]),
+        ))
+        .starts_with("invalid_request"));
+
+        // 公开客户端不允许 token exchange
+        let mut public = client();
+        public.token_endpoint_auth_method = TokenEndpointAuthMethod::None;
+        assert!(error(exchange_claims(
+            &public,
+            &subject,
+            None,
+            &params(&[("audience", NOTIFY_SERVICE)]),
+        ))
+        .starts_with("unauthorized_client"));
+    }
+
+    // 只有签发对象与 audience 可以查看 token 内容
+    #[test]
+    fn test_introspection_restricted() {
+        let token = subject_token(Duration::from_secs(300));
+        let caller = |client_id: &str| ClientConfig {
+            client_id: client_id.to_string(),
+            ..Default::default()
+        };
+        let issued = introspection(&caller("forum-web"), Some(&token));
+        assert_eq!(issued["active"], true);
+        assert_eq!(issued["sub"], "user-openid");
+        assert_eq!(
+            introspection(&caller(POST_SERVICE), Some(&token))["active"],
+            true
+        );
+        let foreign = introspection(&caller(NOTIFY_SERVICE), Some(&token));
+        assert_eq!(foreign, serde_json::json!({ "active": false }));
+        assert_eq!(
+            introspection(&caller("forum-web"), None),
+            serde_json::json!({ "active": false })
+        );
+    }
+}
@@ -0,0 +1,116 @@
+mod totp_test {
+    use forum_api::{
+        common::{
+            constant::{AMR_EMAIL, AMR_PWD},
+            totp::Totp,
+            utils::{encode32, seal, unseal},
+        },
+        dto::{
+            auth::{Flow, FlowStage},
+            mfa::{RecoveryCodes, RECOVERY_CODE_COUNT},
+            user::UserProfile,
+        },
+        service::mfa_service::enrollment_allowed,
+    };
+
+    // https://datatracker.ietf.org/doc/html/rfc6238#appendix-B
+    #[test]
+    fn test_rfc6238_vectors() {
+        let mut totp = Totp::new(b"12345678901234567890".to_vec());
+        totp.digits = 8;
+        for (timestamp, code) in [
+            (59u64, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(totp.generate(totp.counter(timestamp)), code);
+        }
+    }
+
+    #[test]
+    fn test_verify_with_skew() {
+        let totp = Totp::new(b"12345678901234567890".to_vec());
+        let now = 1111111111u64;
+        let previous = totp.generate(totp.counter(now) - 1);
+        assert_eq!(totp.verify(&previous, now, 1), Some(totp.counter(now) - 1));
+        assert_eq!(totp.verify(&previous, now, 0), None);
+        let stale = totp.generate(totp.counter(now) - 2);
+        assert_eq!(totp.verify(&stale, now, 1), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let totp = Totp::new(b"12345678901234567890".to_vec());
+        let uri = totp.otpauth_uri("heliannuuthus", "alice@heliannuuthus.com");
+        assert!(uri.starts_with("otpauth://totp/heliannuuthus:alice@heliannuuthus.com?"));
+        assert!(uri.contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc4648#section-10
+    #[test]
+    fn test_encode32() {
+        assert_eq!(encode32(b"f"), "MY");
+        assert_eq!(encode32(b"fo"), "MZXQ");
+        assert_eq!(encode32(b"foo"), "MZXW6");
+        assert_eq!(encode32(b"foobar"), "MZXW6YTBOI");
+    }
+
+    #[test]
+    fn test_seal_secret() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, b"secret").unwrap();
+        assert_eq!(unseal(&key, &sealed).unwrap(), b"secret");
+        assert!(unseal(&[8u8; 32], &sealed).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let key = [7u8; 32];
+        let (recovery_codes, codes) = RecoveryCodes::generate("openid", &key);
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(recovery_codes.hashes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 19));
+        assert!(!recovery_codes.hashes.contains(&codes[0]));
+
+        let hash = recovery_codes.find(&key, &codes[0]).unwrap();
+        assert_eq!(hash, recovery_codes.hashes[0]);
+        // 用户输入时忽略大小写和分隔符
+        let typed = format!(" {} ", codes[1].replace('-', "").to_uppercase());
+        assert_eq!(
+            recovery_codes.find(&key, &typed).unwrap(),
+            recovery_codes.hashes[1]
+        );
+        assert!(recovery_codes.find(&[8u8; 32], &codes[0]).is_none());
+        assert!(recovery_codes.find(&key, "aaaa-bbbb-cccc-dddd").is_none());
+    }
+
+    // 特权角色只完成了密码认证，不能靠绑定自己的 TOTP 完成多因子认证
+    #[test]
+    fn test_password_only_flow_cannot_enroll() {
+        let mut flow = Flow::default();
+        flow.subject = Some(UserProfile {
+            openid: "admin-openid".to_string(),
+            roles: vec!["admin".to_string()],
+            ..Default::default()
+        });
+        flow.authenticated(AMR_PWD, true);
+        assert!(matches!(flow.stage, FlowStage::MultiFactor));
+        assert!(!enrollment_allowed(&flow, false));
+        assert!(!enrollment_allowed(&flow, true));
+
+        // 先通过邮箱验证码证明控制账号后才允许首次绑定
+        let mut flow = Flow::default();
+        flow.authenticated(AMR_EMAIL, true);
+        assert!(matches!(flow.stage, FlowStage::MultiFactor));
+        assert!(enrollment_allowed(&flow, false));
+        assert!(!enrollment_allowed(&flow, true));
+
+        // 已完成认证的用户可以管理自己的第二因子
+        flow.authenticated(AMR_PWD, true);
+        assert!(matches!(flow.stage, FlowStage::Authenticated));
+        assert!(enrollment_allowed(&flow, true));
+    }
+}
@@ -0,0 +1,328 @@
+mod webauthn_test {
+    use ciborium::value::Value;
+    use forum_api::common::{
+        utils::{encode64url, sha256},
+        webauthn::{RelyingParty, WebauthnError, COSE_ES256, FLAG_AT, FLAG_UP, FLAG_UV},
+    };
+    use openssl::{
+        asn1::Asn1Time,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        sign::Signer,
+        x509::{X509NameBuilder, X509},
+    };
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    const RP_ID: &str = "auth.heliannuuthus.com";
+    const ORIGIN: &str = "https://auth.heliannuuthus.com";
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        ciborium::ser::into_writer(value, &mut out).unwrap();
+        out
+    }
+
+    fn client_data(ceremony: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::json!({ "type": ceremony, "challenge": challenge, "origin": origin })
+            .to_string()
+            .into_bytes()
+    }
+
+    // 软件认证器，使用 ES256 凭证
+    struct SoftAuthenticator {
+        key: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                    .unwrap();
+            Self {
+                key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                    .unwrap(),
+                credential_id: b"soft-authenticator".to_vec(),
+                sign_count: 0,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            // uncompressed point 0x04 | x | y
+            let point = self.key.public_key().as_ref();
+            cbor(&Value::Map(vec![
+                (Value::Integer(1.into()), Value::Integer(2.into())),
+                (Value::Integer(3.into()), Value::Integer(COSE_ES256.into())),
+                (Value::Integer((-1).into()), Value::Integer(1.into())),
+                (
+                    Value::Integer((-2).into()),
+                    Value::Bytes(point[1..33].to_vec()),
+                ),
+                (
+                    Value::Integer((-3).into()),
+                    Value::Bytes(point[33..].to_vec()),
+                ),
+            ]))
+        }
+
+        fn authenticator_data(&self, flags: u8, attested: bool) -> Vec<u8> {
+            let mut data = sha256(RP_ID.as_bytes());
+            data.push(if attested { flags | FLAG_AT } else { flags });
+            data.extend(self.sign_count.to_be_bytes());
+            if attested {
+                data.extend([0u8; 16]);
+                data.extend((self.credential_id.len() as u16).to_be_bytes());
+                data.extend(&self.credential_id);
+                data.extend(self.cose_key());
+            }
+            data
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            self.key
+                .sign(&SystemRandom::new(), message)
+                .unwrap()
+                .as_ref()
+                .to_vec()
+        }
+
+        fn attestation_object(&self, fmt: &str, statement: Value, data: Vec<u8>) -> Vec<u8> {
+            cbor(&Value::Map(vec![
+                (Value::Text("fmt".to_string()), Value::Text(fmt.to_string())),
+                (Value::Text("attStmt".to_string()), statement),
+                (Value::Text("authData".to_string()), Value::Bytes(data)),
+            ]))
+        }
+
+        fn attest_none(&self, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+            let client_data = client_data("webauthn.create", challenge, ORIGIN);
+            let data = self.authenticator_data(FLAG_UP | FLAG_UV, true);
+            (
+                client_data,
+                self.attestation_object("none", Value::Map(Vec::new()), data),
+            )
+        }
+
+        fn attest_packed(&self, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+            let client_data = client_data("webauthn.create", challenge, ORIGIN);
+            let data = self.authenticator_data(FLAG_UP | FLAG_UV, true);
+            let sig = self.sign(&[data.as_slice(), &sha256(&client_data)].concat());
+            let statement = Value::Map(vec![
+                (
+                    Value::Text("alg".to_string()),
+                    Value::Integer(COSE_ES256.into()),
+                ),
+                (Value::Text("sig".to_string()), Value::Bytes(sig)),
+            ]);
+            (
+                client_data,
+                self.attestation_object("packed", statement, data),
+            )
+        }
+
+        fn attest_packed_x5c(
+            &self,
+            challenge: &str,
+            certificate: &X509,
+            key: &PKey<Private>,
+        ) -> (Vec<u8>, Vec<u8>) {
+            let client_data = client_data("webauthn.create", challenge, ORIGIN);
+            let data = self.authenticator_data(FLAG_UP | FLAG_UV, true);
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            signer.update(&data).unwrap();
+            signer.update(&sha256(&client_data)).unwrap();
+            let statement = Value::Map(vec![
+                (
+                    Value::Text("alg".to_string()),
+                    Value::Integer(COSE_ES256.into()),
+                ),
+                (
+                    Value::Text("sig".to_string()),
+                    Value::Bytes(signer.sign_to_vec().unwrap()),
+                ),
+                (
+                    Value::Text("x5c".to_string()),
+                    Value::Array(vec![Value::Bytes(certificate.to_der().unwrap())]),
+                ),
+            ]);
+            (
+                client_data,
+                self.attestation_object("packed", statement, data),
+            )
+        }
+
+        fn assert(&mut self, challenge: &str, flags: u8) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            self.sign_count += 1;
+            let client_data = client_data("webauthn.get", challenge, ORIGIN);
+            let data = self.authenticator_data(flags, false);
+            let sig = self.sign(&[data.as_slice(), &sha256(&client_data)].concat());
+            (client_data, data, sig)
+        }
+    }
+
+    fn attestation_certificate() -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("OU", "Authenticator Attestation")
+            .unwrap();
+        name.append_entry_by_text("CN", "forum soft authenticator")
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    #[test]
+    fn test_registration_and_assertion() {
+        let rp = RelyingParty::new(RP_ID, ORIGIN);
+        let mut authenticator = SoftAuthenticator::new();
+        let challenge = encode64url(b"registration challenge");
+        let (client_data, attestation) = authenticator.attest_none(&challenge);
+        let registered = rp
+            .verify_registration(&challenge, &client_data, &attestation, true)
+            .unwrap();
+        let credential = registered.attested_credential.unwrap();
+        assert_eq!(credential.credential_id, b"soft-authenticator");
+        assert_eq!(credential.public_key, authenticator.cose_key());
+
+        let challenge = encode64url(b"assertion challenge");
+        let (client_data, data, sig) = authenticator.assert(&challenge, FLAG_UP | FLAG_UV);
+        let asserted = rp
+            .verify_assertion(
+                &challenge,
+                &credential.public_key,
+                registered.sign_count,
+                &client_data,
+                &data,
+                &sig,
+                true,
+            )
+            .unwrap();
+        assert_eq!(asserted.sign_count, 1);
+        assert!(asserted.user_verified());
+
+        // 重放同一次断言时计数器没有递增
+        assert_eq!(
+            rp.verify_assertion(
+                &challenge,
+                &credential.public_key,
+                asserted.sign_count,
+                &client_data,
+                &data,
+                &sig,
+                true,
+            )
+            .unwrap_err(),
+            WebauthnError::CounterRegressed
+        );
+    }
+
+    #[test]
+    fn test_packed_self_attestation() {
+        let rp = RelyingParty::new(RP_ID, ORIGIN);
+        let authenticator = SoftAuthenticator::new();
+        let challenge = encode64url(b"packed challenge");
+        let (client_data, attestation) = authenticator.attest_packed(&challenge);
+        assert!(rp
+            .verify_registration(&challenge, &client_data, &attestation, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_packed_x5c_attestation() {
+        let rp = RelyingParty::new(RP_ID, ORIGIN);
+        let authenticator = SoftAuthenticator::new();
+        let (certificate, key) = attestation_certificate();
+        let challenge = encode64url(b"packed x5c challenge");
+        let (client_data, attestation) =
+            authenticator.attest_packed_x5c(&challenge, &certificate, &key);
+        assert!(rp
+            .verify_registration(&challenge, &client_data, &attestation, false)
+            .is_ok());
+
+        // 使用其他证书签名的声明不可信
+        let (other, _) = attestation_certificate();
+        let (client_data, attestation) = authenticator.attest_packed_x5c(&challenge, &other, &key);
+        assert!(rp
+            .verify_registration(&challenge, &client_data, &attestation, false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_mismatched_ceremony() {
+        let authenticator = SoftAuthenticator::new();
+        let challenge = encode64url(b"challenge");
+        let (client_data, attestation) = authenticator.attest_none(&challenge);
+
+        let phishing = RelyingParty::new(RP_ID, "https://auth.heliannuuthus.com.evil");
+        assert_eq!(
+            phishing
+                .verify_registration(&challenge, &client_data, &attestation, false)
+                .unwrap_err(),
+            WebauthnError::Mismatched("origin")
+        );
+        let rp = RelyingParty::new(RP_ID, ORIGIN);
+        assert_eq!(
+            rp.verify_registration(&encode64url(b"other"), &client_data, &attestation, false)
+                .unwrap_err(),
+            WebauthnError::Mismatched("challenge")
+        );
+        let other_rp = RelyingParty::new("heliannuuthus.com", ORIGIN);
+        assert_eq!(
+            other_rp
+                .verify_registration(&challenge, &client_data, &attestation, false)
+                .unwrap_err(),
+            WebauthnError::Mismatched("rp id")
+        );
+    }
+
+    #[test]
+    fn test_passwordless_requires_user_verification() {
+        let rp = RelyingParty::new(RP_ID, ORIGIN);
+        let mut authenticator = SoftAuthenticator::new();
+        let challenge = encode64url(b"assertion challenge");
+        let (client_data, data, sig) = authenticator.assert(&challenge, FLAG_UP);
+        assert_eq!(
+            rp.verify_assertion(
+                &challenge,
+                &authenticator.cose_key(),
+                0,
+                &client_data,
+                &data,
+                &sig,
+                true,
+            )
+            .unwrap_err(),
+            WebauthnError::Mismatched("user verification")
+        );
+        assert!(rp
+            .verify_assertion(
+                &challenge,
+                &authenticator.cose_key(),
+                0,
+                &client_data,
+                &data,
+                &sig,
+                false,
+            )
+            .is_ok());
+    }
+}